@@ -2,6 +2,7 @@ pub mod utils;
 
 pub use {
     utils::*,
+    utils::error::{Error, Result},
 };
 
 pub mod creategcode {
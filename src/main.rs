@@ -65,6 +65,11 @@ fn main() {
                 pre_cut_gcode: String::from(""),
                 force_retouch_off: true,
                 suggested_length: 4.375,
+                geometry: tool_geometry::ToolGeometry::FlatEndMill,
+                spindle_speed: None,
+                spindle_counter_clockwise: false,
+                spindle_warm_up: 0.0,
+                coolant: cnc_router::Coolant::Air,
             },
             cnc_router::Tool {
                 name: String::from("Quarter Inch Bit"),
@@ -83,6 +88,11 @@ fn main() {
                 pre_cut_gcode: String::from(""),
                 force_retouch_off: true,
                 suggested_length: 4.375,
+                geometry: tool_geometry::ToolGeometry::FlatEndMill,
+                spindle_speed: None,
+                spindle_counter_clockwise: false,
+                spindle_warm_up: 0.0,
+                coolant: cnc_router::Coolant::Air,
             },
             // cnc_router::Tool {
             //     name:              String::from("1/8 Inch Bit Leftover"),
@@ -302,7 +312,7 @@ fn main() {
             //     suggested_length: 4.25,
             // },
         ],
-        false, // verbose
+        false, // gcode comments
         cnc_router::Coordinate::from(0.0, 0.0, 10.0),
         // StringHolder::new()
         Output::new()
@@ -319,6 +329,7 @@ fn main() {
         "012345 (The Square)",
         String::from("(Made in main.rs of gcode lib)")
     );
+    gc.set_logger(std::sync::Arc::new(logging::StderrLogger::from(logging::Level::Info)));
 
     // Tight inner line
     /*
@@ -655,12 +666,15 @@ fn main() {
         )
     ];
 
-    gc.build_gcode_smart_path(
+    if let Err(e) = gc.build_gcode_smart_path(
         false,
         &shapes,
         &vec![
             (cnc_router::ShapeType::text(), 0.0),
             (cnc_router::ShapeType::braille(), 0.0),
         ],
-    );
+    ) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
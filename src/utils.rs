@@ -4,3 +4,21 @@ pub mod bit_path;
 pub mod lines_and_curves;
 pub mod sign;
 pub mod polygon_tree;
+pub mod tool_geometry;
+pub mod relief;
+pub mod scheduler;
+pub mod ordering;
+pub mod toolpath;
+pub mod stats;
+pub mod gcode_parser;
+pub mod svg_preview;
+pub mod simulation;
+pub mod validation;
+pub mod feature_analysis;
+pub mod tool_selection;
+pub mod feeds_speeds;
+pub mod machine_profile;
+pub mod error;
+pub mod progress;
+pub mod logging;
+pub mod gcode_format;
@@ -2,13 +2,27 @@
 use super::*;
 use serde::{Serialize, Deserialize};
 
-static ERROR_MSG_COULD_NOT_WRITE: &str = "Could not write in cnc_router.";
-
 fn format_float(x: f64) -> String {
-    if x < 0.0 {
+    let s = if x < 0.0 {
         format!("{:.5}", x)
     } else {
         format!("{:.6}", x)
+    };
+    // -0 and tiny negatives round to 0 and are written the same as it.
+    if s.starts_with('-') && s[1..].chars().all(|c| c == '0' || c == '.') {
+        format!("{:.6}", 0.0)
+    } else {
+        s
+    }
+}
+
+// Machine coordinates are written the short way, 0 as "0." and 4.3125 as is.
+fn machine_float(x: f64) -> String {
+    let s = format!("{}", if x == 0.0 { 0.0 } else { x });
+    if s.contains('.') {
+        s
+    } else {
+        s + "."
     }
 }
 
@@ -19,21 +33,37 @@ enum SpindleState {
     CounterClockwise,
 }
 
+// Writes are sticky on errors: the move and setting methods write as they
+// go and give back nothing, the first write that fails is kept and nothing
+// is written after it. finish gives that error back, as do end_program,
+// end_program2 and reset_program_and_end which call it, and every builder
+// in GCodeCreator ends with one of them. Code that writes with the router
+// itself should end with finish or check write_error.
 pub struct CNCRouter<T: std::io::Write> {
     tools: Vec<Tool>,
     current_tool_index: usize,
     pos: Coordinate,
-    verbose: bool,
+    gcode_comments: bool, // explains each line in the gcode, logs go to logger
     home_pos: Coordinate,
     referance_pos: Coordinate,
     second_referance_pos: Coordinate,
     spindle_state: SpindleState,
     spindle_clock_speed: f64, // in RPM
-    is_flood_colant_on: bool,
+    coolant: Coolant,
     gcode_write: T,
     last_command: String,
     feed_rate: f64,
     exact_stop_change_y: bool,
+    toolpath: Option<toolpath::Toolpath>,
+    profile: machine_profile::MachineProfile,
+    envelope_violations: machine_profile::EnvelopeViolations,
+    tool_sequence: Vec<usize>, // indexes of tools in the order they get changed to
+    tool_changes: usize,
+    write_error: Option<std::io::Error>, // first write that failed, nothing is written after it
+    format_options: gcode_format::FormatOptions,
+    formatter: Option<gcode_format::Formatter>, // None writes lines as they are made
+    held: Option<Vec<u8>>, // output kept back by hold_output
+    logger: std::sync::Arc<dyn logging::Logger>,
 }
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
@@ -85,6 +115,10 @@ impl ShapeType {
     pub fn subset_of(&self, other: &Self) -> bool {
         (self.0 & other.0) == self.0
     }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        (self.0 & other.0) != 0 || self.0 == other.0
+    }
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
@@ -99,6 +133,9 @@ pub enum ToolType {
     PartialContourAngle(f64, f64, ShapeType), // angle, bigger radius
     PartialContourRadius(f64, f64, ShapeType),  // bigger radius, extra shrink by
     PartialContourRadiusOrAngle(f64, f64, f64, ShapeType),  // bigger radius, extra shrink by, angle,
+    HelicalHole(f64, bool, bool, ShapeType), // pitch, spring pass, climb, shape of the holes
+    ChamferContour(f64, ShapeType), // chamfer width
+    RestCutBroad(f64), // only cuts material the tools before left thicker than this
 }
 
 impl Default for ToolType {
@@ -154,6 +191,9 @@ impl ToolType {
             ToolType::PartialContourRadius(_, _, _) => String::from("Partial Contour Radius"),
             ToolType::PartialContourRadiusOrAngle(_, _, _, _) =>
                 String::from("Partial Contour Radius Or Angle"),
+            ToolType::HelicalHole(_, _, _, _) => String::from("Helical Hole"),
+            ToolType::ChamferContour(_, _) => String::from("Chamfer Contour"),
+            ToolType::RestCutBroad(_) => String::from("Rest Cut Broad"),
         }
     }
     pub fn raw_value(&self) -> u32 {
@@ -166,6 +206,9 @@ impl ToolType {
             ToolType::PartialContourAngle(_, _, _) => 5,
             ToolType::PartialContourRadius(_, _, _) => 6,
             ToolType::PartialContourRadiusOrAngle(_, _, _, _) => 7,
+            ToolType::HelicalHole(_, _, _, _) => 8,
+            ToolType::ChamferContour(_, _) => 9,
+            ToolType::RestCutBroad(_) => 10,
         }
     }
     pub fn full_cut(self) -> bool {
@@ -217,6 +260,8 @@ impl ToolType {
             false
         } else if let ToolType::SpaceBetweenCutBroad(_, _, _) = self {
             true
+        } else if let ToolType::RestCutBroad(_) = self {
+            true
         } else {
             ToolType::DontAddCutBroad == self
         }
@@ -229,6 +274,12 @@ impl ToolType {
     }
 
     pub fn to_shape_type(&self) -> ShapeType {
+        if let ToolType::HelicalHole(_, _, _, shape_type) = self {
+            return *shape_type;
+        }
+        if let ToolType::ChamferContour(_, shape_type) = self {
+            return *shape_type;
+        }
         let mut shape_type = ShapeType::new();
         if self.is_text() {
             shape_type.set_text(true);
@@ -243,6 +294,55 @@ impl ToolType {
     pub fn is_text_or_braille(&self) -> bool {
         self.is_text() || self.is_braille()
     }
+
+    pub fn is_helical_hole(&self) -> bool {
+        if let ToolType::HelicalHole(_, _, _, _) = self {
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_chamfer(&self) -> bool {
+        if let ToolType::ChamferContour(_, _) = self {
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_rest(&self) -> bool {
+        if let ToolType::RestCutBroad(_) = self {
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Coolant {
+    Off,
+    Flood,
+    Mist,
+    Air, // air blast, turned on with the fan codes
+}
+
+impl Default for Coolant {
+    fn default() -> Self {
+        Self::Air
+    }
+}
+
+impl Coolant {
+    pub fn description(&self) -> String {
+        match self {
+            Coolant::Off => String::from("Off"),
+            Coolant::Flood => String::from("Flood"),
+            Coolant::Mist => String::from("Mist"),
+            Coolant::Air => String::from("Air"),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -321,6 +421,16 @@ pub struct Tool {
     pub force_retouch_off: bool,
     #[serde(default)]
     pub suggested_length: f64,
+    #[serde(default)]
+    pub geometry: tool_geometry::ToolGeometry,
+    #[serde(default)]
+    pub spindle_speed: Option<f64>, // None uses the GCodeCreator's
+    #[serde(default)]
+    pub spindle_counter_clockwise: bool,
+    #[serde(default)]
+    pub spindle_warm_up: f64, // seconds to wait for the spindle to get up to speed
+    #[serde(default)]
+    pub coolant: Coolant,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
@@ -338,23 +448,33 @@ pub struct OptionalCoordinate {
 }
 
 impl <T: std::io::Write> CNCRouter<T> {
-    pub fn from(tools: Vec<Tool>, verbose: bool, home_pos: Coordinate,
+    pub fn from(tools: Vec<Tool>, gcode_comments: bool, home_pos: Coordinate,
         gcode_write: T) -> CNCRouter<T> {
         CNCRouter {
             gcode_write: gcode_write,
             tools: tools,
             current_tool_index: 0,
             pos: home_pos,
-            verbose: verbose,
+            gcode_comments: gcode_comments,
             home_pos: home_pos,
             referance_pos: home_pos,
             second_referance_pos: home_pos,
             spindle_state: SpindleState::Off,
-            is_flood_colant_on: true,
+            coolant: Coolant::Off,
             spindle_clock_speed: 0.0,
             last_command: String::new(),
             feed_rate: 0.0,
             exact_stop_change_y: false,
+            toolpath: None,
+            profile: machine_profile::MachineProfile::default(),
+            envelope_violations: machine_profile::EnvelopeViolations::default(),
+            tool_sequence: Vec::new(),
+            tool_changes: 0,
+            write_error: None,
+            format_options: gcode_format::FormatOptions::default(),
+            formatter: None,
+            held: None,
+            logger: std::sync::Arc::new(logging::NoLogger),
         }
     }
 
@@ -363,24 +483,185 @@ impl <T: std::io::Write> CNCRouter<T> {
             tools: self.tools.clone(),
             current_tool_index: self.current_tool_index,
             pos: self.pos.clone(),
-            verbose: self.verbose,
+            gcode_comments: self.gcode_comments,
             home_pos: self.home_pos.clone(),
             referance_pos: self.referance_pos.clone(),
             second_referance_pos: self.second_referance_pos.clone(),
             spindle_state: self.spindle_state,
             spindle_clock_speed: self.spindle_clock_speed,
-            is_flood_colant_on: self.is_flood_colant_on,
+            coolant: self.coolant,
             gcode_write: w,
             last_command: self.last_command.clone(),
             feed_rate: self.feed_rate,
             exact_stop_change_y: self.exact_stop_change_y,
+            toolpath: self.toolpath.as_ref().map(|_| toolpath::Toolpath::new()),
+            profile: self.profile.clone(),
+            envelope_violations: machine_profile::EnvelopeViolations::default(),
+            tool_sequence: self.tool_sequence.clone(),
+            tool_changes: self.tool_changes,
+            write_error: None,
+            // Lines from a copy are written through this router, which
+            // formats them, so the copy writes them as they are.
+            format_options: self.format_options.clone(),
+            formatter: None,
+            held: None,
+            logger: self.logger.clone(),
+        }
+    }
+
+    // Where warnings like moves outside of the machine go. Nothing is logged
+    // until this is called.
+    pub fn set_logger(&mut self, logger: std::sync::Arc<dyn logging::Logger>) {
+        self.logger = logger;
+    }
+
+    pub fn get_logger(&self) -> std::sync::Arc<dyn logging::Logger> {
+        self.logger.clone()
+    }
+
+    pub fn set_machine_profile(&mut self, profile: machine_profile::MachineProfile) {
+        if let Some(home_pos) = profile.home_position {
+            self.home_pos = home_pos;
+            self.referance_pos = home_pos;
+            self.second_referance_pos = home_pos;
+        }
+        self.profile = profile;
+    }
+
+    // Line numbers, precision and checksums for the lines written from now
+    // on. Line numbers start over.
+    pub fn set_format_options(&mut self, format_options: gcode_format::FormatOptions) {
+        self.formatter = if format_options.is_plain() {
+            None
+        } else {
+            Some(gcode_format::Formatter::from(format_options.clone()))
+        };
+        self.format_options = format_options;
+    }
+
+    pub fn get_format_options(&self) -> &gcode_format::FormatOptions {
+        &self.format_options
+    }
+
+    pub fn get_machine_profile(&self) -> &machine_profile::MachineProfile {
+        &self.profile
+    }
+
+    // Moves made so far that went outside of the machine profile's envelope.
+    pub fn envelope_violations(&self) -> &machine_profile::EnvelopeViolations {
+        &self.envelope_violations
+    }
+
+    pub fn add_envelope_violations(&mut self, other: &machine_profile::EnvelopeViolations) {
+        self.envelope_violations.join(other);
+    }
+
+    // Err if any move was outside of the envelope and the profile says to
+    // fail on them.
+    pub fn envelope_result(&self) -> error::Result<()> {
+        if self.profile.fail_outside_envelope && self.envelope_violations.count > 0 {
+            return Err(error::Error::OutsideEnvelope(self.envelope_violations));
+        }
+        Ok(())
+    }
+
+    // Every tool change the program will make, as indexes into the tools.
+    // Lets set_tool_and_go_home call up the next tool ahead of time.
+    pub fn set_tool_sequence(&mut self, tool_sequence: Vec<usize>) {
+        self.tool_sequence = tool_sequence;
+        self.tool_changes = 0;
+    }
+
+    // For when a copy made with to_new_write does the tool changes.
+    pub fn skip_tool_changes(&mut self, count: usize) {
+        self.tool_changes += count;
+    }
+
+    // Machine index of the next tool in the sequence that is not the one
+    // in the spindle.
+    fn next_tool_in_machine(&self) -> Option<usize> {
+        let current = self.tools[self.current_tool_index].index_in_machine;
+        self.tool_sequence
+            .iter()
+            .skip(self.tool_changes + 1)
+            .map(|tool_index| self.tools[*tool_index].index_in_machine)
+            .find(|index_in_machine| *index_in_machine != current)
+    }
+
+    // Start or stop keeping every move made in a Toolpath.
+    pub fn record_toolpath(&mut self, should_record: bool) {
+        if !should_record {
+            self.toolpath = None;
+        } else if self.toolpath.is_none() {
+            self.toolpath = Some(toolpath::Toolpath::new());
+        }
+    }
+
+    pub fn get_toolpath(&self) -> Option<&toolpath::Toolpath> {
+        self.toolpath.as_ref()
+    }
+
+    // Adds moves recorded by a copy made with to_new_write.
+    pub fn append_toolpath(&mut self, other: &toolpath::Toolpath) {
+        if let Some(toolpath) = &mut self.toolpath {
+            toolpath.append(other);
+        }
+    }
+
+    fn record_move(&mut self, kind: toolpath::MoveKind, start: Coordinate) {
+        match kind {
+            toolpath::MoveKind::Dwell(_) | toolpath::MoveKind::ToolChange => {},
+            _ => self.check_envelope(),
+        }
+        if self.toolpath.is_none() {
+            return;
+        }
+        let tool = if let Some(tool) = self.tools.get(self.current_tool_index) {
+            tool.index_in_machine
+        } else {
+            0
+        };
+        let m = toolpath::Move::from(kind, start, self.pos, self.feed_rate, tool);
+        if let Some(toolpath) = &mut self.toolpath {
+            toolpath.push(m);
         }
     }
 
+    // Warns on the first move outside of the envelope and counts the rest.
+    fn check_envelope(&mut self) {
+        if self.profile.contains(&self.pos) {
+            return;
+        }
+        if self.envelope_violations.count == 0 {
+            self.logger.warn(
+                logging::Target::Router,
+                format_args!(
+                    "Move to X{} Y{} Z{} is outside of the machine",
+                    self.pos.x, self.pos.y, self.pos.z,
+                ),
+            );
+        }
+        self.envelope_violations.add(self.pos);
+    }
+
     fn format_float(&self, x: f64) -> String {
         format_float(x)
     }
 
+    // Empty if the feed rate has not changed.
+    fn feed_rate_word(&mut self, feed_rate: Option<f64>) -> String {
+        let Some(f) = feed_rate else {
+            return String::new();
+        };
+        let f = self.profile.clamp_feed_rate(f);
+        if self.feed_rate == f {
+            String::new()
+        } else {
+            self.feed_rate = f;
+            format!(" F{}", self.format_float(self.feed_rate))
+        }
+    }
+
     fn format_command(&mut self, new_command: String) -> String {
         if self.last_command == new_command &&
             (new_command == "G00" || new_command == "G01") {
@@ -407,11 +688,18 @@ impl <T: std::io::Write> CNCRouter<T> {
         self.write_gcode_string(extra_header_message);
 
         for i in 0..self.tools.len() {
+            let corner_radius = self.tools[i].corner_radius();
             self.write_gcode_string(format!(
-                "({} D={} CR=0. - ZMIN={} - flat end mill)",
+                "({} D={} CR={} - ZMIN={} - {})",
                 self.tools[i].name,
                 self.tools[i].radius*2.0,
+                if corner_radius == 0.0 {
+                    String::from("0.")
+                } else {
+                    format!("{}", corner_radius)
+                },
                 self.tools[i].length,
+                self.tools[i].geometry.description(),
             ));
         }
         if use_inches {
@@ -442,16 +730,27 @@ impl <T: std::io::Write> CNCRouter<T> {
         self.feed_rate
     }
 
+    // Comments in the gcode saying what each line does. Only changes the
+    // program, diagnostics go to logging whatever this is.
+    pub fn set_gcode_comments(&mut self, gcode_comments: bool) {
+        self.gcode_comments = gcode_comments;
+    }
+
+    pub fn gcode_comments(&self) -> bool {
+        self.gcode_comments
+    }
+
+    // Same as set_gcode_comments.
     pub fn set_verbose(&mut self, verbose: bool) {
-        self.verbose = verbose;
+        self.set_gcode_comments(verbose);
     }
 
     pub fn get_verbose(&self) -> bool {
-        self.verbose
+        self.gcode_comments
     }
 
     fn verbose_string(&self, str: String) -> String {
-        if self.verbose {
+        if self.gcode_comments {
             str
         } else {
             String::new()
@@ -459,7 +758,7 @@ impl <T: std::io::Write> CNCRouter<T> {
     }
 
     fn verbose_str<'a>(&self, str: &'a str) -> &'a str {
-        if self.verbose {
+        if self.gcode_comments {
             str
         } else {
             ""
@@ -500,19 +799,45 @@ impl <T: std::io::Write> CNCRouter<T> {
         }
     }
 
+    // Keeps the first error instead of panicking so a long program can be
+    // made without checking every line.
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.write_error.is_some() {
+            return;
+        }
+        let formatted = match self.formatter.as_mut() {
+            Some(formatter) => formatter.push(&String::from_utf8_lossy(bytes)),
+            None => return self.write_raw(bytes),
+        };
+        match formatted {
+            Ok(lines) => self.write_raw(lines.as_bytes()),
+            Err(e) => self.write_error = Some(e),
+        }
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if let Some(held) = &mut self.held {
+            held.extend_from_slice(bytes);
+            return;
+        }
+        if let Err(e) = self.gcode_write.write_all(bytes) {
+            self.write_error = Some(e);
+        }
+    }
+
     pub fn write_gcode_string_no_line(&mut self, str: String) {
-        self.gcode_write.write((str).as_bytes())
-            .expect(ERROR_MSG_COULD_NOT_WRITE);
+        self.write_bytes(str.as_bytes());
     }
 
     pub fn write_gcode_string(&mut self, str: String) {
-        self.gcode_write.write((str+"\n").as_bytes())
-            .expect(ERROR_MSG_COULD_NOT_WRITE);
+        self.write_bytes((str+"\n").as_bytes());
     }
 
     pub fn write_gcode_str(&mut self, line: &str) {
-        self.gcode_write.write((String::from(line)+&"\n").as_bytes())
-            .expect(ERROR_MSG_COULD_NOT_WRITE);
+        self.write_bytes((String::from(line)+&"\n").as_bytes());
     }
 
     pub fn write_gcode_command<W: std::fmt::Display>(&mut self, command: &str, line: W) {
@@ -522,6 +847,16 @@ impl <T: std::io::Write> CNCRouter<T> {
         )
     }
 
+    // A line the program works without, skipped by block delete if
+    // FormatOptions::block_delete is on.
+    pub fn write_optional_gcode_string(&mut self, str: String) {
+        if self.format_options.block_delete {
+            self.write_gcode_string(String::from("/") + &str);
+        } else {
+            self.write_gcode_string(str);
+        }
+    }
+
     // Used if settings become unknown
     // Like call a sub program
     pub fn reset_settings(&mut self) {
@@ -529,7 +864,53 @@ impl <T: std::io::Write> CNCRouter<T> {
     }
 
     pub fn force_flush_gcode(&mut self) {
-        self.gcode_write.flush();
+        if self.write_error.is_some() {
+            return;
+        }
+        if let Err(e) = self.gcode_write.flush() {
+            self.write_error = Some(e);
+        }
+    }
+
+    // Keeps what is written from now on until release_output so a program
+    // that fails part way can be dropped.
+    pub fn hold_output(&mut self) {
+        if self.held.is_none() {
+            self.held = Some(Vec::new());
+        }
+    }
+
+    // Writes what was held if keep, else drops it, then finishes.
+    pub fn release_output(&mut self, keep: bool) -> error::Result<()> {
+        if let Some(held) = self.held.take() {
+            if keep {
+                self.write_raw(&held);
+            }
+        }
+        self.finish()
+    }
+
+    // Error of the first write that failed, if any have.
+    pub fn write_error(&self) -> Option<&std::io::Error> {
+        self.write_error.as_ref()
+    }
+
+    // Flushes and gives back the first write error. Writing can carry on
+    // after this once the writer works again.
+    pub fn finish(&mut self) -> error::Result<()> {
+        if self.write_error.is_none() {
+            if let Some(formatter) = self.formatter.as_mut() {
+                match formatter.finish() {
+                    Ok(line) => self.write_raw(line.as_bytes()),
+                    Err(e) => self.write_error = Some(e),
+                }
+            }
+        }
+        self.force_flush_gcode();
+        match self.write_error.take() {
+            Some(e) => Err(error::Error::Io(e)),
+            None => Ok(()),
+        }
     }
 
 
@@ -538,16 +919,14 @@ impl <T: std::io::Write> CNCRouter<T> {
     }
 
     pub fn write_gcode_comment(&mut self, str: String) {
-        if self.verbose {
-            self.gcode_write.write((String::from("(") + &str + &")\n").as_bytes())
-                .expect(ERROR_MSG_COULD_NOT_WRITE);
+        if self.gcode_comments {
+            self.write_bytes((String::from("(") + &str + &")\n").as_bytes());
         }
     }
 
     pub fn write_gcode_comment_str(&mut self, comment: &str) {
-        if self.verbose {
-            self.gcode_write.write((String::from("(") + comment + &")\n").as_bytes())
-                .expect(ERROR_MSG_COULD_NOT_WRITE);
+        if self.gcode_comments {
+            self.write_bytes((String::from("(") + comment + &")\n").as_bytes());
         }
     }
 
@@ -576,30 +955,44 @@ impl <T: std::io::Write> CNCRouter<T> {
         );
     }
 
+    // Parks at the machine profile's park position.
     pub fn reset_home_and_return(&mut self) {
+        let park = self.profile.park_position;
         self.write_gcode_command(
             "G53",
-            "G0 Z0."
+            format!("G0 Z{}", machine_float(park.z))
         );
         self.write_gcode_command(
-            "X4.3125",
+            &format!("X{}", machine_float(park.x)),
             "",
         );
         self.write_gcode_command(
             "G53",
-            "G0 Y0."
+            format!("G0 Y{}", machine_float(park.y))
+        );
+    }
+
+    // Goes up then over to a place in machine coordinates.
+    pub fn move_to_machine_position(&mut self, pos: &Coordinate) {
+        self.write_gcode_command(
+            "G53",
+            format!("G0 Z{}", machine_float(pos.z))
+        );
+        self.write_gcode_command(
+            "G53",
+            format!("G0 X{} Y{}", machine_float(pos.x), machine_float(pos.y))
         );
     }
 
-    pub fn end_program(&mut self) {
+    pub fn end_program(&mut self) -> error::Result<()> {
         self.write_gcode_command(
             "M02",
             self.verbose_string(String::from("(End of program)"))
         );
-        self.gcode_write.flush();
+        self.finish()
     }
 
-    pub fn end_program2(&mut self) {
+    pub fn end_program2(&mut self) -> error::Result<()> {
         self.write_gcode_command(
             "M30",
             self.verbose_string(String::from("(End of program)"))
@@ -607,16 +1000,19 @@ impl <T: std::io::Write> CNCRouter<T> {
         self.write_gcode_str(
             "%"
         );
-        self.gcode_write.flush();
+        self.finish()
     }
 
-    pub fn reset_program_and_end(&mut self) {
-        // self.set_spindle_off();
+    pub fn reset_program_and_end(&mut self) -> error::Result<()> {
+        if !matches!(self.spindle_state, SpindleState::Off) {
+            self.set_spindle_off();
+        }
+        self.set_coolant(Coolant::Off);
         self.go_home();
         // self.program_stop();
         // self.end_program();
         self.reset_home_and_return();
-        self.end_program2();
+        self.end_program2()
     }
 
     // MARK: 3d printed functions
@@ -664,6 +1060,7 @@ impl <T: std::io::Write> CNCRouter<T> {
     }
 
     pub fn set_spindle_on(&mut self, counter_clockwise: bool, speed: f64) {
+        let speed = self.profile.clamp_rpm(speed);
         self.spindle_clock_speed = speed;
         self.spindle_state = if counter_clockwise {
             SpindleState::CounterClockwise
@@ -725,9 +1122,16 @@ impl <T: std::io::Write> CNCRouter<T> {
         should_touch_off_tool: bool,
         suggested_length: f64,
     ) {
+        if !self.profile.has_atc {
+            self.manual_tool_change(tool_index, pre_cut_gcode);
+            return;
+        }
         self.reset_settings();
         self.current_tool_index = tool_index;
         self.go_home();
+        if let Some(pos) = self.profile.tool_change_position {
+            self.move_to_machine_position(&pos);
+        }
         self.write_gcode_str("");
         self.write_gcode_string(
             format!(
@@ -739,13 +1143,14 @@ impl <T: std::io::Write> CNCRouter<T> {
             format!("T{} M6{}", self.tools[tool_index].index_in_machine,
                 self.verbose_string(String::from(" (Tool change.)")))
         );
+        self.record_move(toolpath::MoveKind::ToolChange, self.pos);
         self.set_accuracy_control(self.tools[tool_index].smoothness);
         self.set_tool_offset_positive(
             self.tools[tool_index].index_in_machine,
             self.tools[tool_index].offset_length,
             feed_rate
         );
-        if should_touch_off_tool {
+        if should_touch_off_tool && self.profile.has_tool_setter {
             self.touch_off_tool(suggested_length);
         }
         self.write_gcode_str(pre_cut_gcode);
@@ -753,6 +1158,15 @@ impl <T: std::io::Write> CNCRouter<T> {
             format!("T{} M6{}", self.tools[tool_index].index_in_machine,
                 self.verbose_string(String::from(" (Tool change.)")))
         );
+        if self.profile.pre_stage_next_tool {
+            if let Some(next_tool) = self.next_tool_in_machine() {
+                self.write_optional_gcode_string(
+                    format!("T{}{}", next_tool,
+                        self.verbose_string(String::from(" (Get the next tool ready.)")))
+                );
+            }
+        }
+        self.tool_changes += 1;
         self.set_tool_offset_positive(
             self.tools[tool_index].index_in_machine,
             self.tools[tool_index].offset_length,
@@ -762,23 +1176,160 @@ impl <T: std::io::Write> CNCRouter<T> {
         self.go_home();
     }
 
+    // For machines without an ATC. Stops at the tool change position (or the
+    // park position) for someone to put the tool in, zeros Z on the touch
+    // plate if the profile has one, then goes home. The spindle is left off
+    // for the caller to start at the new tool's speed.
+    pub fn manual_tool_change(&mut self, tool_index: usize, pre_cut_gcode: &str) {
+        if !matches!(self.spindle_state, SpindleState::Off) {
+            self.set_spindle_off();
+        }
+        self.reset_settings();
+        self.current_tool_index = tool_index;
+        self.go_home();
+        let pos = self.profile.tool_change_position.unwrap_or(self.profile.park_position);
+        self.move_to_machine_position(&pos);
+        self.write_gcode_str("");
+        self.write_gcode_string(
+            format!(
+                "({})",
+                self.tools[tool_index].name,
+            )
+        );
+        self.write_gcode_string(
+            format!(
+                "M0 (Change to T{} {} D={}{})",
+                self.tools[tool_index].index_in_machine,
+                self.tools[tool_index].name,
+                self.tools[tool_index].radius * 2.0,
+                if self.profile.z_probe.is_some() { "" } else { " and zero Z" },
+            )
+        );
+        self.record_move(toolpath::MoveKind::ToolChange, self.pos);
+        self.tool_changes += 1;
+        if let Some(probe) = self.profile.z_probe {
+            self.probe_z(&probe);
+        }
+        self.write_gcode_str(pre_cut_gcode);
+        self.write_gcode_command("G54", self.verbose_str(" (Change 0 coordinate)"));
+        self.go_home();
+    }
+
+    // Finds the top of the touch plate with G38.2 and sets work Z from it.
+    // Leaves the tool retract above the plate.
+    pub fn probe_z(&mut self, probe: &machine_profile::ZProbe) {
+        self.write_gcode_comment_str("Probe Z on the touch plate");
+        self.write_gcode_str("M0 (Put the touch plate under the tool)");
+        self.reset_settings();
+        if let Some((x, y)) = probe.position {
+            let start = self.pos;
+            self.pos.x = x;
+            self.pos.y = y;
+            self.write_gcode_command(
+                "G00",
+                format!("X{} Y{}", self.format_float(x), self.format_float(y))
+            );
+            self.record_move(toolpath::MoveKind::Rapid, start);
+        }
+        self.write_gcode_command("G91", self.verbose_str(" (Incremental.)"));
+        let feed_rate = self.feed_rate_word(Some(probe.feed_rate));
+        self.write_gcode_command(
+            "G38.2",
+            format!("Z{}{}", self.format_float(-probe.max_travel), feed_rate)
+        );
+        // Recorded as going all the way like it would without a plate.
+        let start = self.pos;
+        self.pos.z -= probe.max_travel;
+        self.record_move(toolpath::MoveKind::Feed, start);
+        self.write_gcode_command("G90", self.verbose_str(" (Absolute.)"));
+        self.write_gcode_command(
+            "G10",
+            format!("L20 P1 Z{}", self.format_float(probe.plate_thickness))
+        );
+        self.pos.z = probe.plate_thickness;
+        let start = self.pos;
+        self.pos.z = probe.plate_thickness + probe.retract;
+        self.write_gcode_command(
+            "G00",
+            format!("Z{}", self.format_float(self.pos.z))
+        );
+        self.record_move(toolpath::MoveKind::Rapid, start);
+        self.write_gcode_str("M0 (Take the touch plate away)");
+        self.reset_settings();
+    }
+
+    // Same as set_tool_and_go_home but the tool is already in the spindle so
+    // it only goes home and runs the pre cut gcode.
+    pub fn keep_tool_and_go_home(
+        &mut self,
+        tool_index: usize,
+        pre_cut_gcode: &str,
+    ) {
+        self.reset_settings();
+        self.current_tool_index = tool_index;
+        self.go_home();
+        self.write_gcode_str("");
+        self.write_gcode_string(
+            format!(
+                "({})",
+                self.tools[tool_index].name,
+            )
+        );
+        self.write_gcode_str(pre_cut_gcode);
+    }
+
     pub fn get_tools(&self) -> &Vec<Tool> {
         &self.tools
     }
 
     pub fn set_flood_colant(&mut self, is_on : bool) {
-        self.is_flood_colant_on = is_on;
+        self.coolant = if is_on { Coolant::Flood } else { Coolant::Off };
         self.write_gcode_command(
             if is_on { "M08" } else { "M09" },
             self.verbose_string(
-                format!("Set flood colant {}.", if is_on { "on" } else { "off" })
+                format!(" (Set flood colant {}.)", if is_on { "on" } else { "off" })
             )
         )
     }
 
+    pub fn set_mist_colant(&mut self, is_on : bool) {
+        self.coolant = if is_on { Coolant::Mist } else { Coolant::Off };
+        self.write_gcode_command(
+            if is_on { "M07" } else { "M09" },
+            self.verbose_string(
+                format!(" (Set mist colant {}.)", if is_on { "on" } else { "off" })
+            )
+        )
+    }
+
+    pub fn get_coolant(&self) -> Coolant {
+        self.coolant
+    }
+
+    // Turns off what is on now then turns on the new one.
+    pub fn set_coolant(&mut self, coolant: Coolant) {
+        if coolant == self.coolant {
+            return;
+        }
+        match self.coolant {
+            Coolant::Off => {},
+            Coolant::Flood => self.set_flood_colant(false),
+            Coolant::Mist => self.set_mist_colant(false),
+            Coolant::Air => self.turn_fan(false),
+        }
+        match coolant {
+            Coolant::Off => {},
+            Coolant::Flood => self.set_flood_colant(true),
+            Coolant::Mist => self.set_mist_colant(true),
+            Coolant::Air => self.turn_fan(true),
+        }
+    }
+
     // Non cutting movement
     pub fn move_to_coordinate_rapid(&mut self, pos: &Coordinate) {
+        let start = self.pos;
         self.pos = *pos;
+        self.record_move(toolpath::MoveKind::Rapid, start);
         self.write_gcode_command(
             "G00",
             format!("X{} Y{} Z{}{}",
@@ -795,17 +1346,10 @@ impl <T: std::io::Write> CNCRouter<T> {
     // Can cut; feed_rate = unit/minute
     pub fn move_to_coordinate(&mut self, pos: &Coordinate,
         feed_rate: Option<f64>, can_be_skipped: bool) {
+        let start = self.pos;
         self.pos = *pos;
-        let f = if let Some(f) = feed_rate {
-            if self.feed_rate == f {
-                String::new()
-            } else {
-                self.feed_rate = f;
-                format!(" F{}", self.format_float(self.feed_rate))
-            }
-        } else {
-            String::new()
-        };
+        let f = self.feed_rate_word(feed_rate);
+        self.record_move(toolpath::MoveKind::Feed, start);
         self.write_gcode_command(
             if can_be_skipped { "G31" } else { "G01" },
             format!("G09 X{} Y{} Z{}{}{}",
@@ -825,6 +1369,7 @@ impl <T: std::io::Write> CNCRouter<T> {
         &mut self, pos: &OptionalCoordinate,
         feed_rate: Option<f64>, can_be_skipped: bool
     ) {
+        let start = self.pos;
         let mut exact_cut = "";
         if let Some(x) = pos.x {
             self.pos.x = x;
@@ -836,16 +1381,8 @@ impl <T: std::io::Write> CNCRouter<T> {
             self.pos.z = z;
             exact_cut = "G09 ";
         }
-        let f = if let Some(f) = feed_rate {
-            if self.feed_rate == f {
-                String::new()
-            } else {
-                self.feed_rate = f;
-                format!(" F{}", self.format_float(self.feed_rate))
-            }
-        } else {
-            String::new()
-        };
+        let f = self.feed_rate_word(feed_rate);
+        self.record_move(toolpath::MoveKind::Feed, start);
         self.write_gcode_command(
             if can_be_skipped { "G31" } else { "G01" },
             format!("{}{}{}{}",
@@ -861,7 +1398,9 @@ impl <T: std::io::Write> CNCRouter<T> {
     }
 
     pub fn exact_stop(&mut self, pos: &Coordinate) {
+        let start = self.pos;
         self.pos = *pos;
+        self.record_move(toolpath::MoveKind::Feed, start);
         self.write_gcode_command(
             "G09",
             format!(
@@ -874,11 +1413,10 @@ impl <T: std::io::Write> CNCRouter<T> {
     }
 
     pub fn exact_stop_next_command(&mut self) {
-        if self.verbose {
+        if self.gcode_comments {
             self.write_gcode_str("(G09 makes the line exact stop)");
         }
-        self.gcode_write.write("G09 ".as_bytes())
-            .expect(ERROR_MSG_COULD_NOT_WRITE);
+        self.write_bytes("G09 ".as_bytes());
     }
 
     pub fn pull_out(&mut self, feed_rate: Option<f64>) {
@@ -893,13 +1431,20 @@ impl <T: std::io::Write> CNCRouter<T> {
         // self.referance_pos = self.pos;
         // self.referance_pos.z = self.home_pos.z;
         self.reset_settings();
+        let start = self.pos;
+        self.pos.z = self.home_pos.z;
+        self.record_move(toolpath::MoveKind::Rapid, start);
+        let start = self.pos;
+        self.pos = self.home_pos;
+        self.record_move(toolpath::MoveKind::Rapid, start);
+        self.pos = start;
         self.write_gcode_command(
             "G00",
             format!("X{} Y{} Z{}{}",
                 self.format_float(self.pos.x),
                 self.format_float(self.pos.y),
                 self.format_float(self.home_pos.z),
-                if self.verbose {String::from(" (return home z)")}
+                if self.gcode_comments {String::from(" (return home z)")}
                 else { String::from("") }
             )
         );
@@ -909,12 +1454,12 @@ impl <T: std::io::Write> CNCRouter<T> {
                 self.format_float(self.home_pos.x),
                 self.format_float(self.home_pos.y),
                 self.format_float(self.home_pos.z),
-                if self.verbose {String::from(" (return home)")}
+                if self.gcode_comments {String::from(" (return home)")}
                 else { String::from("") }
             )
             // format!("G00 G28 X{} Y{} Z{}{}",
             //     self.pos.x, self.pos.y, self.home_pos.z,
-            //     if self.verbose {String::from(" (return home through point specified)")}
+            //     if self.gcode_comments {String::from(" (return home through point specified)")}
             //     else { String::from("") }
             // )
         );
@@ -930,7 +1475,7 @@ impl <T: std::io::Write> CNCRouter<T> {
                 self.format_float(d_pos.x),
                 self.format_float(d_pos.y),
                 self.format_float(d_pos.z),
-                if self.verbose {String::from(" (return home through point specified)")}
+                if self.gcode_comments {String::from(" (return home through point specified)")}
                 else { String::from("") }
             )
         )
@@ -945,7 +1490,7 @@ impl <T: std::io::Write> CNCRouter<T> {
                 self.format_float(intermediate_pos.x),
                 self.format_float(intermediate_pos.y),
                 self.format_float(intermediate_pos.z),
-                if self.verbose {String::from(" (return to reference position through point specified.)")}
+                if self.gcode_comments {String::from(" (return to reference position through point specified.)")}
                 else { String::from("") }
             )
         )
@@ -959,7 +1504,7 @@ impl <T: std::io::Write> CNCRouter<T> {
                 self.format_float(intermediate_pos.x),
                 self.format_float(intermediate_pos.y),
                 self.format_float(intermediate_pos.z),
-                if self.verbose {String::from(" (return to second reference position through point specified.)")}
+                if self.gcode_comments {String::from(" (return to second reference position through point specified.)")}
                 else { String::from("") }
             )
         )
@@ -1036,6 +1581,11 @@ impl <T: std::io::Write> CNCRouter<T> {
     pub fn circular_interpolation_offset_midpoint(&mut self,
         is_clock_wise: bool,
         end_pos: &Coordinate, offset: &Coordinate) {
+        if !self.profile.supports_arcs {
+            let end = Coordinate::from(end_pos.x, end_pos.y, self.pos.z);
+            self.arc_as_lines(is_clock_wise, self.pos + *offset, end, None);
+            return;
+        }
         self.write_gcode_command(
             if is_clock_wise {"G02"} else {"G03"},
             format!("X{} Y{} I{} J{}",
@@ -1045,8 +1595,13 @@ impl <T: std::io::Write> CNCRouter<T> {
                 self.format_float(offset.y)
             )
         );
+        let start = self.pos;
         self.pos.x = end_pos.x;
         self.pos.y = end_pos.y;
+        self.record_move(
+            toolpath::MoveKind::Arc(is_clock_wise, start + *offset),
+            start,
+        );
     }
 
     pub fn circular_interpolation_exact_midpoint(&mut self,
@@ -1075,6 +1630,63 @@ impl <T: std::io::Write> CNCRouter<T> {
         );
     }
 
+    // Same as circular_interpolation_exact_midpoint but Z moves to end_pos.z
+    // while going around so the tool spirals.
+    pub fn helical_interpolation_exact_midpoint(
+        &mut self,
+        is_clock_wise: bool,
+        end_pos: &Coordinate,
+        center_pos: &Coordinate,
+        feed_rate: Option<f64>,
+    ) {
+        let offset = center_pos - &self.pos;
+        if !self.profile.supports_arcs {
+            self.arc_as_lines(is_clock_wise, *center_pos, *end_pos, feed_rate);
+            return;
+        }
+        let f = self.feed_rate_word(feed_rate);
+        self.write_gcode_command(
+            if is_clock_wise {"G02"} else {"G03"},
+            format!("X{} Y{} Z{} I{} J{}{}{}",
+                self.format_float(end_pos.x),
+                self.format_float(end_pos.y),
+                self.format_float(end_pos.z),
+                self.format_float(offset.x),
+                self.format_float(offset.y),
+                f,
+                self.verbose_str(" (Helical interpolation around center I, J.)"),
+            )
+        );
+        let start = self.pos;
+        self.pos = *end_pos;
+        self.record_move(
+            toolpath::MoveKind::Arc(is_clock_wise, start + offset),
+            start,
+        );
+    }
+
+    // One full turn around center_pos dropping z by z_drop.
+    pub fn helical_interpolation_around_midpoint(
+        &mut self,
+        is_clock_wise: bool,
+        center_pos: &Coordinate,
+        z_drop: f64,
+        feed_rate: Option<f64>,
+    ) {
+        let og_pos = self.pos;
+        let mut half_way_pos = 2.0 * *center_pos - self.pos;
+        half_way_pos.z = og_pos.z + z_drop / 2.0;
+        self.helical_interpolation_exact_midpoint(
+            is_clock_wise,
+            &half_way_pos, center_pos, feed_rate,
+        );
+        self.helical_interpolation_exact_midpoint(
+            is_clock_wise,
+            &Coordinate::from(og_pos.x, og_pos.y, og_pos.z + z_drop),
+            center_pos, None,
+        );
+    }
+
     pub fn circular_interpolation_around_change_midpoint(
         &mut self,
         is_clock_wise: bool,
@@ -1082,9 +1694,15 @@ impl <T: std::io::Write> CNCRouter<T> {
         dx: f64,
         dy: f64
     ) {
+        if !self.profile.supports_arcs {
+            let center = Coordinate::from(self.pos.x + dx, self.pos.y + dy, self.pos.z);
+            self.arc_as_lines(is_clock_wise, center, self.pos, feed_rate);
+            return;
+        }
         let verbose = self.verbose_str(
             " (Draw a circle with center point of current position + I, J)"
         );
+        let feed_rate = feed_rate.map(|f| self.profile.clamp_feed_rate(f));
         let feed_rate_msg = if let Some(f) = feed_rate {
             if f == self.feed_rate {
                 String::new()
@@ -1106,6 +1724,13 @@ impl <T: std::io::Write> CNCRouter<T> {
                 verbose
             )
         );
+        self.record_move(
+            toolpath::MoveKind::Arc(
+                is_clock_wise,
+                Coordinate::from(self.pos.x + dx, self.pos.y + dy, self.pos.z),
+            ),
+            self.pos,
+        );
     }
 
 
@@ -1131,9 +1756,52 @@ impl <T: std::io::Write> CNCRouter<T> {
         );
     }
 
+    // For machines without G02 and G03. Lines along the arc no further than
+    // the profile's arc_tolerance from it. Ending where it starts goes all
+    // the way around.
+    fn arc_as_lines(
+        &mut self,
+        is_clock_wise: bool,
+        center_pos: Coordinate,
+        end_pos: Coordinate,
+        feed_rate: Option<f64>,
+    ) {
+        let arc = toolpath::Move::from(
+            toolpath::MoveKind::Arc(is_clock_wise, center_pos),
+            self.pos,
+            end_pos,
+            self.feed_rate,
+            0,
+        );
+        let mut points = arc.points(self.profile.arc_step(arc.arc_radius()));
+        if let Some(last) = points.last_mut() {
+            *last = end_pos;
+        }
+        let mut feed_rate = feed_rate;
+        for point in points {
+            let start = self.pos;
+            self.pos = point;
+            let f = self.feed_rate_word(feed_rate.take());
+            self.record_move(toolpath::MoveKind::Feed, start);
+            self.write_gcode_command(
+                "G01",
+                format!("X{} Y{} Z{}{}",
+                    self.format_float(self.pos.x),
+                    self.format_float(self.pos.y),
+                    self.format_float(self.pos.z),
+                    f,
+                )
+            );
+        }
+    }
+
     // Pause in code to ensure proper cuts. Use X or U for seconds.
     //      Use P for milliseconds.
     pub fn dewel(&mut self, milliseconds: u64) {
+        self.record_move(
+            toolpath::MoveKind::Dwell(milliseconds as f64 / 1000.0),
+            self.pos,
+        );
         self.write_gcode_command(
             "G04",
             format!("P{}{}", milliseconds, self.verbose_string(
@@ -1345,7 +2013,7 @@ impl <T: std::io::Write> CNCRouter<T> {
                 self.format_float(x),
                 self.format_float(y),
                 self.format_float(degrees),
-                if self.verbose {String::from(" (Coordinate system rotating around the x and y axis at r degrees.)")} else {String::new()})
+                if self.gcode_comments {String::from(" (Coordinate system rotating around the x and y axis at r degrees.)")} else {String::new()})
         )
     }
 
@@ -1451,6 +2119,7 @@ impl <T: std::io::Write> CNCRouter<T> {
     }
 
     pub fn turn_fan(&mut self, is_on: bool) {
+        self.coolant = if is_on { Coolant::Air } else { Coolant::Off };
         let verbose = self.verbose_string(
             format!(
                 " (Turn {} fan.)",
@@ -1570,6 +2239,7 @@ impl Tool {
         pre_cut_gcode: String,
         force_retouch_off: bool,
         suggested_length: f64,
+        geometry: tool_geometry::ToolGeometry,
     ) -> Tool {
         Tool {
             name: name,
@@ -1588,6 +2258,11 @@ impl Tool {
             pre_cut_gcode: pre_cut_gcode,
             force_retouch_off: force_retouch_off,
             suggested_length: suggested_length,
+            geometry: geometry,
+            spindle_speed: None,
+            spindle_counter_clockwise: false,
+            spindle_warm_up: 0.0,
+            coolant: Coolant::default(),
         }
     }
 
@@ -1602,6 +2277,29 @@ impl Tool {
             None
         }
     }
+
+    // How far below the top a V-bit tip has to go so the cone is width wide
+    // at the top. Uses the V-bit geometry if set, otherwise front_angle is
+    // the included angle in degrees.
+    pub fn chamfer_depth(&self, width: f64) -> f64 {
+        if let tool_geometry::ToolGeometry::VBit(angle, tip_diameter) = self.geometry {
+            (width - tip_diameter / 2.0).max(0.0) / (angle.to_radians() / 2.0).tan()
+        } else {
+            width / (self.front_angle.to_radians() / 2.0).tan()
+        }
+    }
+
+    pub fn effective_radius(&self, depth: f64) -> f64 {
+        self.geometry.effective_radius(self.radius, depth)
+    }
+
+    pub fn scallop_height(&self, stepover: f64) -> f64 {
+        self.geometry.scallop_height(self.radius, stepover)
+    }
+
+    pub fn corner_radius(&self) -> f64 {
+        self.geometry.corner_radius(self.radius)
+    }
 }
 
 impl Coordinate {
@@ -1906,6 +2604,10 @@ pub trait CNCPath {
 
     fn start_path(&self) -> Option<Coordinate>;
 
+    fn as_circle(&self) -> Option<lines_and_curves::Circle> {
+        None
+    }
+
     fn follow_path<T: std::io::Write>(
         &self,
         cnc_router: &mut CNCRouter<T>,
@@ -2450,6 +3152,17 @@ pub trait CNCPath {
     }
 }
 
+// Flat end mill with the feeds most tests use, tests change what they need.
+#[cfg(test)]
+pub fn test_tool(name: &str, index_in_machine: usize, radius: f64, tool_type: ToolType) -> Tool {
+    Tool::from(
+        String::from(name), index_in_machine, 0.0, radius, 0.0, 0.0, 0.0, 0.0,
+        tool_type, Smoothness::Medium,
+        60.0, 30.0, 0.5, String::new(), false, 0.0,
+        tool_geometry::ToolGeometry::FlatEndMill,
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -2478,4 +3191,263 @@ mod test {
         assert!(shape.is_braille());
         assert!(shape.is_text());
     }
+
+    #[test]
+    pub fn test_tool_chamfer_depth() {
+        let mut tool = test_tool("V-Bit", 1, 0.25, ToolType::ChamferContour(0.05, ShapeType::new()));
+        tool.front_angle = 90.0;
+        tool.smoothness = Smoothness::Finish;
+        assert!((tool.chamfer_depth(0.05) - 0.05).abs() < 0.000001);
+
+        tool.front_angle = 60.0;
+        assert!((tool.chamfer_depth(0.05) - 0.05 * 3.0_f64.sqrt()).abs() < 0.000001);
+
+        tool.geometry = tool_geometry::ToolGeometry::VBit(90.0, 0.02);
+        assert!((tool.chamfer_depth(0.05) - 0.04).abs() < 0.000001);
+    }
+
+    #[test]
+    pub fn test_helical_interpolation_around_midpoint() {
+        let mut router = CNCRouter::from(
+            Vec::new(), false, Coordinate::from(1.0, 0.0, 0.5), Vec::new(),
+        );
+        router.helical_interpolation_around_midpoint(
+            false,
+            &Coordinate::from(0.0, 0.0, 0.0),
+            -0.1,
+            Some(10.0),
+        );
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        assert_eq!(
+            gcode,
+            "G03 X-1.00000 Y0.000000 Z0.450000 I-1.00000 J0.000000 F10.000000\n\
+             G03 X1.000000 Y0.000000 Z0.400000 I1.000000 J0.000000\n"
+        );
+        assert_eq!(router.get_pos(), Coordinate::from(1.0, 0.0, 0.4));
+    }
+
+    #[test]
+    pub fn test_set_coolant() {
+        let mut router = CNCRouter::from(
+            Vec::new(), false, Coordinate::from(0.0, 0.0, 0.0), Vec::new(),
+        );
+        router.set_coolant(Coolant::Air);
+        router.set_coolant(Coolant::Air);
+        router.set_coolant(Coolant::Mist);
+        router.set_spindle_on(true, 10000.0);
+        router.reset_program_and_end().unwrap();
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        let lines: Vec<&str> = gcode.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(lines[0], "M83");
+        assert_eq!(lines[1], "M84");
+        assert_eq!(lines[2], "M07");
+        assert_eq!(lines[3], "S10000.000000 M04");
+        assert_eq!(lines[4], "M05");
+        assert_eq!(lines[5], "M09");
+        assert_eq!(router.get_coolant(), Coolant::Off);
+    }
+
+    #[test]
+    pub fn test_machine_profile() {
+        let mut router = CNCRouter::from(
+            Vec::new(), false, Coordinate::from(1.0, 0.0, 0.0), Vec::new(),
+        );
+        router.reset_home_and_return();
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        assert_eq!(gcode, "G53 G0 Z0.\nX4.3125 \nG53 G0 Y0.\n");
+
+        let mut router = CNCRouter::from(
+            Vec::new(), false, Coordinate::from(1.0, 0.0, 0.0), Vec::new(),
+        );
+        let mut profile = machine_profile::MachineProfile::default();
+        profile.supports_arcs = false;
+        profile.max_feed_rate = Some(50.0);
+        profile.envelope = Some((
+            Coordinate::from(-0.5, -2.0, -1.0),
+            Coordinate::from(2.0, 2.0, 1.0),
+        ));
+        profile.park_position = Coordinate::from(-10.0, 0.5, 0.0);
+        router.set_machine_profile(profile);
+        let logger = std::sync::Arc::new(logging::TestLogger::from(logging::Level::Warn));
+        router.set_logger(logger.clone());
+        router.move_to_coordinate(&Coordinate::from(1.0, 0.0, 0.0), Some(100.0), false);
+        router.circular_interpolation_exact_midpoint(
+            true,
+            &Coordinate::from(-1.0, 0.0, 0.0),
+            &Coordinate::from(0.0, 0.0, 0.0),
+        );
+        router.reset_home_and_return();
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        assert!(!gcode.contains("G02") && !gcode.contains("G03"));
+        assert!(gcode.contains("F50.000000"));
+        assert!(gcode.contains("X-1.00000 Y0.000000 Z0.000000\n"));
+        assert!(gcode.ends_with("G53 G0 Z0.\nX-10. \nG53 G0 Y0.5\n"));
+        assert_eq!(router.get_pos(), Coordinate::from(-1.0, 0.0, 0.0));
+        // The lines near the end of the arc go past x -0.5.
+        let violations = router.envelope_violations();
+        assert!(violations.count > 0);
+        assert!(violations.first.unwrap().x < -0.5);
+        // Only the first one is logged, copies log to the same place.
+        assert_eq!(logger.messages.lock().unwrap().len(), 1);
+        router.to_new_write(Vec::new()).get_logger().warn(logging::Target::Router, format_args!("Copy"));
+        assert_eq!(logger.messages.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    pub fn test_manual_tool_change() {
+        let mut tool = test_tool("Quarter Inch Bit", 2, 0.125, ToolType::default());
+        tool.force_retouch_off = true;
+        let mut router = CNCRouter::from(
+            vec![tool], false, Coordinate::from(0.0, 0.0, 1.0), Vec::new(),
+        );
+        let mut profile = machine_profile::MachineProfile::default();
+        profile.has_atc = false;
+        profile.tool_change_position = Some(Coordinate::from(10.0, 0.0, 0.0));
+        profile.z_probe = Some(machine_profile::ZProbe::from(Some((1.0, 2.0)), 0.5, 2.0, 5.0, 0.25));
+        router.set_machine_profile(profile);
+        router.record_toolpath(true);
+        router.set_spindle_on(false, 12000.0);
+        router.set_tool_and_go_home(0, 60.0, "", true, 0.0);
+
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        let lines: Vec<&str> = gcode.lines().map(|line| line.trim_end()).collect();
+        assert!(!gcode.contains("M6") && !gcode.contains("G43") && !gcode.contains("G65"));
+        let at = |line: &str| lines.iter().position(|l| *l == line).unwrap();
+        let stop = at("M05");
+        let change = at("M0 (Change to T2 Quarter Inch Bit D=0.25)");
+        let probe = at("G38.2 Z-2.00000 F5.000000");
+        let zero = at("G10 L20 P1 Z0.500000");
+        assert!(stop < at("G53 G0 X10. Y0.") && at("G53 G0 X10. Y0.") < change);
+        assert!(change < at("G00 X1.000000 Y2.000000") && at("G00 X1.000000 Y2.000000") < probe);
+        assert!(at("G91") < probe && probe < at("G90") && at("G90") < zero);
+        assert!(zero < at("G00 Z0.750000"));
+        // Only the M03 from before the change, the caller starts the new tool.
+        assert_eq!(lines.iter().filter(|l| l.contains("M03")).count(), 1);
+        assert!(lines.iter().position(|l| l.contains("M03")).unwrap() < stop);
+        // The probe and the retract off the plate are in the toolpath.
+        let moves = router.get_toolpath().unwrap().moves();
+        let probe = moves.iter().find(|m| m.kind == toolpath::MoveKind::Feed).unwrap();
+        assert_eq!((probe.end.x, probe.end.y), (1.0, 2.0));
+        assert_eq!(probe.end.z, probe.start.z - 2.0);
+        assert_eq!(probe.feed_rate, 5.0);
+        assert!(moves.iter().any(|m| {
+            m.kind == toolpath::MoveKind::Rapid && m.start.z == 0.5 && m.end.z == 0.75
+        }));
+
+        // Without a probe it asks for Z to be zeroed by hand.
+        let mut router = CNCRouter::from(
+            router.get_tools().clone(), false, Coordinate::from(0.0, 0.0, 1.0), Vec::new(),
+        );
+        let mut profile = machine_profile::MachineProfile::default();
+        profile.has_atc = false;
+        router.set_machine_profile(profile);
+        router.set_tool_and_go_home(0, 60.0, "", false, 0.0);
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        assert!(gcode.contains("G53 G0 X4.3125 Y0.\n"));
+        assert!(gcode.contains("M0 (Change to T2 Quarter Inch Bit D=0.25 and zero Z)\n"));
+        assert!(!gcode.contains("G38.2") && !gcode.contains("M03"));
+    }
+
+    #[test]
+    pub fn test_pre_stage_next_tool() {
+        let tool = |index: usize| test_tool(&format!("Bit {}", index), index, 0.125, ToolType::default());
+        let tools = vec![tool(1), tool(2), tool(3)];
+        let staged = |pre_stage_next_tool: bool| -> Vec<String> {
+            let mut router = CNCRouter::from(
+                tools.clone(), false, Coordinate::from(0.0, 0.0, 1.0), Vec::new(),
+            );
+            let mut profile = machine_profile::MachineProfile::default();
+            profile.pre_stage_next_tool = pre_stage_next_tool;
+            router.set_machine_profile(profile);
+            router.set_tool_sequence(vec![0, 1, 0, 2]);
+            for tool_index in [0, 1, 0, 2] {
+                router.set_tool_and_go_home(tool_index, 60.0, "", false, 0.0);
+            }
+            String::from_utf8(router.get_gcode_writer().clone())
+                .unwrap()
+                .lines()
+                .filter(|line| line.starts_with('T'))
+                .map(|line| String::from(line.trim_end()))
+                .collect()
+        };
+        assert_eq!(
+            staged(true),
+            vec![
+                "T1 M6", "T1 M6", "T2", "T2 M6", "T2 M6", "T1",
+                "T1 M6", "T1 M6", "T3", "T3 M6", "T3 M6",
+            ],
+        );
+        assert!(staged(false).iter().all(|line| line.ends_with("M6")));
+    }
+
+    #[test]
+    pub fn test_format_float() {
+        assert_eq!(format_float(-0.0), "0.000000");
+        assert_eq!(format_float(-0.000001), "0.000000");
+        assert_eq!(format_float(-0.25), "-0.25000");
+        assert_eq!(format_float(1.5), "1.500000");
+        assert_eq!(machine_float(-0.0), "0.");
+    }
+
+    #[test]
+    pub fn test_write_error() {
+        // Takes the first 40 bytes then fails.
+        struct Full(usize);
+        impl std::io::Write for Full {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.0 + buf.len() > 40 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "Full"));
+                }
+                self.0 += buf.len();
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut router = CNCRouter::from(
+            Vec::new(), false, Coordinate::from(0.0, 0.0, 1.0), Full(0),
+        );
+        router.move_to_coordinate(&Coordinate::from(1.0, 0.0, 1.0), None, false);
+        assert!(router.write_error().is_none());
+        router.move_to_coordinate(&Coordinate::from(2.0, 0.0, 1.0), None, false);
+        router.move_to_coordinate(&Coordinate::from(3.0, 0.0, 1.0), None, false);
+        assert_eq!(router.write_error().unwrap().kind(), std::io::ErrorKind::WriteZero);
+        assert!(matches!(router.reset_program_and_end(), Err(error::Error::Io(_))));
+    }
+
+    #[test]
+    pub fn test_format_options() {
+        let tool = |index: usize| test_tool(&format!("Bit {}", index), index, 0.125, ToolType::default());
+        let mut router = CNCRouter::from(
+            vec![tool(1), tool(2)], false, Coordinate::from(0.0, 0.0, 1.0), Vec::new(),
+        );
+        let mut options = gcode_format::FormatOptions::default();
+        options.line_numbers = Some(gcode_format::LineNumbers::from(100, 10));
+        options.inch_decimals.insert('X', 3);
+        options.inch_decimals.insert('Y', 3);
+        options.suppress_trailing_zeros = true;
+        options.block_delete = true;
+        router.set_format_options(options);
+        router.set_tool_sequence(vec![0, 1]);
+        router.write_gcode_str("G20");
+        router.set_tool_and_go_home(0, 60.0, "", false, 0.0);
+        router.move_to_coordinate(&Coordinate::from(1.25, -0.0001, 1.0), None, false);
+        router.finish().unwrap();
+
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        // Blank lines and comments are not numbered.
+        let lines: Vec<&str> = gcode.lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('('))
+            .collect();
+        assert!(lines.iter().all(|line| line.trim_start_matches('/').starts_with('N')));
+        let numbers: Vec<u64> = lines.iter()
+            .map(|line| line.trim_start_matches("/N").trim_start_matches('N')
+                .split(' ').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(numbers, (0..lines.len() as u64).map(|i| 100 + i * 10).collect::<Vec<u64>>());
+        assert!(lines.iter().any(|line| line.starts_with("/N") && line.ends_with(" T2")));
+        assert!(lines.last().unwrap().ends_with("X1.25 Y0. Z1."));
+    }
 }
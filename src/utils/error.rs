@@ -0,0 +1,72 @@
+use super::*;
+
+// Everything that can go wrong making a program. Nothing in camcam should
+// panic on bad input or a failed write, it comes back as one of these.
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),                       // writing the gcode or reading an input
+    Parse(gcode_parser::ParseError),          // gcode or a heightmap that could not be read
+    Validation(validation::ValidationReport), // signs with errors in them
+    Thread(String),                           // a tool being made on its own thread panicked
+    Toolpath(String),                         // the bit was not where a move expected it
+    // Moves outside of the machine with MachineProfile::fail_outside_envelope
+    OutsideEnvelope(machine_profile::EnvelopeViolations),
+    Cancelled,                                // progress::CancellationToken was cancelled
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Parse(e) => write!(f, "Could not parse: {}", e),
+            Error::Validation(report) => write!(f, "Signs are not valid:\n{}", report),
+            Error::Thread(message) => write!(f, "Thread failed: {}", message),
+            Error::Toolpath(message) => write!(f, "Bad toolpath: {}", message),
+            Error::OutsideEnvelope(violations) => write!(f, "{}", violations),
+            Error::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            Error::Validation(report) => Some(report),
+            Error::Thread(_) | Error::Toolpath(_) | Error::OutsideEnvelope(_) | Error::Cancelled => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<gcode_parser::ParseError> for Error {
+    fn from(e: gcode_parser::ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<validation::ValidationReport> for Error {
+    fn from(report: validation::ValidationReport) -> Self {
+        Error::Validation(report)
+    }
+}
+
+// What a panicking thread left behind, if it was a message.
+pub fn thread_error(panic: Box<dyn std::any::Any + Send>) -> Error {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        Error::Thread(String::from(*message))
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        Error::Thread(message.clone())
+    } else {
+        Error::Thread(String::from("Unknown panic"))
+    }
+}
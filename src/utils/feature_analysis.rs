@@ -0,0 +1,469 @@
+use super::*;
+
+// Finds where a tool of a given radius can not reach before any gcode is
+// made. What a tool can clear is the opening of the area to cut: grow the
+// shapes by the radius to get where the tool's center can go (expand_lines
+// already joins and drops shapes through PolygonTree) then shrink them back by
+// the radius to get everything the tool sweeps. Anything the sign says to cut
+// that is outside of that is left behind.
+//
+// Left behind areas are either sharp inside corners the round tool can not
+// get into or gaps narrower than the tool.
+
+const MAX_PASSES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Corner {
+    pub location: lines_and_curves::Point,
+    pub angle: f64, // radians on the side being cut, less than PI
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gap {
+    pub bounds: lines_and_curves::Rectangle,
+    pub width: f64, // widest the gap gets
+    pub area: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolFit {
+    pub tool_radius: f64,
+    pub uncleared: Vec<lines_and_curves::Rectangle>, // runs of cells along each row
+    pub uncleared_area: f64,
+    pub gaps: Vec<Gap>,
+    pub corners: Vec<(Corner, f64)>, // corner and how far from it is left
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureAnalysis {
+    pub sign_index: usize,
+    pub fits: Vec<ToolFit>,
+    pub corners: Vec<Corner>,
+    pub smallest_tool_radius: f64, // biggest radius that still finishes the sign
+}
+
+impl Corner {
+    // Distance from the corner to the closest point a tool of radius reaches.
+    pub fn leftover(&self, radius: f64) -> f64 {
+        radius / (self.angle / 2.0).sin() - radius
+    }
+
+    // Biggest radius that leaves no more than tolerance in the corner.
+    pub fn max_radius(&self, tolerance: f64) -> f64 {
+        let leftover_per_radius = 1.0 / (self.angle / 2.0).sin() - 1.0;
+        if leftover_per_radius <= 0.0 {
+            f64::INFINITY
+        } else {
+            tolerance / leftover_per_radius
+        }
+    }
+}
+
+impl ToolFit {
+    pub fn clears(&self) -> bool {
+        self.gaps.is_empty() && self.corners.is_empty()
+    }
+}
+
+impl std::fmt::Display for ToolFit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Radius {}: Uncleared area: {:.4}, Gaps: {}, Corners: {}",
+            self.tool_radius,
+            self.uncleared_area,
+            self.gaps.len(),
+            self.corners.len(),
+        )?;
+        if let Some(gap) = self
+            .gaps
+            .iter()
+            .min_by(|a, b| a.width.total_cmp(&b.width))
+        {
+            write!(f, " (narrowest {:.4})", gap.width)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for FeatureAnalysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Sign {}: Smallest tool needed: radius {:.4}",
+            self.sign_index, self.smallest_tool_radius,
+        )?;
+        for fit in &self.fits {
+            writeln!(f, "{}", fit)?;
+        }
+        Ok(())
+    }
+}
+
+// Every corner of a shape where the side being cut is sharper than a straight
+// line. A circle or a rounded corner never has one.
+pub fn inside_corners<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    sign: &sign::Sign<J>,
+    do_cut_on_odd: bool,
+) -> Vec<Corner> {
+    let mut corners = Vec::new();
+    for (shape, cut_inside) in sign.clone().shapes_cut_inside(do_cut_on_odd) {
+        if shape.lines().len() == 1 && shape.lines()[0].as_circle().is_some() {
+            continue;
+        }
+        let mut points: Vec<lines_and_curves::Point> = Vec::new();
+        for p in cnc_router::CNCPath::to_path_vec(shape.lines()) {
+            let (Some(x), Some(y)) = (p.x, p.y) else {
+                continue;
+            };
+            let p = lines_and_curves::Point::from(x, y);
+            if points.last().map_or(true, |last| last.distance_to(&p) > 0.0) {
+                points.push(p);
+            }
+        }
+        if points.len() > 1 && points[0].distance_to(points.last().unwrap()) == 0.0 {
+            points.pop();
+        }
+        if points.len() < 3 {
+            continue;
+        }
+
+        for i in 0..points.len() {
+            let j = (i + 1) % points.len();
+            let k = (i + 2) % points.len();
+            let angle = lines_and_curves::Point::right_angle(&points[i], &points[j], &points[k]);
+            let angle = if cut_inside {
+                2.0 * std::f64::consts::PI - angle
+            } else {
+                angle
+            };
+            if angle.is_nan() || angle <= 0.0 || angle >= std::f64::consts::PI {
+                continue;
+            }
+            corners.push(Corner {
+                location: points[j],
+                angle: angle,
+            });
+        }
+    }
+    corners
+}
+
+struct Grid {
+    rect: lines_and_curves::Rectangle,
+    cell_size: f64,
+    columns: usize,
+    rows: usize,
+}
+
+impl Grid {
+    fn from(rect: &lines_and_curves::Rectangle, cell_size: f64) -> Self {
+        Self {
+            rect: rect.clone(),
+            cell_size: cell_size,
+            columns: ((rect.width() / cell_size).ceil() as usize).max(1),
+            rows: ((rect.height() / cell_size).ceil() as usize).max(1),
+        }
+    }
+
+    fn cell_center(&self, column: usize, row: usize) -> lines_and_curves::Point {
+        lines_and_curves::Point::from(
+            self.rect.min_x() + (column as f64 + 0.5) * self.cell_size,
+            self.rect.min_y() + (row as f64 + 0.5) * self.cell_size,
+        )
+    }
+
+    fn cells_rect(&self, first: usize, end: usize, row: usize) -> lines_and_curves::Rectangle {
+        lines_and_curves::Rectangle::from(
+            lines_and_curves::Point::from(
+                self.rect.min_x() + first as f64 * self.cell_size,
+                self.rect.min_y() + row as f64 * self.cell_size,
+            ),
+            lines_and_curves::Point::from(
+                (self.rect.min_x() + end as f64 * self.cell_size).min(self.rect.max_x()),
+                (self.rect.min_y() + (row + 1) as f64 * self.cell_size).min(self.rect.max_y()),
+            ),
+        )
+    }
+}
+
+// What a tool of radius leaves behind on the sign. Patches thinner than
+// tolerance along the edges are from sampling and are ignored, so are corners
+// that leave less than tolerance.
+pub fn tool_fit<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    sign: &sign::Sign<J>,
+    do_cut_on_odd: bool,
+    corners: &Vec<Corner>,
+    tool_radius: f64,
+    cell_size: f64,
+    tolerance: f64,
+) -> ToolFit {
+    let mut wanted_sign = sign.clone();
+    let mut cleared_sign = sign
+        .expand_lines(tool_radius, do_cut_on_odd, &Vec::new(), &logging::NoLogger)
+        .expand_lines(-tool_radius, do_cut_on_odd, &Vec::new(), &logging::NoLogger);
+    let grid = Grid::from(sign.bounding_rect(), cell_size);
+
+    let mut is_uncleared = vec![false; grid.columns * grid.rows];
+    let mut uncleared = Vec::new();
+    for row in 0..grid.rows {
+        let mut start = None;
+        for column in 0..=grid.columns {
+            let is_bad = column < grid.columns && {
+                let center = grid.cell_center(column, row);
+                wanted_sign.sees_even_odd_lines_before(center.x, center.y, do_cut_on_odd, false)
+                    && !cleared_sign.sees_even_odd_lines_before(
+                        center.x,
+                        center.y,
+                        do_cut_on_odd,
+                        false,
+                    )
+            };
+            if is_bad {
+                is_uncleared[row * grid.columns + column] = true;
+                if start.is_none() {
+                    start = Some(column);
+                }
+            } else if let Some(first) = start.take() {
+                uncleared.push(grid.cells_rect(first, column, row));
+            }
+        }
+    }
+
+    let corners: Vec<(Corner, f64)> = corners
+        .iter()
+        .map(|corner| (*corner, corner.leftover(tool_radius)))
+        .filter(|(_, leftover)| *leftover > tolerance)
+        .collect();
+
+    // Joins touching cells into regions. A region that is all within reach of
+    // a sharp corner is that corner's leftover, anything else is a gap.
+    let mut gaps = Vec::new();
+    let mut seen = vec![false; is_uncleared.len()];
+    for first in 0..is_uncleared.len() {
+        if !is_uncleared[first] || seen[first] {
+            continue;
+        }
+        seen[first] = true;
+        let mut stack = vec![first];
+        let mut bounds: Option<lines_and_curves::Rectangle> = None;
+        let mut count = 0;
+        let mut widest: f64 = 0.0;
+        let mut is_corner = true;
+        while let Some(index) = stack.pop() {
+            let column = index % grid.columns;
+            let row = index / grid.columns;
+            let cell = grid.cells_rect(column, column + 1, row);
+            bounds = Some(if let Some(bounds) = bounds {
+                bounds.join(&cell)
+            } else {
+                cell
+            });
+            count += 1;
+
+            let center = grid.cell_center(column, row);
+            if let Some((distance, _, _)) = sign.closest_shape(&center) {
+                widest = widest.max(2.0 * distance);
+            }
+            is_corner = is_corner
+                && corners.iter().any(|(corner, _)| {
+                    corner.location.distance_to(&center)
+                        <= tool_radius / (corner.angle / 2.0).sin() + cell_size
+                });
+
+            let mut neighbors = Vec::new();
+            if column > 0 {
+                neighbors.push(index - 1);
+            }
+            if column + 1 < grid.columns {
+                neighbors.push(index + 1);
+            }
+            if row > 0 {
+                neighbors.push(index - grid.columns);
+            }
+            if row + 1 < grid.rows {
+                neighbors.push(index + grid.columns);
+            }
+            for neighbor in neighbors {
+                if is_uncleared[neighbor] && !seen[neighbor] {
+                    seen[neighbor] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        if is_corner || widest <= 2.0 * tolerance {
+            continue;
+        }
+        gaps.push(Gap {
+            bounds: bounds.unwrap(),
+            width: widest,
+            area: count as f64 * cell_size * cell_size,
+        });
+    }
+
+    let uncleared_area = is_uncleared.iter().filter(|x| **x).count() as f64 * cell_size * cell_size;
+    ToolFit {
+        tool_radius: tool_radius,
+        uncleared: uncleared,
+        uncleared_area: uncleared_area,
+        gaps: gaps,
+        corners: corners,
+    }
+}
+
+// Biggest radius that leaves no gaps and no more than tolerance in any corner.
+// Starts from start_radius and keeps shrinking to the narrowest gap found
+// until none are left. Never goes below cell_size since the grid can not see
+// anything smaller.
+pub fn smallest_tool_radius<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    sign: &sign::Sign<J>,
+    do_cut_on_odd: bool,
+    corners: &Vec<Corner>,
+    start_radius: f64,
+    cell_size: f64,
+    tolerance: f64,
+) -> f64 {
+    let mut radius = start_radius;
+    for corner in corners {
+        radius = radius.min(corner.max_radius(tolerance));
+    }
+
+    for _ in 0..MAX_PASSES {
+        if radius <= cell_size {
+            return cell_size;
+        }
+        let fit = tool_fit(sign, do_cut_on_odd, &Vec::new(), radius, cell_size, tolerance);
+        let Some(narrowest) = fit
+            .gaps
+            .iter()
+            .map(|gap| gap.width / 2.0)
+            .min_by(|a, b| a.total_cmp(b))
+        else {
+            return radius;
+        };
+        // The widest point of a gap is not always where the tool gets stuck.
+        radius = if narrowest < radius {
+            narrowest
+        } else {
+            radius / 2.0
+        };
+    }
+    radius.max(cell_size)
+}
+
+// Checks every tool radius against every sign.
+pub fn analyze<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    signs: &Vec<sign::Sign<J>>,
+    do_cut_on_odd: bool,
+    tool_radii: &Vec<f64>,
+    cell_size: f64,
+    tolerance: f64,
+) -> Vec<FeatureAnalysis> {
+    let start_radius = tool_radii.iter().cloned().fold(cell_size, f64::max);
+    let mut analyses = Vec::new();
+    for (sign_index, sign) in signs.iter().enumerate() {
+        let corners = inside_corners(sign, do_cut_on_odd);
+        let fits = tool_radii
+            .iter()
+            .map(|radius| tool_fit(sign, do_cut_on_odd, &corners, *radius, cell_size, tolerance))
+            .collect();
+        analyses.push(FeatureAnalysis {
+            sign_index: sign_index,
+            fits: fits,
+            smallest_tool_radius: smallest_tool_radius(
+                sign,
+                do_cut_on_odd,
+                &corners,
+                start_radius,
+                cell_size,
+                tolerance,
+            ),
+            corners: corners,
+        });
+    }
+    analyses
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_tool_fit() {
+        // Two blocks a 0.2 gap apart inside a pocket, cutting around them.
+        let block = |min_x: f64, max_x: f64| {
+            sign::Shape::from(
+                cnc_router::ShapeType::text(),
+                lines_and_curves::AllIntersections::from_line_segment(
+                    lines_and_curves::LineSegment::from_points(&vec![
+                        lines_and_curves::Point::from(min_x, 1.0),
+                        lines_and_curves::Point::from(max_x, 1.0),
+                        lines_and_curves::Point::from(max_x, 3.0),
+                        lines_and_curves::Point::from(min_x, 3.0),
+                    ]),
+                ),
+            )
+        };
+        let sign = sign::Sign::from(
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(4.0, 4.0),
+            ),
+            vec![block(1.0, 1.9), block(2.1, 3.0)],
+        );
+
+        // The blocks are cut around so their corners point out into the cut.
+        let corners = inside_corners(&sign, false);
+        assert!(corners.is_empty());
+
+        let small = tool_fit(&sign, false, &corners, 0.05, 0.02, 0.01);
+        assert!(small.clears());
+        let big = tool_fit(&sign, false, &corners, 0.25, 0.02, 0.01);
+        assert!(!big.clears());
+        assert!(big.uncleared_area > 0.0);
+        let gap = big
+            .gaps
+            .iter()
+            .find(|gap| gap.bounds.min_x() >= 1.85 && gap.bounds.max_x() <= 2.15)
+            .unwrap();
+        assert!((gap.width - 0.2).abs() < 0.05);
+
+        let analyses = analyze(&vec![sign], false, &vec![0.05, 0.25], 0.02, 0.01);
+        assert_eq!(analyses.len(), 1);
+        assert_eq!(analyses[0].fits.len(), 2);
+        assert!(analyses[0].smallest_tool_radius <= 0.1);
+        assert!(analyses[0].smallest_tool_radius >= 0.02);
+    }
+
+    #[test]
+    pub fn test_inside_corners() {
+        // A square pocket: all four corners are sharp on the cut side.
+        let sign = sign::Sign::from(
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(2.0, 2.0),
+            ),
+            vec![sign::Shape::from(
+                cnc_router::ShapeType::text(),
+                lines_and_curves::AllIntersections::from_line_segment(
+                    lines_and_curves::LineSegment::from_points(&vec![
+                        lines_and_curves::Point::from(0.5, 0.5),
+                        lines_and_curves::Point::from(1.5, 0.5),
+                        lines_and_curves::Point::from(1.5, 1.5),
+                        lines_and_curves::Point::from(0.5, 1.5),
+                    ]),
+                ),
+            )],
+        );
+        let corners = inside_corners(&sign, true);
+        assert_eq!(corners.len(), 4);
+        for corner in &corners {
+            assert!((corner.angle - std::f64::consts::PI / 2.0).abs() < 0.000001);
+            // r / sin(45) - r
+            assert!((corner.leftover(0.1) - 0.041421).abs() < 0.00001);
+            assert!((corner.max_radius(0.041421) - 0.1).abs() < 0.0001);
+        }
+        assert!(inside_corners(&sign, false).is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use super::*;
+use serde::{Serialize, Deserialize};
+
+// Works out the spindle speed, feed rate and plunge rate of a tool from its
+// flutes, diameter and the chipload it should take in a material instead of
+// typing in absolute feed rates for every tool.
+//
+//   rpm  = surface speed / (PI * diameter)
+//   feed = rpm * flutes * chipload
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Material {
+    Acrylic,
+    Pvc,
+    Hdu, // high density urethane sign foam
+    Mdf,
+    Aluminum,
+    Brass,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operation {
+    Slot,          // full width of the tool in solid material
+    Pocket(f64),   // step over as a fraction of the diameter
+    Contour,       // along a wall that has been cleared next to it
+    Plunge,
+}
+
+// What the machine can do. Everything worked out is clamped to these.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeedLimits {
+    pub min_rpm: f64,
+    pub max_rpm: f64,
+    pub max_feed_rate: f64,
+    pub max_plunge_rate: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Feeds {
+    pub rpm: f64,
+    pub feed_rate: f64,
+    pub plunge_rate: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToolRecord {
+    pub tool: cnc_router::Tool,
+    #[serde(default)]
+    pub flutes: u32,
+    #[serde(default)]
+    pub chiploads: Vec<(Material, f64)>, // per tooth, same units as the radius
+}
+
+impl Material {
+    pub fn all() -> Vec<Material> {
+        vec![
+            Material::Acrylic,
+            Material::Pvc,
+            Material::Hdu,
+            Material::Mdf,
+            Material::Aluminum,
+            Material::Brass,
+        ]
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Material::Acrylic => String::from("Acrylic"),
+            Material::Pvc => String::from("PVC"),
+            Material::Hdu => String::from("HDU"),
+            Material::Mdf => String::from("MDF"),
+            Material::Aluminum => String::from("Aluminum"),
+            Material::Brass => String::from("Brass"),
+        }
+    }
+
+    // Feet per minute for a carbide tool.
+    pub fn surface_speed(&self) -> f64 {
+        match self {
+            Material::Acrylic => 800.0,
+            Material::Pvc => 800.0,
+            Material::Hdu => 1000.0,
+            Material::Mdf => 1200.0,
+            Material::Aluminum => 600.0,
+            Material::Brass => 400.0,
+        }
+    }
+
+    // Chipload as a fraction of the diameter for when a tool record does
+    // not give one.
+    pub fn chipload_per_diameter(&self) -> f64 {
+        match self {
+            Material::Acrylic => 0.02,
+            Material::Pvc => 0.02,
+            Material::Hdu => 0.03,
+            Material::Mdf => 0.03,
+            Material::Aluminum => 0.008,
+            Material::Brass => 0.006,
+        }
+    }
+
+    // Plunge rate as a fraction of the feed rate.
+    pub fn plunge_factor(&self) -> f64 {
+        match self {
+            Material::Aluminum | Material::Brass => 0.3,
+            _ => 0.5,
+        }
+    }
+}
+
+impl Operation {
+    // How much of the full chipload feed to use. Slotting loads both sides
+    // of the tool so it is slowed down. A small step over thins the chip so
+    // it can go faster to keep the same chipload.
+    pub fn feed_factor(&self) -> f64 {
+        match self {
+            Operation::Slot => 0.6,
+            Operation::Pocket(step_over) => {
+                let step_over = step_over.clamp(0.05, 1.0);
+                if step_over >= 0.5 {
+                    // Goes from 1 at half the diameter down to slotting.
+                    1.0 - 0.4 * (step_over - 0.5) / 0.5
+                } else {
+                    // Radial chip thinning.
+                    let ratio = 1.0 - 2.0 * step_over;
+                    (1.0 / (1.0 - ratio * ratio).sqrt()).min(2.0)
+                }
+            }
+            Operation::Contour => 1.0,
+            Operation::Plunge => 1.0,
+        }
+    }
+
+    // Broad tools go back and forth step over apart and the rest follow
+    // walls. A step over of the whole tool is a slot.
+    pub fn of_tool(tool: &cnc_router::Tool) -> Self {
+        if tool.tool_type().is_broad() {
+            if tool.offset >= 1.0 {
+                Operation::Slot
+            } else {
+                Operation::Pocket(tool.offset)
+            }
+        } else if tool.tool_type().is_helical_hole() {
+            Operation::Slot
+        } else {
+            Operation::Contour
+        }
+    }
+}
+
+impl Default for FeedLimits {
+    fn default() -> Self {
+        Self {
+            min_rpm: 8000.0,
+            max_rpm: 24000.0,
+            max_feed_rate: 400.0,
+            max_plunge_rate: 100.0,
+        }
+    }
+}
+
+impl FeedLimits {
+    pub fn from(min_rpm: f64, max_rpm: f64, max_feed_rate: f64, max_plunge_rate: f64) -> Self {
+        Self {
+            min_rpm: min_rpm,
+            max_rpm: max_rpm,
+            max_feed_rate: max_feed_rate,
+            max_plunge_rate: max_plunge_rate,
+        }
+    }
+}
+
+impl ToolRecord {
+    pub fn from(tool: cnc_router::Tool, flutes: u32, chiploads: Vec<(Material, f64)>) -> Self {
+        Self {
+            tool: tool,
+            flutes: flutes,
+            chiploads: chiploads,
+        }
+    }
+
+    pub fn diameter(&self) -> f64 {
+        2.0 * self.tool.radius
+    }
+
+    pub fn chipload(&self, material: Material) -> f64 {
+        self.chiploads
+            .iter()
+            .find(|(m, _)| *m == material)
+            .map(|(_, chipload)| *chipload)
+            .unwrap_or(self.diameter() * material.chipload_per_diameter())
+    }
+
+    // Feeds for one operation in the material. use_inches says what the
+    // tool's radius and the feed rates are in.
+    pub fn feeds(
+        &self,
+        material: Material,
+        operation: Operation,
+        limits: &FeedLimits,
+        use_inches: bool,
+    ) -> Feeds {
+        let diameter_in_feet = if use_inches {
+            self.diameter() / 12.0
+        } else {
+            self.diameter() / 304.8
+        };
+        let rpm = if diameter_in_feet > 0.0 {
+            material.surface_speed() / (std::f64::consts::PI * diameter_in_feet)
+        } else {
+            limits.max_rpm
+        };
+        let rpm = rpm.clamp(limits.min_rpm, limits.max_rpm);
+
+        // Feed comes from the rpm after clamping so the chipload stays right.
+        let feed_rate = rpm * self.flutes.max(1) as f64 * self.chipload(material);
+        let plunge_rate = (feed_rate * material.plunge_factor()).min(limits.max_plunge_rate);
+        let feed_rate = (feed_rate * operation.feed_factor()).min(limits.max_feed_rate);
+        Feeds {
+            rpm: rpm,
+            feed_rate: if operation == Operation::Plunge {
+                plunge_rate
+            } else {
+                feed_rate
+            },
+            plunge_rate: plunge_rate,
+        }
+    }
+
+    // Copy of the tool with its rates and spindle speed set for what its
+    // tool type does.
+    pub fn tool_for(
+        &self,
+        material: Material,
+        limits: &FeedLimits,
+        use_inches: bool,
+    ) -> cnc_router::Tool {
+        let feeds = self.feeds(material, Operation::of_tool(&self.tool), limits, use_inches);
+        cnc_router::Tool {
+            feed_rate_of_cut: feeds.feed_rate,
+            feed_rate_of_drill: feeds.plunge_rate,
+            spindle_speed: Some(feeds.rpm),
+            ..self.tool.clone()
+        }
+    }
+}
+
+// Every record's tool set up for the material, in the same order.
+pub fn tools_for(
+    records: &Vec<ToolRecord>,
+    material: Material,
+    limits: &FeedLimits,
+    use_inches: bool,
+) -> Vec<cnc_router::Tool> {
+    records
+        .iter()
+        .map(|record| record.tool_for(material, limits, use_inches))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_feeds() {
+        let mut tool = cnc_router::test_tool(
+            "Quarter Inch Bit", 1, 0.125, cnc_router::ToolType::FullCutBroad(100.0, false),
+        );
+        tool.feed_rate_of_cut = 0.0;
+        tool.feed_rate_of_drill = 0.0;
+        let record = ToolRecord::from(tool, 2, vec![(Material::Acrylic, 0.004)]);
+        let limits = FeedLimits::from(8000.0, 12000.0, 500.0, 40.0);
+
+        // 800 / (PI * 0.25 / 12) is about 12223 so it is clamped to the max.
+        let slot = record.feeds(Material::Acrylic, Operation::Slot, &limits, true);
+        assert_eq!(slot.rpm, 12000.0);
+        assert!((slot.feed_rate - 12000.0 * 2.0 * 0.004 * 0.6).abs() < 0.000001);
+        assert_eq!(slot.plunge_rate, 40.0);
+
+        let contour = record.feeds(Material::Acrylic, Operation::Contour, &limits, true);
+        assert!(contour.feed_rate > slot.feed_rate);
+        let thin = record.feeds(Material::Acrylic, Operation::Pocket(0.1), &limits, true);
+        assert!(thin.feed_rate > contour.feed_rate);
+
+        // Brass has no chipload given so it comes from the diameter.
+        assert!((record.chipload(Material::Brass) - 0.25 * 0.006).abs() < 0.000001);
+        let brass = record.feeds(Material::Brass, Operation::Contour, &limits, true);
+        // About 6112 is slower than the spindle goes.
+        assert_eq!(brass.rpm, 8000.0);
+        assert!((brass.feed_rate - 8000.0 * 2.0 * 0.0015).abs() < 0.000001);
+
+        let tool = record.tool_for(Material::Acrylic, &limits, true);
+        assert_eq!(tool.spindle_speed, Some(12000.0));
+        let pocket = record.feeds(Material::Acrylic, Operation::Pocket(0.5), &limits, true);
+        assert_eq!(tool.feed_rate_of_cut, pocket.feed_rate);
+        assert_eq!(tool.feed_rate_of_drill, 40.0);
+    }
+}
@@ -2,33 +2,18 @@
 use super::*;
 use std::sync::mpsc;
 
-use std::sync::{Arc, Mutex};
 use std::thread;
 
-use once_cell::sync::Lazy;
-static TOTAL_THREADS: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(8));
-static THREADS_IN_USE: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
-
-pub fn set_threads(mut thread_count: u32) {
-    if thread_count < 4 {
-        thread_count = 4;
-    }
-    let mut total_threads = TOTAL_THREADS.lock().unwrap();
-    *total_threads = thread_count;
-}
-pub fn try_get_threads() ->
-    std::sync::TryLockResult<std::sync::MutexGuard<'static, u32>>
-{
-    return THREADS_IN_USE.try_lock();
-}
-
 struct ThreadWriter {
     sender: std::sync::mpsc::Sender<String>,
 }
 impl std::io::Write for ThreadWriter {
     fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
-        let msg = std::str::from_utf8(buf).unwrap().to_string();
-        self.sender.send(msg).unwrap();
+        let msg = std::str::from_utf8(buf)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+            .to_string();
+        self.sender.send(msg)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::BrokenPipe, e))?;
         // let msg = std::str::from_utf8(buf).unwrap().to_string();
         // print!("{}", msg);
         return Ok(buf.len());
@@ -45,6 +30,12 @@ pub struct GCodeCreator<T: std::io::Write> {
     feed_rate: f64,
     z_axis_off_cut: f64,
     depth_of_cut: f64,
+    route_report: ordering::RouteReport,
+    route_reports: Vec<(usize, ordering::RouteReport)>,
+    thread_count: usize, // most tools build_gcode_smart_path works on at once
+    max_scallop_height: Option<f64>, // ridge ball and bull nose passes can leave
+    progress: progress::Reporter,
+    logger: std::sync::Arc<dyn logging::Logger>,
 }
 
 enum CutBroadSmartPathMethodArguments {
@@ -58,26 +49,24 @@ enum CutBroadSmartPathMethodReturn {
     MaxY(f64),
     MinY(f64),
 }
+// Each question gets its own kind of answer back. Any other answer is taken
+// as nothing to cut there.
 impl CutBroadSmartPathMethodReturn {
     fn can_cut(&self) -> bool {
-        if let CutBroadSmartPathMethodReturn::CanCut(x) = self {
-            *x
-        } else {
-            panic!("Tried to force a CutBroadSmartPathMethodReturn into a CanCut");
-        }
+        matches!(self, CutBroadSmartPathMethodReturn::CanCut(true))
     }
-    fn max_y(&self) -> f64 {
+    fn max_y(&self, y: f64) -> f64 {
         if let CutBroadSmartPathMethodReturn::MaxY(x) = self {
             *x
         } else {
-            panic!("Tried to force a CutBroadSmartPathMethodReturn into a MaxY");
+            y
         }
     }
-    fn min_y(&self) -> f64 {
+    fn min_y(&self, y: f64) -> f64 {
         if let CutBroadSmartPathMethodReturn::MinY(x) = self {
             *x
         } else {
-            panic!("Tried to force a CutBroadSmartPathMethodReturn into a MinY");
+            y
         }
     }
 }
@@ -101,6 +90,12 @@ impl<T: std::io::Write> GCodeCreator<T> {
             feed_rate: feed_rate,
             z_axis_off_cut: z_axis_off_cut,
             depth_of_cut: depth_of_cut,
+            route_report: ordering::RouteReport::default(),
+            route_reports: Vec::new(),
+            thread_count: 8,
+            max_scallop_height: None,
+            progress: progress::Reporter::default(),
+            logger: std::sync::Arc::new(logging::NoLogger),
         };
 
         gc.cnc_router.generate_header(use_inches, &name, extra_header_message);
@@ -116,7 +111,208 @@ impl<T: std::io::Write> GCodeCreator<T> {
             feed_rate: self.feed_rate,
             z_axis_off_cut: self.z_axis_off_cut,
             depth_of_cut: self.depth_of_cut,
+            route_report: ordering::RouteReport::default(),
+            route_reports: Vec::new(),
+            thread_count: self.thread_count,
+            max_scallop_height: self.max_scallop_height,
+            progress: self.progress.clone(),
+            logger: self.logger.clone(),
+        }
+    }
+
+    // 1 builds every tool one after the other on a single thread. The G-code
+    // is the same whatever the count is.
+    pub fn set_thread_count(&mut self, thread_count: usize) {
+        self.thread_count = thread_count.max(1);
+    }
+
+    pub fn get_thread_count(&self) -> usize {
+        self.thread_count
+    }
+
+    // Ball and bull nose tools step over less than their offset says when
+    // that would leave ridges higher than max_scallop_height between passes.
+    // None, 0 or less leaves the offset alone.
+    pub fn set_max_scallop_height(&mut self, max_scallop_height: Option<f64>) {
+        self.max_scallop_height = max_scallop_height.filter(|height| *height > 0.0);
+    }
+
+    // Called as each tool starts and between pockets. Can be called from
+    // more than one thread at a time.
+    pub fn set_progress_callback(
+        &mut self,
+        callback: impl Fn(&progress::Progress) + Send + Sync + 'static,
+    ) {
+        self.progress.set_callback(Some(std::sync::Arc::new(callback)));
+    }
+
+    // Where timings, sign warnings and anything the geometry or router has to
+    // say go. Nothing is logged until this is called.
+    pub fn set_logger(&mut self, logger: std::sync::Arc<dyn logging::Logger>) {
+        self.cnc_router.set_logger(logger.clone());
+        self.logger = logger;
+    }
+
+    // Cancelling the token or any clone of it stops the build with
+    // error::Error::Cancelled.
+    pub fn set_cancellation_token(&mut self, cancellation_token: progress::CancellationToken) {
+        self.progress.set_cancellation_token(cancellation_token);
+    }
+
+    pub fn cancellation_token(&self) -> progress::CancellationToken {
+        self.progress.cancellation_token().clone()
+    }
+
+    // Machine time and distances of everything cut so far. Only works once
+    // the router is recording its toolpath.
+    pub fn stats(&self, speeds: &stats::MachineSpeeds) -> Option<stats::Stats> {
+        self.cnc_router
+            .get_toolpath()
+            .map(|toolpath| stats::stats(toolpath, speeds))
+    }
+
+    // SVG of the sign and everything cut so far. Only works once the router
+    // is recording its toolpath.
+    pub fn preview_svg<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+        &self,
+        sign: &sign::Sign<J>,
+        options: &svg_preview::SvgOptions,
+    ) -> Option<String> {
+        self.cnc_router.get_toolpath().map(|toolpath| {
+            svg_preview::to_svg(Some(sign), toolpath, self.cnc_router.get_tools(), options)
+        })
+    }
+
+    // Cuts everything so far out of stock the size of the sign and checks it
+    // against the sign. Only works once the router is recording its toolpath.
+    pub fn simulate<J: lines_and_curves::Intersection + Clone>(
+        &self,
+        sign: &sign::Sign<J>,
+        do_cut_on_odd: bool,
+        thickness: f64,
+        cell_size: f64,
+        tolerance: f64,
+    ) -> Option<simulation::SimulationReport> {
+        let toolpath = self.cnc_router.get_toolpath()?;
+        let mut stock = simulation::Stock::from(sign.bounding_rect().clone(), thickness, cell_size);
+        stock.replay(toolpath, self.cnc_router.get_tools());
+        Some(stock.compare(sign, do_cut_on_odd, self.cut_depth(), tolerance))
+    }
+
+    // Rapid distance before and after ordering for every tool cut so far.
+    pub fn route_reports(&self) -> &Vec<(usize, ordering::RouteReport)> {
+        &self.route_reports
+    }
+
+    // Orders the shapes the tool will cut to keep rapids short.
+    fn order_shapes<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+        &mut self,
+        shapes: &Vec<(sign::Shape<J>, bool)>,
+        tool: &cnc_router::Tool,
+    ) -> Vec<usize> {
+        let mut indexes = Vec::new();
+        let mut points = Vec::new();
+        for (i, (shape, _)) in shapes.iter().enumerate() {
+            if !shape
+                .tool_type()
+                .subset_of(&tool.tool_type().to_shape_type())
+            {
+                continue;
+            }
+            // A corner of the shape, enter_shape_near_tool picks the one the
+            // cutter goes in at.
+            let start = shape
+                .lines()
+                .first()
+                .and_then(|line| line.start_path())
+                .map(|c| (c.x, c.y));
+            let Some((x, y)) = start else {
+                continue;
+            };
+            indexes.push(i);
+            points.push(lines_and_curves::Point::from(x, y));
+        }
+
+        let (order, report) = ordering::order_points(&self.cnc_router.get_point(), &points);
+        self.route_report.add(&report);
+        order.iter().map(|i| indexes[*i]).collect()
+    }
+
+    // Starts a closed contour at the corner closest to the tool.
+    fn enter_shape_near_tool<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+        &self,
+        shape: &sign::Shape<J>,
+    ) -> sign::Shape<J> {
+        let mut points = Vec::new();
+        for p in cnc_router::CNCPath::to_path_vec(shape.lines()) {
+            let (Some(x), Some(y)) = (p.x, p.y) else {
+                return shape.clone();
+            };
+            points.push(lines_and_curves::Point::from(x, y));
+        }
+        if points.len() != shape.lines().len() {
+            return shape.clone();
+        }
+        let Some(closest) = ordering::closest_point(&self.cnc_router.get_point(), &points) else {
+            return shape.clone();
+        };
+        // Point i is where line i ends and cut_till goes in where the first
+        // line ends.
+        shape.rotated(closest)
+    }
+
+    // How far below the top of the material the bottom of a cut is, 0 if
+    // the tip never gets down to it.
+    pub fn cut_depth(&self) -> f64 {
+        (-(self.z_axis_off_cut + self.depth_of_cut)).max(0.0)
+    }
+
+    // How far apart the passes of tool are, see set_max_scallop_height.
+    pub fn step_over_of(&self, tool: &cnc_router::Tool) -> f64 {
+        let step_over = 2.0 * tool.radius * tool.offset;
+        match (self.max_scallop_height, tool.geometry) {
+            (
+                Some(height),
+                tool_geometry::ToolGeometry::BallEndMill | tool_geometry::ToolGeometry::BullNose(_),
+            ) => step_over.min(tool.geometry.stepover_for_scallop(tool.radius, height)),
+            _ => step_over,
+        }
+    }
+
+    // Copy of tool with its radius being what it really cuts at the bottom of
+    // the cut and its offset giving step_over_of, so offsets and step overs
+    // work for ball, bull nose and v-bits. Nothing is cut when the tip stays
+    // above the material so the tool is left as it is.
+    pub fn tool_at_depth(&self, tool: &cnc_router::Tool) -> cnc_router::Tool {
+        let radius = tool.effective_radius(self.cut_depth());
+        if !(radius > 0.0) {
+            return tool.clone();
         }
+        cnc_router::Tool {
+            radius: radius,
+            offset: (2.0 * radius * tool.offset).min(self.step_over_of(tool)) / (2.0 * radius),
+            ..tool.clone()
+        }
+    }
+
+    pub fn spindle_speed_of(&self, tool: &cnc_router::Tool) -> f64 {
+        tool.spindle_speed.unwrap_or(self.spindle_speed)
+    }
+
+    // Turns the spindle on the way the tool wants it, waits for it to get up
+    // to speed then turns on its coolant.
+    fn start_spindle(&mut self, tool: &cnc_router::Tool) {
+        self.cnc_router
+            .set_spindle_on(tool.spindle_counter_clockwise, self.spindle_speed_of(tool));
+        if tool.spindle_warm_up > 0.0 {
+            self.cnc_router.dewel((tool.spindle_warm_up * 1000.0).round() as u64);
+        }
+        self.cnc_router.set_coolant(tool.coolant);
+    }
+
+    fn stop_spindle(&mut self) {
+        self.cnc_router.set_spindle_off();
+        self.cnc_router.set_coolant(cnc_router::Coolant::Off);
     }
 
     pub fn is_down(&self, tool_length: f64) -> bool {
@@ -125,6 +321,20 @@ impl<T: std::io::Write> GCodeCreator<T> {
             || (z < self.z_axis_off_cut && self.depth_of_cut < 0.0)
     }
 
+    // Err if the bit is not where cutting left it, is_down or not.
+    fn expect_down(&self, tool: &cnc_router::Tool, down: bool) -> error::Result<()> {
+        if self.is_down(tool.length) == down {
+            return Ok(());
+        }
+        let pos = self.cnc_router.get_pos();
+        Err(error::Error::Toolpath(format!(
+            "Bit {} expected to be {} at ({}, {}, {})",
+            tool.name, if down { "down" } else { "up" }, pos.x, pos.y, pos.z,
+        )))
+    }
+
+    // Like build_gcode_smart_path nothing is written if it fails or is
+    // cancelled part way, the router is left where it stopped.
     pub fn build_gcode<
         J: lines_and_curves::Intersection + std::fmt::Debug + Clone + cnc_router::CNCPath,
     >(
@@ -132,27 +342,66 @@ impl<T: std::io::Write> GCodeCreator<T> {
         do_cut_on_odd: bool,
         next_path: fn(&mut bit_path::PathItr<f64>) -> bool,
         signs: &mut Vec<sign::Sign<J>>,
-    ) {
-        let tools = self.cnc_router.get_tools().clone();
-        for sign in &mut *signs {
-            let mut cuttable_rects = vec![sign.bounding_rect().clone()];
-            let mut seen_full_broad = false;
+    ) -> error::Result<()> {
+        self.cnc_router.hold_output();
+        let result = self.build_gcode_held(do_cut_on_odd, next_path, signs);
+        let released = self.cnc_router.release_output(result.is_ok());
+        result?;
+        released
+    }
 
-            for (tool_index, tool) in tools.iter().enumerate() {
-                if !tool.tool_type().is_broad() {
-                    continue;
-                }
-                let depth_of_cut = self.depth_of_cut;
-                let z_axis_off_cut = self.z_axis_off_cut + tool.length;
-                if !seen_full_broad {
-                    cuttable_rects = vec![sign.bounding_rect().clone()];
-                }
-                if let cnc_router::ToolType::FullCutBroad(_, _) = tool.tool_type {
-                    seen_full_broad = true;
-                }
+    fn build_gcode_held<
+        J: lines_and_curves::Intersection + std::fmt::Debug + Clone + cnc_router::CNCPath,
+    >(
+        &mut self,
+        do_cut_on_odd: bool,
+        next_path: fn(&mut bit_path::PathItr<f64>) -> bool,
+        signs: &mut Vec<sign::Sign<J>>,
+    ) -> error::Result<()> {
+        let tools = self.cnc_router.get_tools().clone();
+        let following = following_tool_types();
+        // Each tool does every sign before the next so the same physical tool
+        // is only put in once for all of them.
+        let schedule = scheduler::schedule(&tools)
+            .into_iter()
+            .filter(|i| {
+                tools[*i].tool_type().is_broad()
+                    || following.iter().any(|t| t.raw_value() == tools[*i].tool_type.raw_value())
+            })
+            .collect::<Vec<usize>>();
+        self.cnc_router.set_tool_sequence(tool_sequence_of(&tools, &schedule));
+        // Rest tools clear what the toolpath so far left behind.
+        if tools.iter().any(|tool| tool.tool_type().is_rest())
+            && self.cnc_router.get_toolpath().is_none() {
+            self.cnc_router.record_toolpath(true);
+        }
+        self.progress.start();
+
+        let mut cuttable_rects = signs
+            .iter()
+            .map(|sign| vec![sign.bounding_rect().clone()])
+            .collect::<Vec<Vec<lines_and_curves::Rectangle>>>();
+        let mut seen_full_broad = vec![false; signs.len()];
+        let mut previous_tool = None;
+        let mut following_started = false;
+        for tool_index in schedule {
+            let tool = &self.tool_at_depth(&tools[tool_index]);
+            if self.progress.is_cancelled() {
+                return Err(error::Error::Cancelled);
+            }
+            if !tool.tool_type().is_broad() && !following_started {
+                following_started = true;
+                self.cnc_router.write_gcode_comment_str("Following Shapes");
+            }
+            self.progress.start_tool(tool_index, &tool.name);
+            if tool.tool_type().is_broad() {
                 self.cnc_router
                     .write_gcode_comment(format!("CHANGED TOOL {}", tool_index));
+            }
 
+            if previous_tool == Some(tool.index_in_machine) {
+                self.cnc_router.keep_tool_and_go_home(tool_index, &tool.pre_cut_gcode);
+            } else {
                 self.cnc_router.set_tool_and_go_home(
                     tool_index,
                     tool.feed_rate_of_cut,
@@ -160,164 +409,33 @@ impl<T: std::io::Write> GCodeCreator<T> {
                     tool.force_retouch_off,
                     tool.suggested_length,
                 );
-                self.cnc_router.set_spindle_on(false, self.spindle_speed);
-                let bit_diameter = 2.0 * tool.radius;
-
-                let mut new_cuttable_rects = lines_and_curves::RectangleConnections::from(
-                    bit_diameter,
-                    bit_diameter,
-                    sign.bounding_rect().clone(),
-                );
-
-                for cut_rect in cuttable_rects {
-                    let sign_width = cut_rect.width(); // sign.bounding_rect().width();
-                    let sign_height = cut_rect.height(); // sign.bounding_rect().height();
-                    let width = (cut_rect.width() / (bit_diameter * tool.offset)) as usize;
-                    let height = (cut_rect.height() / (bit_diameter * tool.offset)) as usize;
-                    let min_x = cut_rect.min_x();
-                    let min_y = cut_rect.min_y();
-
-                    let mut path = bit_path::Path::from(
-                        width,
-                        height,
-                        Box::from(move |x: usize, y: usize| -> (f64, f64) {
-                            (
-                                (x as f64 / width as f64) as f64 * sign_width + min_x,
-                                (y as f64 / height as f64) as f64 * sign_height + min_y,
-                            )
-                        }),
-                        next_path,
-                    );
-                    if self.start_middle {
-                        path = path.start_middle();
-                    }
-                    let mut cut_to: Option<lines_and_curves::Point> = None;
-                    for (x, y, can_be_down) in path {
-                        let seen = sign.y_values_before(x, y);
-                        let rect = lines_and_curves::Rectangle::from(
-                            lines_and_curves::Point::from(
-                                x - bit_diameter / 2.0,
-                                y - bit_diameter / 2.0,
-                            ),
-                            lines_and_curves::Point::from(
-                                x + bit_diameter / 2.0,
-                                y + bit_diameter / 2.0,
-                            ),
-                        );
-
-                        let crosses_rect = sign.line_collides_wth_rect(&rect);
-                        let is_down =
-                            can_be_down && ((seen % 2 == 1) == do_cut_on_odd) && !crosses_rect;
-                        if crosses_rect {
-                            new_cuttable_rects.add_rect(&lines_and_curves::Point::from(x, y));
-                        }
-
-                        if is_down {
-                            if let Some(p) = cut_to {
-                                assert!(self.is_down(tool.length));
-                                if !lines_and_curves::LineSegment::from(
-                                    self.cnc_router.get_point(),
-                                    lines_and_curves::Point::from(x, y),
-                                )
-                                .contains_point_endless_line(p)
-                                {
-                                    new_cuttable_rects
-                                        .add_rect(&lines_and_curves::Point::from(p.x, p.y));
-                                    self.cnc_router.move_to_optional_coordinate(
-                                        &cnc_router::OptionalCoordinate::from(
-                                            Some(p.x),
-                                            Some(p.y),
-                                            None,
-                                        ),
-                                        Some(tool.feed_rate_of_cut),
-                                        false,
-                                    );
-                                }
-                            } else {
-                                assert!(!self.is_down(tool.length));
-                                // removing this as it adds in unnecessary fillers on paths
-                                // with horizontal lines
-                                // new_cuttable_rects.add_rect(&lines_and_curves::Point::from(x, y));
-                                self.cnc_router.move_to_coordinate_rapid(
-                                    &cnc_router::Coordinate::from(x, y, z_axis_off_cut),
-                                );
-                                self.cnc_router.move_to_optional_coordinate(
-                                    &cnc_router::OptionalCoordinate::from_z(Some(
-                                        z_axis_off_cut + depth_of_cut,
-                                    )),
-                                    Some(tool.feed_rate_of_drill),
-                                    false,
-                                );
-                            }
+            }
+            previous_tool = Some(tool.index_in_machine);
+            self.start_spindle(tool);
 
-                            cut_to = Some(lines_and_curves::Point::from(x, y));
-                        } else {
-                            if let Some(p) = cut_to {
-                                assert!(self.is_down(tool.length));
-                                self.cnc_router.move_to_optional_coordinate(
-                                    &cnc_router::OptionalCoordinate::from(
-                                        Some(p.x),
-                                        Some(p.y),
-                                        None,
-                                    ),
-                                    Some(tool.feed_rate_of_cut),
-                                    false,
-                                );
-                                self.cnc_router.move_to_optional_coordinate(
-                                    &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
-                                    None,
-                                    false,
-                                );
-                            }
-                            cut_to = None;
-                        }
+            if let cnc_router::ToolType::RestCutBroad(tolerance) = tool.tool_type() {
+                let cut_before = self.cnc_router.get_toolpath().cloned().unwrap_or_default();
+                for sign in signs.iter() {
+                    self.rest_path(do_cut_on_odd, sign.clone(), &Vec::new(), tool, &cut_before, tolerance);
+                }
+            } else if tool.tool_type().is_broad() {
+                for (sign_index, sign) in signs.iter_mut().enumerate() {
+                    if !seen_full_broad[sign_index] {
+                        cuttable_rects[sign_index] = vec![sign.bounding_rect().clone()];
                     }
-
-                    if self.is_down(tool.length) {
-                        if let Some(p) = cut_to {
-                            self.cnc_router.move_to_optional_coordinate(
-                                &cnc_router::OptionalCoordinate::from(Some(p.x), Some(p.y), None),
-                                Some(tool.feed_rate_of_cut),
-                                false,
-                            );
-                        }
+                    if let cnc_router::ToolType::FullCutBroad(_, _) = tool.tool_type {
+                        seen_full_broad[sign_index] = true;
                     }
-                    self.cnc_router.move_to_optional_coordinate(
-                        &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
-                        Some(tool.feed_rate_of_cut),
-                        false,
-                    );
-                }
-
-                let mut new_rects = Vec::new();
-                new_cuttable_rects.to_bigger_rect_iter();
-                new_rects.extend(new_cuttable_rects);
-                cuttable_rects = new_rects;
-
-                self.cnc_router.set_spindle_off();
-            }
-        }
-
-        self.cnc_router.write_gcode_comment_str("Following Shapes");
-        for tool_type in vec![
-            cnc_router::ToolType::full_text(),
-            cnc_router::ToolType::PartialContourAngle(0.0, 0.0, cnc_router::ShapeType::all()),
-            cnc_router::ToolType::full_braille(),
-        ] {
-            for (tool_index, tool) in tools.iter().enumerate() {
-                if tool_type.raw_value() != tool.tool_type.raw_value() {
-                    continue;
+                    cuttable_rects[sign_index] = self.cut_broad_sign(
+                        do_cut_on_odd,
+                        next_path,
+                        sign,
+                        tool,
+                        std::mem::take(&mut cuttable_rects[sign_index]),
+                    )?;
                 }
-
+            } else {
                 let z_axis_off_cut = self.z_axis_off_cut + tool.length;
-                self.cnc_router.set_tool_and_go_home(
-                    tool_index,
-                    tool.feed_rate_of_cut,
-                    &tool.pre_cut_gcode,
-                    tool.force_retouch_off,
-                    tool.suggested_length,
-                );
-                self.cnc_router.set_spindle_on(false, self.spindle_speed);
                 for sign in &mut *signs {
                     for shape in sign.shapes() {
                         let mut first_line = true;
@@ -358,13 +476,161 @@ impl<T: std::io::Write> GCodeCreator<T> {
                         }
                     }
                 }
-                self.cnc_router.set_spindle_off();
             }
+            self.stop_spindle();
         }
 
         self.cnc_router.go_home();
-        self.cnc_router.set_spindle_off();
-        self.cnc_router.reset_program_and_end();
+        self.cnc_router.reset_program_and_end()?;
+        self.cnc_router.envelope_result()
+    }
+
+    // Clears what is left of cuttable_rects on sign with a broad tool giving
+    // back the rects the next broad tool should clear.
+    fn cut_broad_sign<
+        J: lines_and_curves::Intersection + std::fmt::Debug + Clone + cnc_router::CNCPath,
+    >(
+        &mut self,
+        do_cut_on_odd: bool,
+        next_path: fn(&mut bit_path::PathItr<f64>) -> bool,
+        sign: &mut sign::Sign<J>,
+        tool: &cnc_router::Tool,
+        cuttable_rects: Vec<lines_and_curves::Rectangle>,
+    ) -> error::Result<Vec<lines_and_curves::Rectangle>> {
+        let depth_of_cut = self.depth_of_cut;
+        let z_axis_off_cut = self.z_axis_off_cut + tool.length;
+        let bit_diameter = 2.0 * tool.radius;
+
+        let mut new_cuttable_rects = lines_and_curves::RectangleConnections::from(
+            bit_diameter,
+            bit_diameter,
+            sign.bounding_rect().clone(),
+        );
+
+        for cut_rect in cuttable_rects {
+            let sign_width = cut_rect.width(); // sign.bounding_rect().width();
+            let sign_height = cut_rect.height(); // sign.bounding_rect().height();
+            let width = (cut_rect.width() / (bit_diameter * tool.offset)) as usize;
+            let height = (cut_rect.height() / (bit_diameter * tool.offset)) as usize;
+            let min_x = cut_rect.min_x();
+            let min_y = cut_rect.min_y();
+
+            let mut path = bit_path::Path::from(
+                width,
+                height,
+                Box::from(move |x: usize, y: usize| -> (f64, f64) {
+                    (
+                        (x as f64 / width as f64) as f64 * sign_width + min_x,
+                        (y as f64 / height as f64) as f64 * sign_height + min_y,
+                    )
+                }),
+                next_path,
+            );
+            if self.start_middle {
+                path = path.start_middle();
+            }
+            let mut cut_to: Option<lines_and_curves::Point> = None;
+            for (x, y, can_be_down) in path {
+                let seen = sign.y_values_before(x, y);
+                let rect = lines_and_curves::Rectangle::from(
+                    lines_and_curves::Point::from(
+                        x - bit_diameter / 2.0,
+                        y - bit_diameter / 2.0,
+                    ),
+                    lines_and_curves::Point::from(
+                        x + bit_diameter / 2.0,
+                        y + bit_diameter / 2.0,
+                    ),
+                );
+
+                let crosses_rect = sign.line_collides_wth_rect(&rect);
+                let is_down =
+                    can_be_down && ((seen % 2 == 1) == do_cut_on_odd) && !crosses_rect;
+                if crosses_rect {
+                    new_cuttable_rects.add_rect(&lines_and_curves::Point::from(x, y));
+                }
+
+                if is_down {
+                    if let Some(p) = cut_to {
+                        self.expect_down(tool, true)?;
+                        if !lines_and_curves::LineSegment::from(
+                            self.cnc_router.get_point(),
+                            lines_and_curves::Point::from(x, y),
+                        )
+                        .contains_point_endless_line(p)
+                        {
+                            new_cuttable_rects
+                                .add_rect(&lines_and_curves::Point::from(p.x, p.y));
+                            self.cnc_router.move_to_optional_coordinate(
+                                &cnc_router::OptionalCoordinate::from(
+                                    Some(p.x),
+                                    Some(p.y),
+                                    None,
+                                ),
+                                Some(tool.feed_rate_of_cut),
+                                false,
+                            );
+                        }
+                    } else {
+                        self.expect_down(tool, false)?;
+                        // removing this as it adds in unnecessary fillers on paths
+                        // with horizontal lines
+                        // new_cuttable_rects.add_rect(&lines_and_curves::Point::from(x, y));
+                        self.cnc_router.move_to_coordinate_rapid(
+                            &cnc_router::Coordinate::from(x, y, z_axis_off_cut),
+                        );
+                        self.cnc_router.move_to_optional_coordinate(
+                            &cnc_router::OptionalCoordinate::from_z(Some(
+                                z_axis_off_cut + depth_of_cut,
+                            )),
+                            Some(tool.feed_rate_of_drill),
+                            false,
+                        );
+                    }
+
+                    cut_to = Some(lines_and_curves::Point::from(x, y));
+                } else {
+                    if let Some(p) = cut_to {
+                        self.expect_down(tool, true)?;
+                        self.cnc_router.move_to_optional_coordinate(
+                            &cnc_router::OptionalCoordinate::from(
+                                Some(p.x),
+                                Some(p.y),
+                                None,
+                            ),
+                            Some(tool.feed_rate_of_cut),
+                            false,
+                        );
+                        self.cnc_router.move_to_optional_coordinate(
+                            &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                            None,
+                            false,
+                        );
+                    }
+                    cut_to = None;
+                }
+            }
+
+            if self.is_down(tool.length) {
+                if let Some(p) = cut_to {
+                    self.cnc_router.move_to_optional_coordinate(
+                        &cnc_router::OptionalCoordinate::from(Some(p.x), Some(p.y), None),
+                        Some(tool.feed_rate_of_cut),
+                        false,
+                    );
+                }
+            }
+            self.cnc_router.move_to_optional_coordinate(
+                &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                Some(tool.feed_rate_of_cut),
+                false,
+            );
+        }
+
+        let mut new_rects = Vec::new();
+        new_cuttable_rects.to_bigger_rect_iter();
+        new_rects.extend(new_cuttable_rects);
+        Ok(new_rects)
     }
 
     pub fn cut_broad_rect<
@@ -560,21 +826,34 @@ impl<T: std::io::Write> GCodeCreator<T> {
         tool_index: usize,
         tool: cnc_router::Tool,
         thinnest_radius_seen: f64,
+        change_tool: bool,
+        cut_before: toolpath::Toolpath,
     ) {
         use std::time::Instant;
         let tool_time = Instant::now();
+        self.route_report = ordering::RouteReport::default();
+        self.progress.start_tool(tool_index, &tool.name);
+
+        if change_tool {
+            self.cnc_router.set_tool_and_go_home(
+                tool_index,
+                tool.feed_rate_of_cut,
+                &tool.pre_cut_gcode,
+                tool.force_retouch_off,
+                tool.suggested_length,
+            );
+        } else {
+            self.cnc_router
+                .keep_tool_and_go_home(tool_index, &tool.pre_cut_gcode);
+        }
+        self.start_spindle(&tool);
 
-        self.cnc_router.set_tool_and_go_home(
-            tool_index,
-            tool.feed_rate_of_cut,
-            &tool.pre_cut_gcode,
-            tool.force_retouch_off,
-            tool.suggested_length,
-        );
-        self.cnc_router.set_spindle_on(false, self.spindle_speed);
-        self.cnc_router.turn_fan(true);
-
-        if tool.tool_type().is_broad() {
+        let tool = self.tool_at_depth(&tool);
+        if let cnc_router::ToolType::RestCutBroad(tolerance) = tool.tool_type() {
+            for sign in signs {
+                self.rest_path(do_cut_on_odd, sign, &add_padding_to, &tool, &cut_before, tolerance);
+            }
+        } else if tool.tool_type().is_broad() {
             for sign in signs {
                 let mut sign = sign;
                 let increment = 2.0 * tool.radius * tool.offset;
@@ -601,19 +880,28 @@ impl<T: std::io::Write> GCodeCreator<T> {
                         override_thinest_radius
                     }; // + 8.0 * increment;
 
-                    thick_sign =
-                        thick_sign.expand_lines(bigger_radius, do_cut_on_odd, &add_padding_to);
+                    thick_sign = thick_sign.expand_lines(
+                        bigger_radius,
+                        do_cut_on_odd,
+                        &add_padding_to,
+                        self.logger.as_ref(),
+                    );
                     thick_sign = thick_sign.expand_lines(
                         // tool.radius - bigger_radius - 1.1 * tool.radius * tool.offset,
                         // do_cut_on_odd,
                         -(bigger_radius - tool.radius + shrink_by_radius),
                         do_cut_on_odd,
                         &Vec::new(),
+                        self.logger.as_ref(),
                     );
 
                     if smaller_grow_by_radius != 0.0 {
-                        sign =
-                            sign.expand_lines(smaller_grow_by_radius, do_cut_on_odd, &Vec::new());
+                        sign = sign.expand_lines(
+                            smaller_grow_by_radius,
+                            do_cut_on_odd,
+                            &Vec::new(),
+                            self.logger.as_ref(),
+                        );
                     }
                 }
 
@@ -626,7 +914,12 @@ impl<T: std::io::Write> GCodeCreator<T> {
 
                 self.broad_smart_path2(
                     do_cut_on_odd,
-                    &mut sign.expand_lines(tool.radius, do_cut_on_odd, &add_padding_to),
+                    &mut sign.expand_lines(
+                        tool.radius,
+                        do_cut_on_odd,
+                        &add_padding_to,
+                        self.logger.as_ref(),
+                    ),
                     if let cnc_router::ToolType::SpaceBetweenCutBroad(_, _, _) = tool.tool_type() {
                         Some(&mut thick_sign)
                     } else {
@@ -643,19 +936,38 @@ impl<T: std::io::Write> GCodeCreator<T> {
             }
         } else if tool.tool_type().is_text_or_braille() {
             self.cut_text(do_cut_on_odd, &signs, &add_padding_to, &tool, &mut None);
+        } else if tool.tool_type().is_helical_hole() {
+            self.cut_helical_holes(do_cut_on_odd, &signs, &tool);
+        } else if tool.tool_type().is_chamfer() {
+            self.cut_chamfer(do_cut_on_odd, &signs, &tool);
         }
-        self.cnc_router.set_spindle_off();
-        self.cnc_router.turn_fan(false);
+        self.stop_spindle();
         self.cnc_router.force_flush_gcode();
-
-        eprintln!(
-            "Tool: {}, \tRadius: {}, \tTime: {:?}",
-            tool.name,
-            tool.radius,
-            tool_time.elapsed(),
+        self.progress.report(1.0, 0);
+
+        self.route_reports.push((tool_index, self.route_report));
+        self.logger.info(
+            logging::Target::Planner,
+            format_args!(
+                "Tool: {}, \tRadius: {}, \tTime: {:?}, \t{}",
+                tool.name,
+                tool.radius,
+                tool_time.elapsed(),
+                self.route_report,
+            ),
         );
+        if self.logger.enabled(logging::Level::Info, logging::Target::Planner) {
+            if let Some(stats) = self.stats(&stats::MachineSpeeds::default()) {
+                self.logger.info(
+                    logging::Target::Planner,
+                    format_args!("Tool: {}, \t{}", tool.name, stats.total),
+                );
+            }
+        }
     }
 
+    // Nothing is written if a tool fails, the build is cancelled or moves go
+    // outside of a machine that fails on them.
     pub fn build_gcode_smart_path<
         J: lines_and_curves::Intersection
             + std::fmt::Debug
@@ -669,7 +981,7 @@ impl<T: std::io::Write> GCodeCreator<T> {
         do_cut_on_odd: bool,
         signs: &Vec<sign::Sign<J>>,
         add_padding_to: &Vec<(cnc_router::ShapeType, f64)>,
-    ) {
+    ) -> error::Result<()> {
         use std::time::Instant;
         let begining = Instant::now();
 
@@ -685,43 +997,68 @@ impl<T: std::io::Write> GCodeCreator<T> {
         }
 
         let tools = self.cnc_router.get_tools().clone();
-        let mut handlers = Vec::new();
+        let mut handlers: Vec<(
+            thread::JoinHandle<(
+                Vec<(usize, ordering::RouteReport)>,
+                Option<toolpath::Toolpath>,
+                machine_profile::EnvelopeViolations,
+                error::Result<()>,
+            )>,
+            mpsc::Receiver<String>,
+        )> = Vec::new();
+        // Joined early so rest tools know what was cut before them.
+        let mut finished = Vec::new();
+        let mut cut_before = toolpath::Toolpath::new();
+        let mut thread_failed = false;
+        let has_rest = tools.iter().any(|tool| tool.tool_type().is_rest());
+
+        self.progress.start();
+        let schedule = scheduler::schedule(&tools);
+        self.cnc_router.set_tool_sequence(tool_sequence_of(&tools, &schedule));
 
         let mut thinnest_radius_seen = 10.0;
-        for (tool_index, tool) in tools.iter().enumerate() {
+        let mut previous_tool = None;
+        for tool_index in schedule {
+            // No more tools once one has failed, what is running is still
+            // joined below.
+            if self.progress.is_cancelled() || thread_failed {
+                break;
+            }
+            let tool = &tools[tool_index];
+            let change_tool = previous_tool != Some(tool.index_in_machine);
+            previous_tool = Some(tool.index_in_machine);
             let (tx, rx) = mpsc::channel();
 
+            // Rest tools wait on every tool before them. Otherwise only wait
+            // on the oldest when every thread is busy. Output is still written
+            // in order after all of them.
+            let wait_on = if tool.tool_type().is_rest() {
+                handlers.len()
+            } else {
+                (handlers.len() + 1).saturating_sub(self.thread_count)
+            };
+            for (handle, rx) in handlers.drain(..wait_on) {
+                let joined = handle.join().map_err(error::thread_error);
+                match &joined {
+                    Ok((_, Some(toolpath), _, _)) => cut_before.append(toolpath),
+                    Ok(_) => {},
+                    Err(_) => thread_failed = true,
+                }
+                finished.push((joined, rx));
+            }
+
             {
                 let mut copy_self = self.to_new_write(ThreadWriter { sender: tx });
+                if change_tool {
+                    self.cnc_router.skip_tool_changes(1);
+                }
+                if has_rest && copy_self.cnc_router.get_toolpath().is_none() {
+                    copy_self.cnc_router.record_toolpath(true);
+                }
                 let tool = tool.clone();
                 let add_padding_to = add_padding_to.clone();
                 let signs = signs.clone();
-
-                // Make sure we have empty threads
-                let mut times_looped = 0;
-                loop {
-                    {
-                        let total_threads = TOTAL_THREADS.lock().unwrap();
-                        let mut threads_in_use = THREADS_IN_USE.lock().unwrap();
-
-                        if *threads_in_use < *total_threads {
-                            *threads_in_use += 1;
-                            break;
-                        }
-                    }
-                    times_looped += 1;
-
-                    use rand::prelude::*;
-                    let mut rng = rand::thread_rng();
-                    let r = rng.gen::<f64>();
-                    thread::sleep(std::time::Duration::from_millis(
-                        (250. * r * if times_looped < 3 {
-                            1
-                        } else {
-                            1 << if times_looped > 8 { 8 } else { times_looped }
-                        } as f64) as u64
-                    ));
-                }
+                let cut_before = cut_before.clone();
 
                 let handle = thread::spawn(move || {
                     copy_self.build_gcode_smart_path_helepr(
@@ -731,9 +1068,16 @@ impl<T: std::io::Write> GCodeCreator<T> {
                         tool_index,
                         tool,
                         thinnest_radius_seen,
+                        change_tool,
+                        cut_before,
                     );
-                    let mut threads_in_use = THREADS_IN_USE.lock().unwrap();
-                    *threads_in_use -= 1;
+                    let result = copy_self.cnc_router.finish();
+                    (
+                        copy_self.route_reports,
+                        copy_self.cnc_router.get_toolpath().cloned(),
+                        *copy_self.cnc_router.envelope_violations(),
+                        result,
+                    )
                 });
                 handlers.push((handle, rx));
             }
@@ -744,14 +1088,171 @@ impl<T: std::io::Write> GCodeCreator<T> {
             }
         }
 
+        // Every thread is joined before any error is given back so none are
+        // left running.
         for (handle, rx) in handlers {
-            handle.join().unwrap();
+            finished.push((handle.join().map_err(error::thread_error), rx));
+        }
+        // Nothing is written for a build that was cancelled or failed part way.
+        if self.progress.is_cancelled() {
+            return Err(error::Error::Cancelled);
+        }
+        let mut outputs = Vec::new();
+        for (joined, rx) in finished {
+            let (route_reports, toolpath, violations, result) = joined?;
+            result?;
+            self.cnc_router.add_envelope_violations(&violations);
+            outputs.push((route_reports, toolpath, rx));
+        }
+        self.cnc_router.envelope_result()?;
+        for (route_reports, toolpath, rx) in outputs {
+            self.route_reports.extend(route_reports);
+            if let Some(toolpath) = toolpath {
+                self.cnc_router.append_toolpath(&toolpath);
+            }
             for line in rx {
                 self.cnc_router.write_gcode_string_no_line(line);
             }
         }
 
         // self.cnc_router.reset_program_and_end();
+        self.cnc_router.finish()
+    }
+
+    // Same as build_gcode_smart_path but checks the signs first and does not
+    // write anything if any of them have errors, giving back the report in
+    // Error::Validation. Warnings are logged. Signs
+    // that do not fit in the machine are errors if the machine profile says
+    // to fail on them, otherwise the router warns as it goes.
+    pub fn build_gcode_smart_path_checked<
+        J: lines_and_curves::Intersection
+            + std::fmt::Debug
+            + Clone
+            + cnc_router::CNCPath
+            + Sync
+            + Send
+            + 'static,
+    >(
+        &mut self,
+        do_cut_on_odd: bool,
+        signs: &Vec<sign::Sign<J>>,
+        add_padding_to: &Vec<(cnc_router::ShapeType, f64)>,
+    ) -> error::Result<()> {
+        let mut report = validation::validate(signs);
+        let profile = self.cnc_router.get_machine_profile();
+        if profile.fail_outside_envelope {
+            let radius = self
+                .cnc_router
+                .get_tools()
+                .iter()
+                .map(|tool| tool.radius)
+                .fold(0.0, f64::max);
+            report.issues.extend(validation::validate_machine(signs, profile, radius));
+        }
+        if report.has_errors() {
+            return Err(error::Error::Validation(report));
+        }
+        for issue in &report.issues {
+            self.logger.warn(logging::Target::Planner, format_args!("{}", issue));
+        }
+        self.build_gcode_smart_path(do_cut_on_odd, signs, add_padding_to)
+    }
+
+    // Clears only the material the tools before left behind. What they left
+    // is found by playing their toolpaths back on stock the size of the sign
+    // so tabs, extra_distance_x and skipped pockets are all covered.
+    fn rest_path<
+        J: lines_and_curves::Intersection + std::fmt::Debug + Clone + cnc_router::CNCPath,
+    >(
+        &mut self,
+        do_cut_on_odd: bool,
+        sign: sign::Sign<J>,
+        add_padding_to: &Vec<(cnc_router::ShapeType, f64)>,
+        tool: &cnc_router::Tool,
+        cut_before: &toolpath::Toolpath,
+        tolerance: f64,
+    ) {
+        let increment = 2.0 * tool.radius * tool.offset;
+        let step = increment / 2.0;
+        // Only needs to be thick enough to hold the cut.
+        let mut stock = simulation::Stock::from(
+            sign.bounding_rect().clone(),
+            2.0 * self.cut_depth(),
+            step,
+        );
+        stock.replay(cut_before, self.cnc_router.get_tools());
+        let rest = stock.compare(&sign, do_cut_on_odd, self.cut_depth(), tolerance);
+
+        let mut sign =
+            sign.expand_lines(tool.radius, do_cut_on_odd, add_padding_to, self.logger.as_ref());
+        let bounding_rect = sign.bounding_rect().clone();
+        sign.add_xs_layers(bounding_rect.min_x(), increment);
+        let mut check_sign = sign.clone();
+        let mut fill_rect = range_map::FillRect::from(
+            bounding_rect.min_x(),
+            bounding_rect.min_y(),
+            bounding_rect.max_x(),
+            bounding_rect.max_y(),
+        );
+
+        let mut methods = Box::new(|args: CutBroadSmartPathMethodArguments| match args {
+            CutBroadSmartPathMethodArguments::CanCut(x, y) => {
+                CutBroadSmartPathMethodReturn::CanCut(
+                    rest.has_leftover_near(x, y, tool.radius)
+                        && check_sign.sees_even_odd_lines_before(x, y, do_cut_on_odd, true),
+                )
+            }
+            // Goes along y only as far as there is something left to cut.
+            CutBroadSmartPathMethodArguments::MaxY(x, y) => {
+                let limit = check_sign.get_next_y_value_bounds(x, y);
+                let mut to = y;
+                while to + step < limit && rest.has_leftover_near(x, to + step, tool.radius) {
+                    to += step;
+                }
+                CutBroadSmartPathMethodReturn::MaxY(to)
+            }
+            CutBroadSmartPathMethodArguments::MinY(x, y) => {
+                let limit = check_sign.get_prev_y_value_bounds(x, y);
+                let mut to = y;
+                while to - step > limit && rest.has_leftover_near(x, to - step, tool.radius) {
+                    to -= step;
+                }
+                CutBroadSmartPathMethodReturn::MinY(to)
+            }
+        });
+
+        let mut pockets = Vec::new();
+        for x in float_loop(bounding_rect.min_x(), bounding_rect.max_x(), increment, Vec::new()) {
+            for y in float_loop(bounding_rect.min_y(), bounding_rect.max_y(), increment, Vec::new()) {
+                if methods.as_mut()(CutBroadSmartPathMethodArguments::CanCut(x, y)).can_cut() {
+                    pockets.push(lines_and_curves::Point::from(x, y));
+                }
+            }
+        }
+
+        let (order, report) = ordering::order_points(&self.cnc_router.get_point(), &pockets);
+        self.route_report.add(&report);
+        for (done, index) in order.into_iter().enumerate() {
+            if self.progress.is_cancelled() {
+                return;
+            }
+            self.progress.report_x(
+                pockets[index].x,
+                bounding_rect.min_x(),
+                bounding_rect.max_x(),
+                pockets.len() - done,
+            );
+            self.cut_broad_smart_path2(
+                &mut sign,
+                tool,
+                &mut fill_rect,
+                pockets[index].x,
+                pockets[index].y,
+                increment,
+                true,
+                &mut methods,
+            );
+        }
     }
 
     // MARK: Smart method 2
@@ -875,11 +1376,11 @@ impl<T: std::io::Write> GCodeCreator<T> {
                     // 1 => (x, sign.get_prev_y_value(x, y-0.0001).unwrap_or(min_y(x, y))),
                     0 => (
                         x,
-                        methods(CutBroadSmartPathMethodArguments::MaxY(x, y + 0.000001)).max_y(),
+                        methods(CutBroadSmartPathMethodArguments::MaxY(x, y + 0.000001)).max_y(y),
                     ),
                     1 => (
                         x,
-                        methods(CutBroadSmartPathMethodArguments::MinY(x, y - 0.000001)).min_y(),
+                        methods(CutBroadSmartPathMethodArguments::MinY(x, y - 0.000001)).min_y(y),
                     ),
                     3 => (x + increment, y),
                     4 => (x - increment, y),
@@ -1136,22 +1637,21 @@ impl<T: std::io::Write> GCodeCreator<T> {
         }
 
         if only_cleanup {
-            while !pockets.is_empty() {
-                let mut index = 0;
-                let mut current_point = self.cnc_router.get_point();
-                current_point.x -= 1.0;
-                current_point.y -= 0.5;
-                let mut best_distance = current_point.distance_to(&pockets[0]);
-                for i in 1..pockets.len() {
-                    let distance = current_point.distance_to(&pockets[i]);
-                    if distance < best_distance {
-                        best_distance = distance;
-                        index = i;
-                    }
+            let (order, report) =
+                ordering::order_points(&self.cnc_router.get_point(), &pockets);
+            self.route_report.add(&report);
+            for (done, index) in order.into_iter().enumerate() {
+                if self.progress.is_cancelled() {
+                    return;
                 }
-
                 let x = pockets[index].x;
                 let y = pockets[index].y;
+                self.progress.report_x(
+                    x,
+                    bounding_rect.min_x(),
+                    bounding_rect.max_x(),
+                    pockets.len() - done,
+                );
                 self.cut_broad_smart_path2(
                     // do_cut_on_odd,
                     &mut sign_clone,
@@ -1163,7 +1663,6 @@ impl<T: std::io::Write> GCodeCreator<T> {
                     only_cleanup,
                     &mut methods,
                 );
-                pockets.swap_remove(index);
             }
         } else {
             let mut seen_shapes = vec![std::collections::HashSet::new()];
@@ -1173,9 +1672,18 @@ impl<T: std::io::Write> GCodeCreator<T> {
             } else {
                 false
             };
-            for point in &pockets {
+            for (done, point) in pockets.iter().enumerate() {
+                if self.progress.is_cancelled() {
+                    return;
+                }
                 let x = point.x;
                 let y = point.y;
+                self.progress.report_x(
+                    x,
+                    bounding_rect.min_x(),
+                    bounding_rect.max_x(),
+                    pockets.len() - done,
+                );
                 if should_contour {
                     self.cut_text(
                         do_cut_on_odd,
@@ -1221,17 +1729,47 @@ impl<T: std::io::Write> GCodeCreator<T> {
             let mut sign = if let Some(_) = dont_cut {
                 original_sign.clone()
             } else {
-                original_sign.expand_lines(tool.radius, do_cut_on_odd, add_padding_to)
+                original_sign.expand_lines(
+                    tool.radius,
+                    do_cut_on_odd,
+                    add_padding_to,
+                    self.logger.as_ref(),
+                )
             };
 
             if let cnc_router::ToolType::FullContour(_, shrink_by) = tool.tool_type {
                 if shrink_by != 0.0 {
-                    sign = sign.expand_lines(-shrink_by, do_cut_on_odd, &Vec::new());
+                    sign = sign.expand_lines(
+                        -shrink_by,
+                        do_cut_on_odd,
+                        &Vec::new(),
+                        self.logger.as_ref(),
+                    );
                 }
             }
             let shapes = sign.shapes_cut_inside(do_cut_on_odd);
-            for (shape_index, (shape, cut_inside)) in shapes.iter().enumerate() {
+            let shape_order = if let Some(_) = dont_cut {
+                (0..shapes.len()).collect::<Vec<usize>>()
+            } else {
+                self.order_shapes(&shapes, tool)
+            };
+            let shape_count = shape_order.len();
+            for (done, shape_index) in shape_order.into_iter().enumerate() {
+                if self.progress.is_cancelled() {
+                    return;
+                }
+                let (shape, cut_inside) = &shapes[shape_index];
                 let cut_inside = *cut_inside;
+                if dont_cut.is_none() {
+                    if let Some(bounding_box) = shape.bounding_box() {
+                        self.progress.report_x(
+                            bounding_box.min_x(),
+                            sign.bounding_rect().min_x(),
+                            sign.bounding_rect().max_x(),
+                            shape_count - done,
+                        );
+                    }
+                }
                 if let Some(dont_cut) = &mut dont_cut {
                     if let Some(bounding_box) = shape.bounding_box() {
                         if bounding_box.max_x() > dont_cut.1 {
@@ -1251,6 +1789,11 @@ impl<T: std::io::Write> GCodeCreator<T> {
                 {
                     continue;
                 }
+                let shape = &if let Some(_) = dont_cut {
+                    shape.clone()
+                } else {
+                    self.enter_shape_near_tool(shape)
+                };
 
                 if let Some((bigger_radius, shrink_by_radius)) =
                     if let cnc_router::ToolType::PartialContourRadius(
@@ -1278,11 +1821,13 @@ impl<T: std::io::Write> GCodeCreator<T> {
                             bigger_radius,
                             do_cut_on_odd,
                             add_padding_to,
+                            self.logger.as_ref(),
                         )
                         .expand_lines(
                             -(bigger_radius - tool.radius + shrink_by_radius),
                             do_cut_on_odd,
                             &Vec::new(),
+                            self.logger.as_ref(),
                         );
                     cnc_router::CNCPath::cut_till::<T>(
                         shape.lines(),
@@ -1335,6 +1880,398 @@ impl<T: std::io::Write> GCodeCreator<T> {
         }
     }
 
+    // Mills every hole made of a single circle bigger than the tool by
+    // spiraling down to depth then going around once more at the bottom.
+    fn cut_helical_holes<
+        J: lines_and_curves::Intersection + std::fmt::Debug + Clone + cnc_router::CNCPath,
+    >(
+        &mut self,
+        do_cut_on_odd: bool,
+        signs: &Vec<sign::Sign<J>>,
+        tool: &cnc_router::Tool,
+    ) {
+        let cnc_router::ToolType::HelicalHole(pitch, spring_pass, climb, _) = tool.tool_type() else {
+            return;
+        };
+        let top = self.z_axis_off_cut + tool.length;
+        let bottom = top + self.depth_of_cut;
+        let turns = ((top - bottom).abs() / pitch).ceil().max(1.0);
+        let z_drop = (bottom - top) / turns;
+        // With the spindle turning clockwise (M03) going counter clockwise
+        // inside of a hole is climb milling, with M04 it is the other way.
+        let is_clock_wise = if tool.spindle_counter_clockwise { climb } else { !climb };
+
+        let mut holes = Vec::new();
+        for sign in signs {
+            for (shape, cut_inside) in sign.clone().shapes_cut_inside(do_cut_on_odd) {
+                if !cut_inside || shape.lines().len() != 1 {
+                    continue;
+                }
+                if !shape
+                    .tool_type()
+                    .subset_of(&tool.tool_type().to_shape_type())
+                {
+                    continue;
+                }
+                let Some(circle) = shape.lines()[0].as_circle() else {
+                    continue;
+                };
+                if circle.radius - tool.radius > 0.0 {
+                    holes.push(circle);
+                }
+            }
+        }
+
+        let (order, report) = ordering::order_points(
+            &self.cnc_router.get_point(),
+            &holes.iter().map(|circle| circle.center).collect(),
+        );
+        self.route_report.add(&report);
+        for index in order {
+            let circle = &holes[index];
+            let radius = circle.radius - tool.radius;
+            let center = cnc_router::Coordinate::from(
+                circle.center.x,
+                circle.center.y,
+                bottom,
+            );
+            let start = cnc_router::Coordinate::from(
+                circle.center.x + radius,
+                circle.center.y,
+                top,
+            );
+            self.cnc_router.move_to_coordinate_rapid(&start);
+            for _ in 0..turns as usize {
+                self.cnc_router.helical_interpolation_around_midpoint(
+                    is_clock_wise,
+                    &center,
+                    z_drop,
+                    Some(tool.feed_rate_of_cut),
+                );
+            }
+            self.cnc_router.circular_interpolation_around_midpoint(
+                is_clock_wise,
+                &center,
+            );
+            if spring_pass {
+                self.cnc_router.circular_interpolation_around_midpoint(
+                    is_clock_wise,
+                    &center,
+                );
+            }
+
+            // Step off the wall before pulling out so it does not drag. Only a
+            // little as the middle of a blind hole bigger than the tool is
+            // still there.
+            let step_off = (0.1 * tool.radius).min(radius);
+            self.cnc_router.move_to_coordinate(
+                &cnc_router::Coordinate::from(
+                    circle.center.x + radius - step_off,
+                    circle.center.y,
+                    bottom,
+                ),
+                Some(tool.feed_rate_of_cut),
+                false,
+            );
+            self.cnc_router.move_to_optional_coordinate(
+                &cnc_router::OptionalCoordinate::from_z(Some(top)),
+                Some(tool.feed_rate_of_drill),
+                false,
+            );
+        }
+    }
+
+    // Runs a V-bit with its tip on every edge at the depth that makes the
+    // chamfer the requested width. Corners a cone can not reach are cleaned
+    // out by lifting the tip into the corner along its bisector, up to the
+    // same safe Z helical holes start from.
+    fn cut_chamfer<
+        J: lines_and_curves::Intersection + std::fmt::Debug + Clone + cnc_router::CNCPath,
+    >(
+        &mut self,
+        do_cut_on_odd: bool,
+        signs: &Vec<sign::Sign<J>>,
+        tool: &cnc_router::Tool,
+    ) {
+        let cnc_router::ToolType::ChamferContour(width, _) = tool.tool_type() else {
+            return;
+        };
+        let z_axis_off_cut = self.z_axis_off_cut + tool.length;
+        let surface = tool.length;
+        let top = z_axis_off_cut.max(surface);
+        let bottom = surface - tool.chamfer_depth(width);
+        // Lifting past the surface keeps going out along the same line so the
+        // tip still crosses the surface at the corner.
+        let reach = (top - bottom) / (surface - bottom);
+
+        for sign in signs {
+            for (shape, cut_inside) in sign.clone().shapes_cut_inside(do_cut_on_odd) {
+                if !shape
+                    .tool_type()
+                    .subset_of(&tool.tool_type().to_shape_type())
+                {
+                    continue;
+                }
+
+                if shape.lines().len() == 1 {
+                    if let Some(circle) = shape.lines()[0].as_circle() {
+                        let center = cnc_router::Coordinate::from(
+                            circle.center.x,
+                            circle.center.y,
+                            bottom,
+                        );
+                        self.cnc_router.move_to_coordinate_rapid(
+                            &cnc_router::Coordinate::from(
+                                circle.center.x + circle.radius,
+                                circle.center.y,
+                                z_axis_off_cut,
+                            ),
+                        );
+                        self.cnc_router.move_to_optional_coordinate(
+                            &cnc_router::OptionalCoordinate::from_z(Some(bottom)),
+                            Some(tool.feed_rate_of_drill),
+                            false,
+                        );
+                        self.cnc_router.helical_interpolation_around_midpoint(
+                            !cut_inside,
+                            &center,
+                            0.0,
+                            Some(tool.feed_rate_of_cut),
+                        );
+                        self.cnc_router.move_to_optional_coordinate(
+                            &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                            Some(tool.feed_rate_of_drill),
+                            false,
+                        );
+                        continue;
+                    }
+                }
+
+                let mut points = Vec::new();
+                for p in cnc_router::CNCPath::to_path_vec(shape.lines()) {
+                    let (Some(x), Some(y)) = (p.x, p.y) else {
+                        continue;
+                    };
+                    points.push(lines_and_curves::Point::from(x, y));
+                }
+                if points.len() < 3 {
+                    continue;
+                }
+
+                self.cnc_router.move_to_coordinate_rapid(&cnc_router::Coordinate::from(
+                    points[0].x,
+                    points[0].y,
+                    z_axis_off_cut,
+                ));
+                self.cnc_router.move_to_optional_coordinate(
+                    &cnc_router::OptionalCoordinate::from_z(Some(bottom)),
+                    Some(tool.feed_rate_of_drill),
+                    false,
+                );
+
+                for h in 0..points.len() {
+                    let i = h;
+                    let j = (h + 1) % points.len();
+                    let k = (h + 2) % points.len();
+                    self.cnc_router.move_to_coordinate(
+                        &cnc_router::Coordinate::from(points[j].x, points[j].y, bottom),
+                        Some(tool.feed_rate_of_cut),
+                        false,
+                    );
+
+                    let Some(corner) =
+                        chamfer_corner(&points[i], &points[j], &points[k], width, cut_inside)
+                    else {
+                        continue;
+                    };
+                    let corner = (corner - points[j]) * reach + points[j];
+                    self.cnc_router.move_to_coordinate(
+                        &cnc_router::Coordinate::from(corner.x, corner.y, top),
+                        Some(tool.feed_rate_of_cut),
+                        false,
+                    );
+                    self.cnc_router.move_to_coordinate(
+                        &cnc_router::Coordinate::from(points[j].x, points[j].y, bottom),
+                        Some(tool.feed_rate_of_cut),
+                        false,
+                    );
+                }
+
+                self.cnc_router.move_to_optional_coordinate(
+                    &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                    Some(tool.feed_rate_of_drill),
+                    false,
+                );
+            }
+        }
+    }
+
+    // Carves a heightmap with an optional level by level waterline roughing
+    // pass before raster finishing it with tool_index, usually a ball nose.
+    pub fn carve_relief(
+        &mut self,
+        relief: &relief::Relief,
+        tool_index: usize,
+        roughing: Option<relief::Roughing>,
+    ) -> error::Result<()> {
+        if let Some(roughing) = roughing {
+            if !(roughing.step_down.abs() > 0.0 && roughing.step_down.is_finite()) {
+                return Err(relief::invalid(validation::Problem::BadStep));
+            }
+        }
+        for index in roughing.iter().map(|roughing| roughing.tool_index).chain([tool_index]) {
+            let tool = &self.cnc_router.get_tools()[index];
+            let step_over = self.step_over_of(tool);
+            if !(step_over > 0.0 && step_over.is_finite()) {
+                return Err(relief::invalid(validation::Problem::BadStep));
+            }
+        }
+        self.cnc_router.set_tool_sequence(
+            roughing.iter().map(|roughing| roughing.tool_index).chain([tool_index]).collect()
+        );
+        if let Some(roughing) = roughing {
+            let tool = self.cnc_router.get_tools()[roughing.tool_index].clone();
+            self.start_relief_tool(roughing.tool_index, &tool);
+            let waterline = relief.waterline(
+                &tool,
+                roughing.stock_to_leave,
+                self.step_over_of(&tool),
+            );
+            let step_down = roughing.step_down.abs();
+            // Nothing is cut lower than the bottom of the relief less the stock.
+            let lowest = (-relief.max_depth() + roughing.stock_to_leave).min(0.0);
+            let mut level = 0.0;
+            while level > lowest {
+                level = (level - step_down).max(lowest);
+                self.waterline_pass(&waterline, &tool, level);
+            }
+            self.stop_relief_tool();
+        }
+
+        let tool = self.cnc_router.get_tools()[tool_index].clone();
+        self.start_relief_tool(tool_index, &tool);
+        self.relief_pass(relief, &tool, |x, y| Some(relief.tip_z(&tool, x, y)));
+        self.stop_relief_tool();
+        self.cnc_router.finish()?;
+        self.cnc_router.envelope_result()
+    }
+
+    fn start_relief_tool(&mut self, tool_index: usize, tool: &cnc_router::Tool) {
+        self.cnc_router.set_tool_and_go_home(
+            tool_index,
+            tool.feed_rate_of_cut,
+            &tool.pre_cut_gcode,
+            tool.force_retouch_off,
+            tool.suggested_length,
+        );
+        self.start_spindle(tool);
+    }
+
+    fn stop_relief_tool(&mut self) {
+        self.stop_spindle();
+        self.cnc_router.force_flush_gcode();
+    }
+
+    // Cuts every contour of the waterline at z, going into each one where it
+    // is closest to the tool.
+    fn waterline_pass(&mut self, waterline: &relief::Waterline, tool: &cnc_router::Tool, z: f64) {
+        let z_axis_off_cut = self.z_axis_off_cut + tool.length;
+        let mut paths = waterline.paths(z);
+        while !paths.is_empty() {
+            let position = self.cnc_router.get_point();
+            let mut best = (0, 0, f64::INFINITY);
+            for (i, path) in paths.iter().enumerate() {
+                if let Some(closest) = ordering::closest_point(&position, path) {
+                    let distance = position.distance_to(&path[closest]);
+                    if distance < best.2 {
+                        best = (i, closest, distance);
+                    }
+                }
+            }
+            let mut path = paths.swap_remove(best.0);
+            path.rotate_left(best.1);
+            let start = path[0];
+            self.cnc_router
+                .move_to_coordinate_rapid(&cnc_router::Coordinate::from(start.x, start.y, z_axis_off_cut));
+            self.cnc_router.move_to_optional_coordinate(
+                &cnc_router::OptionalCoordinate::from_z(Some(z + tool.length)),
+                Some(tool.feed_rate_of_drill),
+                false,
+            );
+            for point in path.iter().skip(1).chain([&start]) {
+                self.cnc_router.move_to_coordinate(
+                    &cnc_router::Coordinate::from(point.x, point.y, z + tool.length),
+                    Some(tool.feed_rate_of_cut),
+                    false,
+                );
+            }
+            self.cnc_router.move_to_optional_coordinate(
+                &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                Some(tool.feed_rate_of_drill),
+                false,
+            );
+        }
+    }
+
+    // Follows the raster of the relief going to the z given for each point,
+    // lifting up over every point given None.
+    fn relief_pass(
+        &mut self,
+        relief: &relief::Relief,
+        tool: &cnc_router::Tool,
+        z_of: impl Fn(f64, f64) -> Option<f64>,
+    ) {
+        let z_axis_off_cut = self.z_axis_off_cut + tool.length;
+        let step_over = self.step_over_of(tool);
+        let mut is_up = true;
+        for (x, y, is_down) in relief.raster_path(step_over) {
+            let Some(z) = z_of(x, y) else {
+                if !is_up {
+                    self.cnc_router.move_to_optional_coordinate(
+                        &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                        Some(tool.feed_rate_of_drill),
+                        false,
+                    );
+                    is_up = true;
+                }
+                continue;
+            };
+            let z = z + tool.length;
+
+            if !is_up && !is_down {
+                self.cnc_router.move_to_optional_coordinate(
+                    &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                    Some(tool.feed_rate_of_drill),
+                    false,
+                );
+                is_up = true;
+            }
+            if is_up {
+                self.cnc_router
+                    .move_to_coordinate_rapid(&cnc_router::Coordinate::from(x, y, z_axis_off_cut));
+                self.cnc_router.move_to_optional_coordinate(
+                    &cnc_router::OptionalCoordinate::from_z(Some(z)),
+                    Some(tool.feed_rate_of_drill),
+                    false,
+                );
+                is_up = false;
+            } else {
+                self.cnc_router.move_to_coordinate(
+                    &cnc_router::Coordinate::from(x, y, z),
+                    Some(tool.feed_rate_of_cut),
+                    false,
+                );
+            }
+        }
+        if !is_up {
+            self.cnc_router.move_to_optional_coordinate(
+                &cnc_router::OptionalCoordinate::from_z(Some(z_axis_off_cut)),
+                Some(tool.feed_rate_of_drill),
+                false,
+            );
+        }
+    }
+
     pub fn get_router(&self) -> &cnc_router::CNCRouter<T> {
         &self.cnc_router
     }
@@ -1344,6 +2281,54 @@ impl<T: std::io::Write> GCodeCreator<T> {
     }
 }
 
+// Where the chamfer at points j, coming from i and going to k, reaches on the
+// surface when the corner is one the cone can not get into, None otherwise.
+fn chamfer_corner(
+    i: &lines_and_curves::Point,
+    j: &lines_and_curves::Point,
+    k: &lines_and_curves::Point,
+    width: f64,
+    cut_inside: bool,
+) -> Option<lines_and_curves::Point> {
+    let angle = lines_and_curves::Point::right_angle(i, j, k);
+    let angle = if cut_inside {
+        2.0 * std::f64::consts::PI - angle
+    } else {
+        angle
+    };
+    if angle.is_nan() || angle <= 0.0 || angle >= std::f64::consts::PI {
+        return None;
+    }
+
+    let dji = (*i - *j).normalize();
+    let djk = (*k - *j).normalize();
+    let into_corner = (lines_and_curves::Point::from(0.0, 0.0) - (dji + djk)).normalize();
+    Some(into_corner * (width / (angle / 2.0).sin()) + *j)
+}
+
+// Tools in schedule that are put in the machine, a tool is only changed to
+// when the physical tool before it is a different one.
+fn tool_sequence_of(tools: &Vec<cnc_router::Tool>, schedule: &Vec<usize>) -> Vec<usize> {
+    let mut tool_sequence = Vec::new();
+    let mut previous_tool = None;
+    for tool_index in schedule {
+        if previous_tool != Some(tools[*tool_index].index_in_machine) {
+            tool_sequence.push(*tool_index);
+        }
+        previous_tool = Some(tools[*tool_index].index_in_machine);
+    }
+    tool_sequence
+}
+
+// Tool types build_gcode follows shapes with, in order.
+fn following_tool_types() -> Vec<cnc_router::ToolType> {
+    vec![
+        cnc_router::ToolType::full_text(),
+        cnc_router::ToolType::PartialContourAngle(0.0, 0.0, cnc_router::ShapeType::all()),
+        cnc_router::ToolType::full_braille(),
+    ]
+}
+
 fn float_loop(
     start: f64,
     threshold: f64,
@@ -1372,3 +2357,426 @@ fn float_loop(
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_tools() -> Vec<cnc_router::Tool> {
+        use cnc_router::{test_tool, ToolType};
+        vec![
+            test_tool("Quarter Inch Bit", 1, 0.125, ToolType::FullCutBroad(100.0, false)),
+            test_tool("Eighth Inch Bit", 2, 0.0625, ToolType::SpaceBetweenCutBroad(0.0, 0.0, 0.0)),
+            test_tool("Sixteenth Inch Bit", 3, 0.03125, ToolType::full_text()),
+            test_tool("Braille Bit", 4, 0.03125, ToolType::full_braille()),
+        ]
+    }
+
+    fn test_signs() -> Vec<sign::Sign<lines_and_curves::AllIntersections>> {
+        vec![
+            sign::Sign::from(
+                lines_and_curves::Rectangle::from(
+                    lines_and_curves::Point::from(0.0, 0.0),
+                    lines_and_curves::Point::from(4.0, 3.0),
+                ),
+                vec![
+                    sign::Shape::from(
+                        cnc_router::ShapeType::text(),
+                        lines_and_curves::AllIntersections::from_line_segment(
+                            lines_and_curves::LineSegment::from_points(&vec![
+                                lines_and_curves::Point::from(1.0, 1.0),
+                                lines_and_curves::Point::from(2.0, 1.0),
+                                lines_and_curves::Point::from(2.0, 1.2),
+                                lines_and_curves::Point::from(1.2, 1.2),
+                                lines_and_curves::Point::from(1.2, 2.0),
+                                lines_and_curves::Point::from(1.0, 2.0),
+                            ]),
+                        ),
+                    ),
+                    sign::Shape::from(
+                        cnc_router::ShapeType::braille(),
+                        lines_and_curves::AllIntersections::from_circle(vec![
+                            lines_and_curves::Circle {
+                                center: lines_and_curves::Point::from(3.0, 2.0),
+                                radius: 0.03,
+                            },
+                        ]),
+                    ),
+                ],
+            ),
+        ]
+    }
+
+    fn test_creator() -> GCodeCreator<Vec<u8>> {
+        let router = cnc_router::CNCRouter::from(
+            test_tools(), true, cnc_router::Coordinate::from(0.0, 0.0, 1.0), Vec::new(),
+        );
+        GCodeCreator::from(
+            router, true, false, 12000.0, 60.0, 0.1, -0.05,
+            "012345 (Same Output)", String::new(),
+        )
+    }
+
+    #[test]
+    pub fn test_chamfer_corner() {
+        let p = lines_and_curves::Point::from;
+        // Corner of a square pocket going counter clockwise, the cone can not
+        // get into it so the tip goes out along -(dji + djk).
+        let (i, j, k) = (p(0.0, 0.0), p(1.0, 0.0), p(1.0, 1.0));
+        let corner = chamfer_corner(&i, &j, &k, 0.1, true).unwrap();
+        let into_corner = (p(0.0, 0.0) - ((i - j).normalize() + (k - j).normalize())).normalize();
+        assert!(((corner - j).normalize() - into_corner).distance_to(&p(0.0, 0.0)) < 0.000001);
+        assert!((into_corner.x - 0.5_f64.sqrt()).abs() < 0.000001);
+        assert!((into_corner.y + 0.5_f64.sqrt()).abs() < 0.000001);
+        // A right angle is width / sin(45) from the point.
+        assert!((corner.distance_to(&j) - 0.1 * 2.0_f64.sqrt()).abs() < 0.000001);
+        // Cutting around the outside of the square the cone gets around it.
+        assert_eq!(chamfer_corner(&i, &j, &k, 0.1, false), None);
+    }
+
+    #[test]
+    pub fn test_tool_at_depth() {
+        let mut gc = test_creator();
+        let mut tool = cnc_router::test_tool("Ball", 1, 0.125, cnc_router::ToolType::PartialCutBroad);
+        tool.geometry = tool_geometry::ToolGeometry::BallEndMill;
+        // 0.05 above the top never reaches the material.
+        assert_eq!(gc.cut_depth(), 0.0);
+        assert_eq!(gc.tool_at_depth(&tool), tool);
+
+        gc.depth_of_cut = -0.255;
+        assert!((gc.cut_depth() - 0.155).abs() < 0.000001);
+        assert_eq!(gc.tool_at_depth(&tool).radius, 0.125);
+        assert_eq!(gc.step_over_of(&tool), 0.125);
+        gc.set_max_scallop_height(Some(0.001));
+        let step_over = gc.step_over_of(&tool);
+        assert!(step_over < 0.125);
+        assert!((tool.scallop_height(step_over) - 0.001).abs() < 0.000001);
+        let at_depth = gc.tool_at_depth(&tool);
+        assert!((2.0 * at_depth.radius * at_depth.offset - step_over).abs() < 0.000001);
+
+        // Flat end mills leave no ridge so keep their offset.
+        tool.geometry = tool_geometry::ToolGeometry::FlatEndMill;
+        assert_eq!(gc.step_over_of(&tool), 0.125);
+    }
+
+    #[test]
+    pub fn test_enter_contour_near_tool() {
+        let square = sign::Shape::from(
+            cnc_router::ShapeType::text(),
+            lines_and_curves::AllIntersections::from_line_segment(
+                lines_and_curves::LineSegment::from_points(&vec![
+                    lines_and_curves::Point::from(1.0, 1.0),
+                    lines_and_curves::Point::from(3.0, 1.0),
+                    lines_and_curves::Point::from(3.0, 2.0),
+                    lines_and_curves::Point::from(1.0, 2.0),
+                ]),
+            ),
+        );
+        let tool = test_tools()[2].clone();
+        let mut gc = test_creator();
+        gc.get_router_mut().move_to_coordinate(
+            &cnc_router::Coordinate::from(3.5, 2.5, 1.0), None, false,
+        );
+        gc.get_router_mut().record_toolpath(true);
+
+        let entered = gc.enter_shape_near_tool(&square);
+        cnc_router::CNCPath::cut_till::<Vec<u8>>(
+            entered.lines(), None, None, gc.get_router_mut(), Some(tool.feed_rate_of_cut),
+            true, tool.feed_rate_of_drill, 0.1, -0.05, &tool.tool_type(), tool.radius,
+            tool.offset, true, Box::from(|_, _| true),
+        );
+
+        let toolpath = gc.get_router().get_toolpath().unwrap();
+        let plunge = toolpath.moves().iter().find(|m| m.is_plunge()).unwrap();
+        assert!((plunge.end.x - 3.0).abs() < 1e-6);
+        assert!((plunge.end.y - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    pub fn test_helical_hole() {
+        let hole = vec![
+            sign::Sign::from(
+                lines_and_curves::Rectangle::from(
+                    lines_and_curves::Point::from(0.0, 0.0),
+                    lines_and_curves::Point::from(4.0, 3.0),
+                ),
+                vec![
+                    sign::Shape::from(
+                        cnc_router::ShapeType::braille(),
+                        lines_and_curves::AllIntersections::from_circle(vec![
+                            lines_and_curves::Circle {
+                                center: lines_and_curves::Point::from(2.0, 1.5),
+                                radius: 0.5,
+                            },
+                        ]),
+                    ),
+                ],
+            ),
+        ];
+        let cut = |spindle_counter_clockwise: bool| -> Vec<toolpath::Move> {
+            let mut tool = test_tools()[3].clone();
+            tool.tool_type = cnc_router::ToolType::HelicalHole(
+                0.05, false, true, cnc_router::ShapeType::braille(),
+            );
+            tool.spindle_counter_clockwise = spindle_counter_clockwise;
+            let mut gc = test_creator();
+            gc.get_router_mut().record_toolpath(true);
+            gc.cut_helical_holes(true, &hole, &tool);
+            gc.get_router().get_toolpath().unwrap().moves().clone()
+        };
+
+        let moves = cut(false);
+        let lift = moves.iter().find(|m| m.is_lift()).unwrap();
+        // Lifts just inside the wall, not through the middle of the hole.
+        assert!((lift.start.x - (2.0 + 0.5 - 0.03125 - 0.003125)).abs() < 1e-6);
+        assert!(moves.iter().all(|m| (m.end.x - 2.0).abs() > 0.1 || (m.end.y - 1.5).abs() > 0.1));
+
+        let clockwise = |moves: &Vec<toolpath::Move>| moves.iter().find_map(|m| match m.kind {
+            toolpath::MoveKind::Arc(is_clock_wise, _) => Some(is_clock_wise),
+            _ => None,
+        });
+        // Climb milling with M03 goes counter clockwise, with M04 clockwise.
+        assert_eq!(clockwise(&moves), Some(false));
+        assert_eq!(clockwise(&cut(true)), Some(true));
+    }
+
+    #[test]
+    pub fn test_carve_relief_rejects_bad_steps() {
+        let relief = relief::Relief::from(
+            relief::Heightmap::from(2, 2, vec![0.0, 1.0, 1.0, 0.0]).unwrap(),
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(1.0, 1.0),
+            ),
+            0.25,
+        ).unwrap();
+        let roughing = relief::Roughing {
+            tool_index: 0,
+            step_down: 0.0,
+            stock_to_leave: 0.01,
+        };
+        let mut gc = test_creator();
+        assert!(matches!(
+            gc.carve_relief(&relief, 2, Some(roughing)),
+            Err(error::Error::Validation(_))
+        ));
+        assert!(gc.carve_relief(&relief, 2, None).is_ok());
+        let roughing = relief::Roughing { step_down: 0.1, ..roughing };
+        assert!(test_creator().carve_relief(&relief, 2, Some(roughing)).is_ok());
+    }
+
+    #[test]
+    pub fn test_build_gcode_changes_tools_once() {
+        let mut tools = test_tools();
+        tools.truncate(3);
+        tools[1].tool_type = cnc_router::ToolType::full_text();
+        tools[2].index_in_machine = 2;
+        tools[2].tool_type = cnc_router::ToolType::PartialCutBroad;
+        let router = cnc_router::CNCRouter::from(
+            tools, false, cnc_router::Coordinate::from(0.0, 0.0, 1.0), Vec::new(),
+        );
+        let mut gc = GCodeCreator::from(
+            router, true, false, 12000.0, 60.0, 0.1, -0.05, "012345", String::new(),
+        );
+        let mut signs = test_signs();
+        signs.extend(test_signs());
+        gc.build_gcode(true, bit_path::Path::path_x_then_y, &mut signs).unwrap();
+
+        let gcode = String::from_utf8(gc.get_router().get_gcode_writer().clone()).unwrap();
+        let mut changes: Vec<&str> = gcode.lines()
+            .filter(|line| line.starts_with('T') && line.contains("M6"))
+            .collect();
+        changes.dedup();
+        assert_eq!(changes, vec!["T1 M6", "T2 M6"]);
+    }
+
+    #[test]
+    pub fn test_build_gcode_rest_tool() {
+        // A square the first tool clears with a slot too narrow for it.
+        let signs = vec![
+            sign::Sign::from(
+                lines_and_curves::Rectangle::from(
+                    lines_and_curves::Point::from(0.0, 0.0),
+                    lines_and_curves::Point::from(4.0, 3.0),
+                ),
+                vec![
+                    sign::Shape::from(
+                        cnc_router::ShapeType::text(),
+                        lines_and_curves::AllIntersections::from_line_segment(
+                            lines_and_curves::LineSegment::from_points(&vec![
+                                lines_and_curves::Point::from(1.0, 1.0),
+                                lines_and_curves::Point::from(2.8, 1.0),
+                                lines_and_curves::Point::from(2.8, 1.15),
+                                lines_and_curves::Point::from(2.0, 1.15),
+                                lines_and_curves::Point::from(2.0, 2.0),
+                                lines_and_curves::Point::from(1.0, 2.0),
+                            ]),
+                        ),
+                    ),
+                ],
+            ),
+        ];
+        // 0.055 into the material like main.rs.
+        let build = |tool_type: cnc_router::ToolType, record: bool| -> GCodeCreator<Vec<u8>> {
+            let mut tools = test_tools();
+            tools.truncate(2);
+            tools[1].tool_type = tool_type;
+            let router = cnc_router::CNCRouter::from(
+                tools, false, cnc_router::Coordinate::from(0.0, 0.0, 1.0), Vec::new(),
+            );
+            let mut gc = GCodeCreator::from(
+                router, true, false, 12000.0, 60.0, 0.1, -0.155, "012345", String::new(),
+            );
+            gc.get_router_mut().record_toolpath(record);
+            gc.build_gcode(true, bit_path::Path::path_x_then_y, &mut signs.clone()).unwrap();
+            gc
+        };
+        let second_tool_cuts = |gc: &GCodeCreator<Vec<u8>>| -> Vec<toolpath::Move> {
+            gc.get_router().get_toolpath().unwrap().moves().iter()
+                .filter(|m| m.tool == 2 && m.is_cutting())
+                .cloned()
+                .collect()
+        };
+        // The rest tool needs what was cut before so the toolpath is recorded
+        // without asking.
+        let rest_gc = build(cnc_router::ToolType::RestCutBroad(0.01), false);
+        assert!(build(cnc_router::ToolType::PartialCutBroad, false).get_router().get_toolpath().is_none());
+        let rest = second_tool_cuts(&rest_gc);
+        let full = second_tool_cuts(&build(cnc_router::ToolType::PartialCutBroad, true));
+        assert!(rest.len() < full.len(), "{} < {}", rest.len(), full.len());
+
+        // Play back only the first tool and check the rest tool only goes
+        // down where that left material within its reach.
+        let tools = rest_gc.get_router().get_tools().clone();
+        let mut stock = simulation::Stock::from(
+            signs[0].bounding_rect().clone(),
+            0.2,
+            0.01,
+        );
+        stock.replay(rest_gc.get_router().get_toolpath().unwrap(), &vec![tools[0].clone()]);
+        let depth = rest_gc.cut_depth();
+        assert!((0..stock.rows()).any(|row| {
+            (0..stock.columns()).any(|column| stock.height(column, row) <= -depth + 0.0001)
+        }));
+        let rest_tool = rest_gc.tool_at_depth(&tools[1]);
+        let left_near = |x: f64, y: f64| {
+            let steps = (rest_tool.radius / 0.01).ceil() as i64;
+            (-steps..=steps).any(|i| {
+                (-steps..=steps).any(|j| {
+                    let (dx, dy) = (i as f64 * 0.01, j as f64 * 0.01);
+                    (dx * dx + dy * dy).sqrt() <= rest_tool.radius + 0.01
+                        && stock.height_at(x + dx, y + dy).map_or(false, |h| h > -depth + 0.01)
+                })
+            })
+        };
+        for m in rest.iter().filter(|m| m.end.z - rest_tool.length < 0.0) {
+            assert!(left_near(m.end.x, m.end.y), "{:?}", m.end);
+        }
+    }
+
+    #[test]
+    pub fn test_write_error_is_returned() {
+        struct Broken;
+        impl std::io::Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Broken"))
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let gc = test_creator();
+        let result = gc.to_new_write(Broken)
+            .build_gcode(true, bit_path::Path::path_x_then_y, &mut test_signs());
+        assert!(matches!(result, Err(error::Error::Io(_))));
+        let result = gc.to_new_write(Broken)
+            .build_gcode_smart_path(true, &test_signs(), &Vec::new());
+        assert!(matches!(result, Err(error::Error::Io(_))));
+    }
+
+    #[test]
+    pub fn test_fail_outside_envelope() {
+        // Home is outside so going there for tool changes is too.
+        let mut profile = machine_profile::MachineProfile::default();
+        profile.envelope = Some((
+            cnc_router::Coordinate::from(0.5, 0.5, -1.0),
+            cnc_router::Coordinate::from(2.0, 2.0, 2.0),
+        ));
+        let mut gc = test_creator();
+        gc.get_router_mut().set_machine_profile(profile.clone());
+        gc.build_gcode_smart_path(true, &test_signs(), &Vec::new()).unwrap();
+        assert!(gc.get_router().envelope_violations().count > 0);
+
+        profile.fail_outside_envelope = true;
+        let mut gc = test_creator();
+        gc.get_router_mut().set_machine_profile(profile);
+        let header = gc.get_router().get_gcode_writer().len();
+        let result = gc.build_gcode_smart_path(true, &test_signs(), &Vec::new());
+        assert!(matches!(result, Err(error::Error::OutsideEnvelope(_))));
+        assert_eq!(gc.get_router().get_gcode_writer().len(), header);
+    }
+
+    #[test]
+    pub fn test_same_output_for_any_thread_count() {
+        let signs = test_signs();
+        let gcode = |thread_count: usize| -> Vec<u8> {
+            let mut gc = test_creator();
+            gc.set_thread_count(thread_count);
+            gc.build_gcode_smart_path(true, &signs, &Vec::new()).unwrap();
+            gc.get_router_mut().reset_program_and_end().unwrap();
+            gc.get_router().get_gcode_writer().clone()
+        };
+
+        let one_thread = gcode(1);
+        assert!(one_thread.len() > 0);
+        assert_eq!(one_thread, gcode(4));
+        assert_eq!(one_thread, gcode(4));
+        assert_eq!(one_thread, gcode(1));
+    }
+
+    #[test]
+    pub fn test_progress_and_cancel() {
+        let signs = test_signs();
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut gc = test_creator();
+        let seen_by_callback = seen.clone();
+        gc.set_progress_callback(move |progress| {
+            seen_by_callback.lock().unwrap().push(progress.clone());
+        });
+        gc.build_gcode_smart_path(true, &signs, &Vec::new()).unwrap();
+        let seen = seen.lock().unwrap();
+        for tool_index in 0..test_tools().len() {
+            assert!(seen.iter().any(|p| p.tool_index == tool_index && p.fraction == 0.0));
+            assert!(seen.iter().any(|p| p.tool_index == tool_index && p.fraction == 1.0));
+        }
+        assert!(seen.iter().all(|p| p.fraction >= 0.0 && p.fraction <= 1.0));
+
+        // Cancelled from the callback as the first tool starts.
+        let mut gc = test_creator();
+        let written = gc.get_router().get_gcode_writer().len();
+        let token = progress::CancellationToken::new();
+        gc.set_cancellation_token(token.clone());
+        gc.set_progress_callback(move |_| token.cancel());
+        assert!(matches!(
+            gc.build_gcode_smart_path(true, &signs, &Vec::new()),
+            Err(error::Error::Cancelled)
+        ));
+        assert!(gc.cancellation_token().is_cancelled());
+        assert_eq!(gc.get_router().get_gcode_writer().len(), written);
+
+        // build_gcode does not write part of the program either.
+        let mut gc = test_creator();
+        let written = gc.get_router().get_gcode_writer().len();
+        let token = progress::CancellationToken::new();
+        gc.set_cancellation_token(token.clone());
+        gc.set_progress_callback(move |progress| if progress.tool_index == 1 {
+            token.cancel()
+        });
+        assert!(matches!(
+            gc.build_gcode(true, bit_path::Path::path_x_then_y, &mut test_signs()),
+            Err(error::Error::Cancelled)
+        ));
+        assert_eq!(gc.get_router().get_gcode_writer().len(), written);
+    }
+}
@@ -0,0 +1,367 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+// How CNCRouter lays out each line it writes, for controllers and DNC
+// systems that want line numbers, other precision or checksums. The router
+// makes its lines as always and these are applied to every finished line on
+// the way to the writer. The default changes nothing.
+
+fn space() -> String {
+    String::from(" ")
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FormatOptions {
+    #[serde(default)]
+    pub line_numbers: Option<LineNumbers>,
+    // Decimal places for the words with these letters, like 'X' => 4. Words
+    // that are not in the map keep what the router wrote. G20 and G21 in the
+    // program pick which map is used, mm until one is seen.
+    #[serde(default)]
+    pub inch_decimals: BTreeMap<char, usize>,
+    #[serde(default)]
+    pub mm_decimals: BTreeMap<char, usize>,
+    #[serde(default)]
+    pub suppress_trailing_zeros: bool, // 1.500000 as 1.5 and 2.000000 as 2.
+    #[serde(default = "space")]
+    pub separator: String, // between words, "" packs them together
+    // Comments are dropped from longer lines, it is an error if the line is
+    // still too long.
+    #[serde(default)]
+    pub max_line_length: Option<usize>,
+    // Puts / in front of the lines that can be skipped, like calling up the
+    // next tool, so the block delete switch leaves them out.
+    #[serde(default)]
+    pub block_delete: bool,
+    // Ends lines with *checksum for Marlin and RepRap. Those need a line
+    // number with the checksum so lines are numbered from 1 if line_numbers
+    // is None. They do not read ( ) comments so comments are dropped.
+    #[serde(default)]
+    pub checksum: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineNumbers {
+    pub start: u64,
+    pub increment: u64,
+}
+
+// Applies FormatOptions to text as it is written. Text can come in any
+// size of piece, lines are formatted once their newline comes in.
+#[derive(Clone, Debug)]
+pub struct Formatter {
+    options: FormatOptions,
+    next_line_number: u64,
+    use_inches: bool,
+    pending: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(char, String),
+    Comment(String), // with its ( ) or ;
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            line_numbers: None,
+            inch_decimals: BTreeMap::new(),
+            mm_decimals: BTreeMap::new(),
+            suppress_trailing_zeros: false,
+            separator: space(),
+            max_line_length: None,
+            block_delete: false,
+            checksum: false,
+        }
+    }
+}
+
+impl FormatOptions {
+    // True if lines are written the way the router makes them. block_delete
+    // is left out as the router writes the / itself.
+    pub fn is_plain(&self) -> bool {
+        self.line_numbers.is_none()
+            && self.inch_decimals.is_empty()
+            && self.mm_decimals.is_empty()
+            && !self.suppress_trailing_zeros
+            && self.separator == " "
+            && self.max_line_length.is_none()
+            && !self.checksum
+    }
+}
+
+impl LineNumbers {
+    pub fn from(start: u64, increment: u64) -> Self {
+        Self {
+            start: start,
+            increment: increment,
+        }
+    }
+}
+
+impl Formatter {
+    pub fn from(options: FormatOptions) -> Self {
+        let line_numbers = Self::line_numbers_of(&options);
+        Self {
+            options: options,
+            next_line_number: line_numbers.map(|l| l.start).unwrap_or(0),
+            use_inches: false,
+            pending: String::new(),
+        }
+    }
+
+    fn line_numbers_of(options: &FormatOptions) -> Option<LineNumbers> {
+        match options.line_numbers {
+            Some(line_numbers) => Some(line_numbers),
+            None if options.checksum => Some(LineNumbers::from(1, 1)),
+            None => None,
+        }
+    }
+
+    pub fn get_options(&self) -> &FormatOptions {
+        &self.options
+    }
+
+    // Gives back the formatted lines finished by text.
+    pub fn push(&mut self, text: &str) -> std::io::Result<String> {
+        self.pending += text;
+        let mut out = String::new();
+        while let Some(end) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=end).collect();
+            if let Some(line) = self.format_line(line.trim_end_matches(&['\n', '\r'][..]))? {
+                out += &line;
+                out += "\n";
+            }
+        }
+        Ok(out)
+    }
+
+    // Gives back the last line if it did not end in a newline, ended with
+    // one so more can be written after it.
+    pub fn finish(&mut self) -> std::io::Result<String> {
+        let line = std::mem::take(&mut self.pending);
+        if line.is_empty() {
+            return Ok(line);
+        }
+        Ok(match self.format_line(&line)? {
+            Some(line) => line + "\n",
+            None => String::new(),
+        })
+    }
+
+    // None if the line is left out.
+    fn format_line(&mut self, line: &str) -> std::io::Result<Option<String>> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('%') {
+            return Ok(Some(String::from(line)));
+        }
+        let (block_delete, body) = match trimmed.strip_prefix('/') {
+            Some(body) => ("/", body.trim_start()),
+            None => ("", trimmed),
+        };
+
+        let tokens = Self::tokens(body);
+        if let Some(tokens) = &tokens {
+            for token in tokens {
+                if let Token::Word('G', value) | Token::Word('g', value) = token {
+                    match value.parse::<f64>() {
+                        Ok(v) if v == 20.0 => self.use_inches = true,
+                        Ok(v) if v == 21.0 => self.use_inches = false,
+                        _ => {},
+                    }
+                }
+            }
+        }
+        let has_words = match &tokens {
+            Some(tokens) => tokens.iter().any(|t| matches!(t, Token::Word(..))),
+            None => true,
+        };
+        if !has_words && self.options.checksum {
+            return Ok(None);
+        }
+        // Program numbers and comments do not get line numbers.
+        let is_block = has_words && !matches!(
+            tokens.as_ref().and_then(|t| t.first()),
+            Some(Token::Word('O', _)) | Some(Token::Word('o', _))
+        );
+
+        let line_number = if is_block {
+            Self::line_numbers_of(&self.options).map(|line_numbers| {
+                let n = self.next_line_number;
+                self.next_line_number += line_numbers.increment;
+                n
+            })
+        } else {
+            None
+        };
+
+        let mut formatted = self.build_line(block_delete, line_number, body, &tokens, self.options.checksum);
+        if let Some(max) = self.options.max_line_length {
+            if formatted.len() > max {
+                formatted = self.build_line(block_delete, line_number, body, &tokens, true);
+            }
+            if formatted.len() > max {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Line is longer than {} characters: {}", max, formatted),
+                ));
+            }
+        }
+        Ok(Some(formatted))
+    }
+
+    fn build_line(
+        &self,
+        block_delete: &str,
+        line_number: Option<u64>,
+        body: &str,
+        tokens: &Option<Vec<Token>>,
+        drop_comments: bool,
+    ) -> String {
+        let mut words = Vec::new();
+        if let Some(n) = line_number {
+            words.push(format!("N{}", n));
+        }
+        match tokens {
+            Some(tokens) => {
+                for token in tokens {
+                    match token {
+                        Token::Word(letter, value) => words.push(self.format_word(*letter, value)),
+                        Token::Comment(comment) => if !drop_comments {
+                            words.push(comment.clone())
+                        },
+                    }
+                }
+            },
+            // Not something we can read so it is left as it is.
+            None => words.push(String::from(body)),
+        }
+        let mut line = String::from(block_delete) + &words.join(&self.options.separator);
+        if self.options.checksum {
+            let checksum = line.bytes().fold(0u8, |c, b| c ^ b);
+            line += &format!("*{}", checksum);
+        }
+        line
+    }
+
+    fn format_word(&self, letter: char, value: &str) -> String {
+        // Codes, not amounts.
+        if "GMNOgmno".contains(letter) {
+            return format!("{}{}", letter, value);
+        }
+        let decimals = if self.use_inches {
+            &self.options.inch_decimals
+        } else {
+            &self.options.mm_decimals
+        };
+        let mut value = String::from(value);
+        if let Some(places) = decimals.get(&letter.to_ascii_uppercase()) {
+            if let Ok(v) = value.parse::<f64>() {
+                value = format!("{:.*}", *places, v);
+            }
+        }
+        if self.options.suppress_trailing_zeros && value.contains('.') {
+            value = String::from(value.trim_end_matches('0'));
+        }
+        // -0 after rounding.
+        if value.starts_with('-') && value[1..].chars().all(|c| c == '0' || c == '.') {
+            value.remove(0);
+        }
+        format!("{}{}", letter, value)
+    }
+
+    // None if the line is not all words and comments.
+    fn tokens(body: &str) -> Option<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut chars = body.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            } else if c == '(' {
+                let mut comment = String::from(c);
+                loop {
+                    let c = chars.next()?;
+                    comment.push(c);
+                    if c == ')' {
+                        break;
+                    }
+                }
+                tokens.push(Token::Comment(comment));
+            } else if c == ';' {
+                tokens.push(Token::Comment(String::from(c) + &chars.by_ref().collect::<String>()));
+            } else if c.is_ascii_alphabetic() {
+                let mut value = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_ascii_digit() || d == '.' || d == '-' || d == '+' {
+                        value.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if value.is_empty() {
+                    return None;
+                }
+                tokens.push(Token::Word(c, value));
+            } else {
+                return None;
+            }
+        }
+        Some(tokens)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format(options: FormatOptions, text: &str) -> String {
+        let mut formatter = Formatter::from(options);
+        let mut out = formatter.push(text).unwrap();
+        out += &formatter.finish().unwrap();
+        out
+    }
+
+    #[test]
+    pub fn test_format_options() {
+        let program = "%\nO1000\n(Bit 1 D=0.25)\nG20 (Use inches)\nG01 X1.500000 Y-0.000010 F30.000000\nT5\n";
+
+        assert_eq!(format(FormatOptions::default(), program), program);
+
+        let mut options = FormatOptions::default();
+        options.line_numbers = Some(LineNumbers::from(10, 5));
+        options.inch_decimals.insert('X', 4);
+        options.inch_decimals.insert('Y', 3);
+        options.mm_decimals.insert('X', 1);
+        options.suppress_trailing_zeros = true;
+        assert_eq!(
+            format(options.clone(), program),
+            "%\nO1000\n(Bit 1 D=0.25)\nN10 G20 (Use inches)\nN15 G01 X1.5 Y0. F30.\nN20 T5\n",
+        );
+
+        options.separator = String::new();
+        options.max_line_length = Some(16);
+        assert_eq!(
+            format(options.clone(), "G20 (Use inches)\nG0 X1.23456 Y2"),
+            "N10G20\nN15G0X1.2346Y2.\n",
+        );
+        options.max_line_length = Some(8);
+        assert!(Formatter::from(options).push("G0 X1.23456 Y2\n").is_err());
+    }
+
+    #[test]
+    pub fn test_checksum_and_block_delete() {
+        let mut options = FormatOptions::default();
+        options.checksum = true;
+        // Checksums worked out the same way as Marlin does.
+        assert_eq!(
+            format(options.clone(), "(Start)\nG28 (Home)\nM104 S200\n/T2\n"),
+            "N1 G28*18\nN2 M104 S200*101\n/N3 T2*20\n",
+        );
+        assert!(!options.is_plain());
+        options.checksum = false;
+        options.block_delete = true;
+        assert!(options.is_plain());
+    }
+}
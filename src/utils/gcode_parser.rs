@@ -0,0 +1,499 @@
+use super::*;
+
+// Reads gcode back into a Toolpath. Handles what CNCRouter writes along with
+// the usual LinuxCNC and GRBL extras (N line numbers, * checksums, ; comments,
+// / block delete, # parameter lines and R arcs).
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize, // from 1, 0 if the input is not lines of text
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Rapid,
+    Linear,
+    ClockwiseArc,
+    CounterClockwiseArc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModalState {
+    pub motion: Motion,
+    pub use_inches: bool,
+    pub is_absolute: bool,
+    pub feed_rate: f64,
+    pub spindle_speed: f64,
+    pub tool: usize,
+    pub position: cnc_router::Coordinate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedProgram {
+    pub name: Option<String>,
+    pub toolpath: toolpath::Toolpath,
+    pub state: ModalState,
+}
+
+impl ParseError {
+    pub(crate) fn from(line: usize, message: String) -> Self {
+        Self {
+            line: line,
+            message: message,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}", self.message);
+        }
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ModalState {
+    pub fn from(home: cnc_router::Coordinate) -> Self {
+        Self {
+            motion: Motion::Rapid,
+            use_inches: false,
+            is_absolute: true,
+            feed_rate: 0.0,
+            spindle_speed: 0.0,
+            tool: 0,
+            position: home,
+        }
+    }
+}
+
+// G codes that change a mode we do not track or need to plot.
+const IGNORED_G_CODES: &[f64] = &[
+    9.0, 15.0, 16.0, 17.0, 22.0, 23.0, 25.0, 26.0, 27.0, 40.0, 41.0, 42.0, 44.0, 49.0,
+    50.0, 51.0, 54.0, 55.0, 56.0, 57.0, 58.0, 59.0, 59.1, 59.2, 59.3, 61.0, 61.1, 62.0,
+    64.0, 69.0, 80.0, 90.1, 91.1, 92.1, 94.0, 95.0, 98.0, 99.0, 187.0,
+];
+
+// Splits one line into its letter and number words. Comments, checksums and
+// line numbers are dropped.
+fn words(line: &str, line_number: usize) -> Result<Vec<(char, f64, String)>, ParseError> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '(' {
+            while let Some(c) = chars.next() {
+                if c == ')' {
+                    break;
+                }
+            }
+            continue;
+        }
+        if c == ';' || c == '*' {
+            break;
+        }
+        if c.is_whitespace() {
+            continue;
+        }
+        if !c.is_ascii_alphabetic() {
+            return Err(ParseError::from(line_number, format!("Unexpected '{}'", c)));
+        }
+
+        while let Some(' ') | Some('\t') = chars.peek() {
+            chars.next();
+        }
+        let mut number = String::new();
+        while let Some(d) = chars.peek() {
+            if d.is_ascii_digit() || *d == '.' || *d == '-' || *d == '+' {
+                number.push(*d);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let Ok(value) = number.parse::<f64>() else {
+            return Err(ParseError::from(
+                line_number,
+                format!("'{}' is missing a number", c),
+            ));
+        };
+        words.push((c.to_ascii_uppercase(), value, number));
+    }
+    Ok(words.into_iter().filter(|(letter, _, _)| *letter != 'N').collect())
+}
+
+pub fn parse(text: &str) -> error::Result<ParsedProgram> {
+    parse_from(text, cnc_router::Coordinate::zero())
+}
+
+// home is where the machine starts and where G28 and G30 go to.
+pub fn parse_from(
+    text: &str,
+    home: cnc_router::Coordinate,
+) -> error::Result<ParsedProgram> {
+    let mut state = ModalState::from(home);
+    let mut toolpath = toolpath::Toolpath::new();
+    let mut name = None;
+    let mut loaded_tool = None;
+    let mut has_commands = false;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty()
+            || line.starts_with('%')
+            || line.starts_with('/')
+            || line.starts_with('#')
+        {
+            continue;
+        }
+        if line.starts_with('O') || line.starts_with('o') {
+            name = Some(String::from(&line[1..]));
+            continue;
+        }
+
+        let words = match words(line, line_number) {
+            Ok(words) => words,
+            // The program name CNCRouter writes under % can be anything.
+            Err(_) if !has_commands && name.is_none() => {
+                name = Some(String::from(line));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if words.is_empty() {
+            continue;
+        }
+        has_commands = true;
+
+        let get = |letter: char| {
+            words
+                .iter()
+                .find(|(l, _, _)| *l == letter)
+                .map(|(_, value, _)| *value)
+        };
+
+        let mut non_modal = None;
+        let mut is_machine_coordinates = false;
+        for (letter, value, number) in &words {
+            match letter {
+                'G' => match *value {
+                    v if v == 0.0 => state.motion = Motion::Rapid,
+                    v if v == 1.0 || v == 31.0 || (v > 38.0 && v < 39.0) => {
+                        state.motion = Motion::Linear
+                    }
+                    v if v == 2.0 => state.motion = Motion::ClockwiseArc,
+                    v if v == 3.0 => state.motion = Motion::CounterClockwiseArc,
+                    v if v == 4.0 || v == 28.0 || v == 30.0 || v == 92.0 => non_modal = Some(v),
+                    v if v == 53.0 => is_machine_coordinates = true,
+                    v if v == 65.0 => non_modal = Some(v),
+                    // Their axis words set offsets and are not a move.
+                    v if v == 10.0 || v == 43.0 || v == 43.1 || v == 52.0 => non_modal = Some(v),
+                    v if v == 18.0 || v == 19.0 => {
+                        return Err(ParseError::from(
+                            line_number,
+                            String::from("Only the XY plane (G17) is supported"),
+                        ).into())
+                    }
+                    v if v == 20.0 => state.use_inches = true,
+                    v if v == 21.0 => state.use_inches = false,
+                    v if v == 90.0 => state.is_absolute = true,
+                    v if v == 91.0 => state.is_absolute = false,
+                    v if IGNORED_G_CODES.contains(&v) => {}
+                    _ => {
+                        return Err(ParseError::from(
+                            line_number,
+                            format!("Unsupported G{}", number),
+                        ).into())
+                    }
+                },
+                'M' => {
+                    if *value == 6.0 {
+                        let tool = get('T').map(|t| t as usize).unwrap_or(state.tool);
+                        state.tool = tool;
+                        if loaded_tool != Some(tool) {
+                            loaded_tool = Some(tool);
+                            toolpath.push(toolpath::Move::from(
+                                toolpath::MoveKind::ToolChange,
+                                state.position,
+                                state.position,
+                                state.feed_rate,
+                                tool,
+                            ));
+                        }
+                    }
+                }
+                'T' => {
+                    if get('M') != Some(6.0) {
+                        state.tool = *value as usize;
+                    }
+                }
+                'F' => state.feed_rate = *value,
+                'S' => state.spindle_speed = *value,
+                'X' | 'Y' | 'Z' | 'I' | 'J' | 'K' | 'R' | 'P' | 'Q' | 'H' | 'D' | 'L' | 'E' | 'U'
+                | 'A' | 'B' | 'C' => {}
+                _ => {
+                    return Err(ParseError::from(
+                        line_number,
+                        format!("Unsupported word {}{}", letter, number),
+                    ).into())
+                }
+            }
+        }
+
+        let x = get('X');
+        let y = get('Y');
+        let z = get('Z');
+        let target = |state: &ModalState| {
+            let p = state.position;
+            if state.is_absolute {
+                cnc_router::Coordinate::from(
+                    x.unwrap_or(p.x),
+                    y.unwrap_or(p.y),
+                    z.unwrap_or(p.z),
+                )
+            } else {
+                cnc_router::Coordinate::from(
+                    p.x + x.unwrap_or(0.0),
+                    p.y + y.unwrap_or(0.0),
+                    p.z + z.unwrap_or(0.0),
+                )
+            }
+        };
+
+        if let Some(g) = non_modal {
+            if g == 4.0 {
+                // P without a decimal point is milliseconds like CNCRouter
+                // writes, otherwise seconds.
+                let seconds = if let Some(x) = get('X').or(get('U')) {
+                    x
+                } else if let Some((_, p, number)) = words.iter().find(|(l, _, _)| *l == 'P') {
+                    if number.contains('.') {
+                        *p
+                    } else {
+                        p / 1000.0
+                    }
+                } else {
+                    0.0
+                };
+                toolpath.push(toolpath::Move::from(
+                    toolpath::MoveKind::Dwell(seconds),
+                    state.position,
+                    state.position,
+                    state.feed_rate,
+                    state.tool,
+                ));
+            } else if g == 28.0 || g == 30.0 {
+                // Go through the point given then home.
+                for end in [target(&state), home] {
+                    toolpath.push(toolpath::Move::from(
+                        toolpath::MoveKind::Rapid,
+                        state.position,
+                        end,
+                        state.feed_rate,
+                        state.tool,
+                    ));
+                    state.position = end;
+                }
+            } else if g == 92.0 {
+                state.position = target(&state);
+            } else if g == 10.0 && get('L') == Some(20.0) {
+                // The tool is now at the axes given in the work coordinates.
+                let p = state.position;
+                state.position = cnc_router::Coordinate::from(
+                    x.unwrap_or(p.x),
+                    y.unwrap_or(p.y),
+                    z.unwrap_or(p.z),
+                );
+            }
+            continue;
+        }
+
+        // Machine coordinates are not known relative to the work so skip them.
+        let has_axis = x.is_some() || y.is_some() || z.is_some();
+        if is_machine_coordinates || (!has_axis && !is_arc(&state)) {
+            continue;
+        }
+        let end = target(&state);
+        let kind = match state.motion {
+            Motion::Rapid => toolpath::MoveKind::Rapid,
+            Motion::Linear => toolpath::MoveKind::Feed,
+            Motion::ClockwiseArc | Motion::CounterClockwiseArc => {
+                let is_clock_wise = state.motion == Motion::ClockwiseArc;
+                let center = if let Some(r) = get('R') {
+                    arc_center_from_radius(&state.position, &end, r, is_clock_wise)
+                        .ok_or(ParseError::from(
+                            line_number,
+                            String::from("Arc radius is too small to reach the end"),
+                        ))?
+                } else if get('I').is_some() || get('J').is_some() {
+                    cnc_router::Coordinate::from(
+                        state.position.x + get('I').unwrap_or(0.0),
+                        state.position.y + get('J').unwrap_or(0.0),
+                        state.position.z,
+                    )
+                } else {
+                    return Err(ParseError::from(
+                        line_number,
+                        String::from("Arc is missing I, J or R"),
+                    ).into());
+                };
+                toolpath::MoveKind::Arc(is_clock_wise, center)
+            }
+        };
+        toolpath.push(toolpath::Move::from(
+            kind,
+            state.position,
+            end,
+            state.feed_rate,
+            state.tool,
+        ));
+        state.position = end;
+    }
+
+    Ok(ParsedProgram {
+        name: name,
+        toolpath: toolpath,
+        state: state,
+    })
+}
+
+fn is_arc(state: &ModalState) -> bool {
+    state.motion == Motion::ClockwiseArc || state.motion == Motion::CounterClockwiseArc
+}
+
+// Center of the arc of radius r from start to end. A negative r means the
+// long way around.
+fn arc_center_from_radius(
+    start: &cnc_router::Coordinate,
+    end: &cnc_router::Coordinate,
+    r: f64,
+    is_clock_wise: bool,
+) -> Option<cnc_router::Coordinate> {
+    let dx = end.x - start.x;
+    let dy = end.y - start.y;
+    let chord = (dx * dx + dy * dy).sqrt();
+    if chord == 0.0 || chord > 2.0 * r.abs() + 0.0000001 {
+        return None;
+    }
+    let height = (r * r - chord * chord / 4.0).max(0.0).sqrt();
+    // Left of the chord for counter clockwise short arcs.
+    let side = if is_clock_wise == (r > 0.0) { -1.0 } else { 1.0 };
+    Some(cnc_router::Coordinate::from(
+        start.x + dx / 2.0 - side * height * dy / chord,
+        start.y + dy / 2.0 + side * height * dx / chord,
+        start.z,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_router_output() {
+        let mut router = cnc_router::CNCRouter::from(
+            vec![cnc_router::test_tool("Bit", 4, 0.125, cnc_router::ToolType::full_text())],
+            true,
+            cnc_router::Coordinate::from(0.0, 0.0, 1.0),
+            Vec::new(),
+        );
+        router.record_toolpath(true);
+        router.generate_header(true, "Test Sign", String::from("(extra)"));
+        router.set_tool_and_go_home(0, 60.0, "", false, 0.0);
+        router.move_to_coordinate_rapid(&cnc_router::Coordinate::from(2.0, 0.0, 1.0));
+        router.move_to_coordinate(&cnc_router::Coordinate::from(2.0, 0.0, -0.1), Some(30.0), false);
+        router.circular_interpolation_around_midpoint(
+            true,
+            &cnc_router::Coordinate::from(1.0, 0.0, -0.1),
+        );
+        router.circular_interpolation_around_change_midpoint(false, Some(60.0), -1.0, 0.0);
+        router.dewel(250);
+        router.go_home();
+
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        let program = parse_from(&gcode, cnc_router::Coordinate::from(0.0, 0.0, 1.0)).unwrap();
+        assert_eq!(program.name, Some(String::from("Test Sign")));
+        assert!(program.state.use_inches);
+        assert_eq!(program.state.tool, 4);
+
+        let recorded = router.get_toolpath().unwrap().moves();
+        let parsed = program.toolpath.moves();
+        assert_eq!(parsed.len(), recorded.len());
+        for (p, r) in parsed.iter().zip(recorded.iter()) {
+            assert_eq!(p.kind, r.kind);
+            assert!((p.end - r.end).x.abs() < 0.00001);
+            assert!((p.end - r.end).y.abs() < 0.00001);
+            assert!((p.end - r.end).z.abs() < 0.00001);
+        }
+        // The router counts moves before the first tool change as tool 4 too.
+        assert_eq!(parsed.last().unwrap().tool, 4);
+    }
+
+    #[test]
+    pub fn test_parse_grbl() {
+        let program = parse(
+            "N10 G21 G91 ; relative\nN20 G1 X10 F100*57\n/G1 Y5\n#1=3\nG0 Y5\nG90 G2 X5 Y0 R5\n",
+        )
+        .unwrap();
+        let moves = program.toolpath.moves();
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[0].end, cnc_router::Coordinate::from(10.0, 0.0, 0.0));
+        assert_eq!(moves[0].feed_rate, 100.0);
+        assert_eq!(moves[1].kind, toolpath::MoveKind::Rapid);
+        assert_eq!(moves[1].end, cnc_router::Coordinate::from(10.0, 5.0, 0.0));
+        let toolpath::MoveKind::Arc(true, center) = moves[2].kind else {
+            panic!("Expected a clockwise arc");
+        };
+        assert!((center.x - 5.0).abs() < 0.000001 && (center.y - 5.0).abs() < 0.000001);
+    }
+
+    #[test]
+    pub fn test_parse_errors() {
+        let Err(error::Error::Parse(error)) = parse("G0 X1\nG1 X2\nG7 X3\n") else {
+            panic!("Expected a parse error");
+        };
+        assert_eq!(error.line, 3);
+        let Err(error::Error::Parse(error)) = parse("G0 X1\nG2 X2\n") else {
+            panic!("Expected a parse error");
+        };
+        assert_eq!(error.line, 2);
+        assert_eq!(error.to_string(), "Line 2: Arc is missing I, J or R");
+    }
+
+    #[test]
+    pub fn test_parse_probe_z() {
+        let mut router = cnc_router::CNCRouter::from(
+            vec![cnc_router::test_tool("Bit", 2, 0.125, cnc_router::ToolType::default())],
+            false,
+            cnc_router::Coordinate::from(0.0, 0.0, 1.0),
+            Vec::new(),
+        );
+        let mut profile = machine_profile::MachineProfile::default();
+        profile.has_atc = false;
+        profile.z_probe = Some(machine_profile::ZProbe::from(Some((1.0, 2.0)), 0.5, 2.0, 5.0, 0.25));
+        router.set_machine_profile(profile);
+        router.set_tool_and_go_home(0, 60.0, "", false, 0.0);
+
+        let gcode = String::from_utf8(router.get_gcode_writer().clone()).unwrap();
+        assert!(gcode.contains("G10 L20 P1 Z0.500000"));
+        let program = parse_from(&gcode, cnc_router::Coordinate::from(0.0, 0.0, 1.0)).unwrap();
+        let moves = program.toolpath.moves();
+        // The probe is the only feed, G10 only says where the tool is.
+        let feeds: Vec<&toolpath::Move> = moves
+            .iter()
+            .filter(|m| m.kind == toolpath::MoveKind::Feed)
+            .collect();
+        assert_eq!(feeds.len(), 1);
+        assert_eq!(feeds[0].end.z, feeds[0].start.z - 2.0);
+        let retract = moves.iter().position(|m| m.end.z == 0.75).unwrap();
+        assert_eq!(moves[retract].kind, toolpath::MoveKind::Rapid);
+        assert_eq!(moves[retract].start.z, 0.5);
+
+        let program = parse("G43 H1 Z1\nG52 X5\nG1 X1 F10\n").unwrap();
+        assert_eq!(program.toolpath.moves().len(), 1);
+        assert_eq!(program.toolpath.moves()[0].end, cnc_router::Coordinate::from(1.0, 0.0, 0.0));
+    }
+}
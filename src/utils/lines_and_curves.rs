@@ -106,11 +106,13 @@ pub trait Intersection {
         items: &Vec<Self>,
         radius: f64,
         cut_inside: bool,
+        logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized;
 
     fn remove_touching_shapes(
         shapes: &Vec<(Vec<Self>, bool)>,
         is_growing: bool,
+        logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized;
     // fn lines_below_point(shape: &Vec<Box<Self>>, point: Point) -> usize {
     //     let mut count = 0;
@@ -1510,7 +1512,8 @@ impl LineSegment {
 
     pub fn find_all_polygons(
         points_graph: &Vec<(Point, Vec<usize>)>,
-        valid_points: &Vec<usize>
+        valid_points: &Vec<usize>,
+        logger: &dyn logging::Logger,
     ) -> Vec<Vec<usize>> {
 
         let mut seen = Vec::new();
@@ -1631,7 +1634,10 @@ impl LineSegment {
                     l.1
                 } else {
                     // panic!("Should have had another option.");
-                    eprintln!("Should have had another option. Trying to remove line. E0767");
+                    logger.debug(
+                        logging::Target::Geometry,
+                        format_args!("Should have had another option. Trying to remove line. E0767"),
+                    );
                     seen[prev_index][current_point_index] = 2;
                     seen[current_point_index][prev_index] = 2;
                     continue 'mainloop;
@@ -1696,6 +1702,7 @@ impl LineSegment {
 
     pub fn line_segments_to_polygons(
         lines: &Vec<Self>,
+        logger: &dyn logging::Logger,
     ) -> Vec<Vec<(Self, usize)>> {
         let mut polygons = Vec::new();
 
@@ -1730,7 +1737,11 @@ impl LineSegment {
 
         // Combine each component together
         for component in scc {
-            let new_polygons = LineSegment::find_all_polygons(&graph_points_to_points, &component);
+            let new_polygons = LineSegment::find_all_polygons(
+                &graph_points_to_points,
+                &component,
+                logger,
+            );
 
             for new_poly in new_polygons {
                 let mut lines = Vec::new();
@@ -1751,7 +1762,8 @@ impl LineSegment {
     }
 
     pub fn get_outlines_and_inlines_of_touching_polygons(
-        shapes: &Vec<Vec<Self>>
+        shapes: &Vec<Vec<Self>>,
+        logger: &dyn logging::Logger,
     ) -> Vec<Vec<Self>> {
         let mut lines = Vec::new();
         let mut line_index_to_poly = Vec::new();
@@ -1762,7 +1774,7 @@ impl LineSegment {
             }
         }
 
-        let polygons : Vec<Vec<Self>> = Self::line_segments_to_polygons(&lines)
+        let polygons : Vec<Vec<Self>> = Self::line_segments_to_polygons(&lines, logger)
             .iter()
             .filter(|polygon| {
                 let mut borders = Vec::new();
@@ -2081,7 +2093,10 @@ impl LineSegment {
         return intersection_points;
     }
 
-    pub fn all_intersections_my_version(lines: &Vec<Self>) -> Vec<(Point, usize, usize)> {
+    pub fn all_intersections_my_version(
+        lines: &Vec<Self>,
+        logger: &dyn logging::Logger,
+    ) -> Vec<(Point, usize, usize)> {
 
         use core::cmp::Reverse;
 
@@ -2239,7 +2254,7 @@ impl LineSegment {
                             latest_y: top.point.y,
                         });
                     } else {
-                        eprintln!("Did not find a point");
+                        logger.debug(logging::Target::Geometry, format_args!("Did not find a point"));
                         break;
                     }
                 }
@@ -2333,9 +2348,11 @@ impl LineSegment {
             }
         }
 
+        // Sorted since a HashSet comes out in a different order every run.
         return point_indexes.iter().map(|x| {
             let mut v = Vec::new();
             for index in &x.1 { v.push(*index) }
+            v.sort();
             (x.0, v)
         }).collect();
     }
@@ -2343,11 +2360,12 @@ impl LineSegment {
     pub fn remove_wrong_determinate_polygons(
         lines: &Vec<Self>,
         determinate_is_positive: bool,
+        logger: &dyn logging::Logger,
     ) -> Vec<Self> {
 
         let mut best_index = lines.len();
         let mut best_area = 0.0;
-        let polygons = Self::line_segments_to_polygons(lines);
+        let polygons = Self::line_segments_to_polygons(lines, logger);
         for i in 0..polygons.len() {
             let mut score = 0;
             for j in 0..polygons[i].len() {
@@ -2391,7 +2409,10 @@ impl LineSegment {
         }
     }
 
-    pub fn remove_inner_intersecting_polygons(lines: &Vec<Self>) -> Vec<Self> {
+    pub fn remove_inner_intersecting_polygons(
+        lines: &Vec<Self>,
+        logger: &dyn logging::Logger,
+    ) -> Vec<Self> {
         let graph = LineSegment::all_intersections_combine_points(&lines, 1000.0);
 
         let mut current_index = 0;
@@ -2456,18 +2477,22 @@ impl LineSegment {
                 }
             }
             let Some(next_tuple) = next_tuple else {
-                eprintln!("POINTS: {:?}", points);
-                for (index, (p1, next_point)) in graph.iter().enumerate() {
-                    eprintln!("{}) {:?}:", index, p1);
-                    eprint!("\t[");
-                    for (point_index, _) in next_point {
-                        eprint!("{}, ", point_index);
+                if logger.enabled(logging::Level::Trace, logging::Target::Geometry) {
+                    let mut dump = format!("POINTS: {:?}\n", points);
+                    for (index, (p1, next_point)) in graph.iter().enumerate() {
+                        dump += &format!("{}) {:?}:\n\t[", index, p1);
+                        for (point_index, _) in next_point {
+                            dump += &format!("{}, ", point_index);
+                        }
+                        dump += "]\n";
                     }
-                    eprintln!("]");
+                    logger.trace(logging::Target::Geometry, format_args!("{}", dump));
+                    Self::print_python_code_to_graph(&lines, logger);
                 }
-                Self::print_python_code_to_graph(&lines);
-                eprintln!("Should have found another point. E0768");
-                eprintln!("We will try another way.");
+                logger.debug(
+                    logging::Target::Geometry,
+                    format_args!("Should have found another point. E0768. We will try another way."),
+                );
 
                 // panic!("Should have found another point. E0768");
 
@@ -2559,8 +2584,10 @@ impl LineSegment {
             let Some((iy, ix)) = polygons[i].0
                 .iter()
                 .map(|x| (x.p1.y, x.p1.x))
-                .min_by(|a, b| a.partial_cmp(b).unwrap())
+                .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)))
             else {
+                // Nothing to keep in an empty polygon.
+                polygons.remove(i);
                 continue;
             };
 
@@ -2614,13 +2641,18 @@ impl LineSegment {
         return polygons;
     }
 
-    pub fn print_python_code_to_graph(lines: &Vec<Self>) {
-        eprintln!("import matplotlib.pyplot as plt\n");
+    // Logs a matplotlib script drawing the lines at Trace.
+    pub fn print_python_code_to_graph(lines: &Vec<Self>, logger: &dyn logging::Logger) {
+        if !logger.enabled(logging::Level::Trace, logging::Target::Geometry) {
+            return;
+        }
+        let mut code = String::from("import matplotlib.pyplot as plt\n\n");
         for line in lines {
-            eprintln!("plt.plot([{}, {}], [{}, {}])",
+            code += &format!("plt.plot([{}, {}], [{}, {}])\n",
                 line.p1.x, line.p2.x, line.p1.y, line.p2.y);
         }
-        eprintln!("\nplt.show()");
+        code += "\nplt.show()";
+        logger.trace(logging::Target::Geometry, format_args!("{}", code));
     }
 }
 
@@ -2749,6 +2781,7 @@ impl Intersection for LineSegment {
         items: &Vec<Self>,
         bit_radius: f64,
         cut_inside: bool,
+        _logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         use geo_types::{LineString, Coordinate, Polygon};
         let mut input : Vec<Coordinate<f64>> =
@@ -3125,6 +3158,7 @@ impl Intersection for LineSegment {
         let lines = Self::remove_wrong_determinate_polygons(
             &lines,
             og_area < 0.0,
+            _logger,
         );
         let lines = Self::fix_lines_too_close_to_polygon(&items_no_address, &lines, bit_radius);
         let lines : Vec<Box<Self>> = lines.iter().map(|line| {
@@ -3142,6 +3176,7 @@ impl Intersection for LineSegment {
     fn remove_touching_shapes(
         shapes: &Vec<(Vec<Self>, bool)>,
         is_growing: bool,
+        _logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         if shapes.len() == 0 {
             return Vec::new();
@@ -3261,7 +3296,6 @@ impl Intersection for Rectangle {
     }
     fn y(&self, _next: &Self, x: f64) -> Vec<(f64, bool)> {
         if self.contains_x(x) {
-            eprintln!("RECTANGLE USED");
             vec![
                 (self.min_y(), true),
                 (self.max_y(), false),
@@ -3355,6 +3389,7 @@ impl Intersection for Rectangle {
         items: &Vec<Self>,
         radius: f64,
         cut_inside: bool,
+        _logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         let mut new_rects = Vec::new();
         let cut_inside_factor = if cut_inside {
@@ -3383,6 +3418,7 @@ impl Intersection for Rectangle {
     fn remove_touching_shapes(
         shapes: &Vec<(Vec<Self>, bool)>,
         is_growing: bool,
+        _logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         return shapes.iter().map(|x| {
             x.clone()
@@ -3527,6 +3563,7 @@ impl Intersection for Circle {
         items: &Vec<Self>,
         radius: f64,
         cut_inside: bool,
+        _logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         let mut v = Vec::new();
 
@@ -3547,6 +3584,7 @@ impl Intersection for Circle {
     fn remove_touching_shapes(
         shapes: &Vec<(Vec<Self>, bool)>,
         is_growing: bool,
+        _logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         return shapes.iter().map(|x| {
             x.clone()
@@ -3585,6 +3623,10 @@ impl cnc_router::CNCPath for Circle {
         ))
     }
 
+    fn as_circle(&self) -> Option<Circle> {
+        Some(self.clone())
+    }
+
     fn follow_path<T: std::io::Write>(
         &self,
         cnc_router: &mut cnc_router::CNCRouter<T>,
@@ -3881,6 +3923,7 @@ impl Intersection for AllIntersections {
         items: &Vec<Self>,
         bit_radius: f64,
         cut_inside: bool,
+        logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         if items.len() == 0 {
             return Vec::new();
@@ -3899,6 +3942,7 @@ impl Intersection for AllIntersections {
                     &v,
                     bit_radius,
                     cut_inside,
+                    logger,
                 ).iter().map(|r| {
                     (
                         r.0.iter().map(|r| {
@@ -3920,6 +3964,7 @@ impl Intersection for AllIntersections {
                     &v,
                     bit_radius,
                     cut_inside,
+                    logger,
                 ).iter().map(|l| {
                     (
                         l.0.iter().map(|l| {
@@ -3941,6 +3986,7 @@ impl Intersection for AllIntersections {
                     &v,
                     bit_radius,
                     cut_inside,
+                    logger,
                 ).iter().map(|l| {
                     (
                         l.0.iter().map(|l| {
@@ -3962,6 +4008,7 @@ impl Intersection for AllIntersections {
                     &v,
                     bit_radius,
                     cut_inside,
+                    logger,
                 ).iter().map(|c| {
                     (
                         c.0.iter().map(|c| {
@@ -3977,6 +4024,7 @@ impl Intersection for AllIntersections {
     fn remove_touching_shapes(
         shapes: &Vec<(Vec<Self>, bool)>,
         is_growing: bool,
+        logger: &dyn logging::Logger,
     ) -> Vec<(Vec<Self>, bool)> where Self : Sized {
         let mut line_segment_shapes = Vec::new();
         let mut soft_line_segment_shapes = Vec::new();
@@ -4032,10 +4080,12 @@ impl Intersection for AllIntersections {
         let line_segment_shapes = Intersection::remove_touching_shapes(
             &line_segment_shapes,
             is_growing,
+            logger,
         );
         let soft_line_segment_shapes = Intersection::remove_touching_shapes(
             &soft_line_segment_shapes,
             is_growing,
+            logger,
         );
 
         let mut r = Vec::new();
@@ -4175,6 +4225,15 @@ impl cnc_router::CNCPath for AllIntersections {
         }
     }
 
+    fn as_circle(&self) -> Option<Circle> {
+        match self {
+            AllIntersections::Circle(c) => {
+                c.as_circle()
+            }
+            _ => None,
+        }
+    }
+
     fn follow_path<T: std::io::Write>(
         &self,
         mut cnc_router: &mut cnc_router::CNCRouter<T>,
@@ -5655,7 +5714,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -5697,7 +5759,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -5740,7 +5805,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -5985,7 +6053,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -6044,7 +6115,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -6104,7 +6178,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -6186,7 +6263,10 @@ mod test {
             }
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -6231,7 +6311,10 @@ mod test {
             }
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -6447,7 +6530,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         /*
         compare_output_all_intersections(
@@ -6530,7 +6616,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         /*
         compare_output_all_intersections(
@@ -6749,7 +6838,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         /*
         compare_output_all_intersections(
@@ -6828,7 +6920,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
@@ -6855,7 +6950,10 @@ mod test {
             },
         ];
 
-        LineSegment::print_python_code_to_graph(&lines);
+        LineSegment::print_python_code_to_graph(
+            &lines,
+            &logging::StderrLogger::from(logging::Level::Trace),
+        );
 
         compare_output_all_intersections(
             &LineSegment::all_intersections(&lines),
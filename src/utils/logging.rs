@@ -0,0 +1,159 @@
+// Where camcam's diagnostics go. Nothing in the library prints on its own,
+// GCodeCreator and CNCRouter hold a logger that drops everything until
+// set_logger is called, and hand it to the geometry they run. G-code
+// comments are not logs, CNCRouter::set_gcode_comments turns those on and
+// off.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    Geometry, // lines_and_curves and sign
+    Planner,  // gcode_creator working out the toolpaths
+    Router,   // cnc_router writing them
+}
+
+pub trait Logger: Send + Sync {
+    fn enabled(&self, level: Level, target: Target) -> bool;
+    fn log(&self, level: Level, target: Target, message: &str);
+
+    // Only formats the message if the logger wants it.
+    fn log_args(&self, level: Level, target: Target, message: std::fmt::Arguments) {
+        if self.enabled(level, target) {
+            self.log(level, target, &message.to_string());
+        }
+    }
+
+    fn warn(&self, target: Target, message: std::fmt::Arguments) {
+        self.log_args(Level::Warn, target, message);
+    }
+
+    fn info(&self, target: Target, message: std::fmt::Arguments) {
+        self.log_args(Level::Info, target, message);
+    }
+
+    fn debug(&self, target: Target, message: std::fmt::Arguments) {
+        self.log_args(Level::Debug, target, message);
+    }
+
+    fn trace(&self, target: Target, message: std::fmt::Arguments) {
+        self.log_args(Level::Trace, target, message);
+    }
+}
+
+// Writes everything at max_level or more important to stderr.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StderrLogger {
+    pub max_level: Level,
+}
+
+// Drops everything.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct NoLogger;
+
+impl Level {
+    pub fn description(&self) -> String {
+        match self {
+            Level::Error => String::from("Error"),
+            Level::Warn => String::from("Warning"),
+            Level::Info => String::from("Info"),
+            Level::Debug => String::from("Debug"),
+            Level::Trace => String::from("Trace"),
+        }
+    }
+}
+
+impl Target {
+    pub fn description(&self) -> String {
+        match self {
+            Target::Geometry => String::from("geometry"),
+            Target::Planner => String::from("planner"),
+            Target::Router => String::from("router"),
+        }
+    }
+}
+
+impl StderrLogger {
+    pub fn from(max_level: Level) -> Self {
+        Self {
+            max_level: max_level,
+        }
+    }
+}
+
+impl Logger for StderrLogger {
+    fn enabled(&self, level: Level, _target: Target) -> bool {
+        level <= self.max_level
+    }
+
+    fn log(&self, level: Level, target: Target, message: &str) {
+        eprintln!("{} [{}]: {}", level.description(), target.description(), message);
+    }
+}
+
+impl Logger for NoLogger {
+    fn enabled(&self, _level: Level, _target: Target) -> bool {
+        false
+    }
+
+    fn log(&self, _level: Level, _target: Target, _message: &str) {}
+}
+
+// Keeps every message at max_level or more important for tests to check.
+#[cfg(test)]
+pub struct TestLogger {
+    pub max_level: Level,
+    pub messages: std::sync::Mutex<Vec<(Level, Target, String)>>,
+}
+
+#[cfg(test)]
+impl TestLogger {
+    pub fn from(max_level: Level) -> Self {
+        Self {
+            max_level: max_level,
+            messages: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Logger for TestLogger {
+    fn enabled(&self, level: Level, _target: Target) -> bool {
+        level <= self.max_level
+    }
+
+    fn log(&self, level: Level, target: Target, message: &str) {
+        self.messages.lock().unwrap().push((level, target, String::from(message)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_logger() {
+        let logger = TestLogger::from(Level::Info);
+        logger.warn(Target::Router, format_args!("Move to X{} is outside", 5));
+        logger.debug(Target::Router, format_args!("Not wanted"));
+        logger.info(Target::Planner, format_args!("Tool: {}", "Bit"));
+        assert!(logger.enabled(Level::Error, Target::Geometry));
+        assert!(!NoLogger.enabled(Level::Error, Target::Planner));
+
+        let messages = logger.messages.lock().unwrap();
+        assert_eq!(
+            *messages,
+            vec![
+                (Level::Warn, Target::Router, String::from("Move to X5 is outside")),
+                (Level::Info, Target::Planner, String::from("Tool: Bit")),
+            ],
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use super::*;
+use serde::{Serialize, Deserialize};
+
+// What the machine running the program can do and where it can go.
+// CNCRouter checks every move against it, keeps feeds and spindle speeds in
+// range and uses it to park and change tools. The default is the machine
+// camcam was written for.
+
+fn default_park_position() -> cnc_router::Coordinate {
+    cnc_router::Coordinate::from(4.3125, 0.0, 0.0)
+}
+
+fn default_arc_tolerance() -> f64 {
+    0.001
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MachineProfile {
+    #[serde(default)]
+    pub name: String,
+    // Smallest and biggest corner the program may move to, in the same
+    // coordinates as the program. None means anywhere.
+    #[serde(default)]
+    pub envelope: Option<(cnc_router::Coordinate, cnc_router::Coordinate)>,
+    #[serde(default)]
+    pub fail_outside_envelope: bool, // else only warns
+    #[serde(default)]
+    pub max_feed_rate: Option<f64>,
+    #[serde(default)]
+    pub min_rpm: Option<f64>,
+    #[serde(default)]
+    pub max_rpm: Option<f64>,
+    #[serde(default)]
+    pub home_position: Option<cnc_router::Coordinate>, // None uses the one given to CNCRouter
+    #[serde(default = "default_park_position")]
+    pub park_position: cnc_router::Coordinate, // machine coordinates (G53)
+    #[serde(default)]
+    pub tool_change_position: Option<cnc_router::Coordinate>, // machine coordinates (G53)
+    #[serde(default = "yes")]
+    pub has_atc: bool,
+    #[serde(default = "yes")]
+    pub has_tool_setter: bool,
+    // Calls up the next tool with a T word after every tool change so a
+    // carousel can turn while the current tool cuts.
+    #[serde(default = "yes")]
+    pub pre_stage_next_tool: bool,
+    #[serde(default = "yes")]
+    pub supports_arcs: bool,
+    #[serde(default = "default_arc_tolerance")]
+    pub arc_tolerance: f64, // how far lines can be from an arc they replace
+    // Without an ATC, zeros Z on a touch plate after every manual tool
+    // change. None leaves zeroing to whoever changes the tool.
+    #[serde(default)]
+    pub z_probe: Option<ZProbe>,
+}
+
+// A touch plate sitting on top of the work.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZProbe {
+    #[serde(default)]
+    pub position: Option<(f64, f64)>, // X Y over the plate, None probes where the tool is
+    pub plate_thickness: f64,
+    pub max_travel: f64, // how far down to look for the plate
+    pub feed_rate: f64,
+    pub retract: f64,    // above the plate once it is found
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct EnvelopeViolations {
+    pub count: usize,
+    pub first: Option<cnc_router::Coordinate>,
+}
+
+impl Default for MachineProfile {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            envelope: None,
+            fail_outside_envelope: false,
+            max_feed_rate: None,
+            min_rpm: None,
+            max_rpm: None,
+            home_position: None,
+            park_position: default_park_position(),
+            tool_change_position: None,
+            has_atc: true,
+            has_tool_setter: true,
+            pre_stage_next_tool: true,
+            supports_arcs: true,
+            arc_tolerance: default_arc_tolerance(),
+            z_probe: None,
+        }
+    }
+}
+
+impl MachineProfile {
+    pub fn contains(&self, pos: &cnc_router::Coordinate) -> bool {
+        let Some((min, max)) = &self.envelope else {
+            return true;
+        };
+        pos.x >= min.x && pos.x <= max.x
+            && pos.y >= min.y && pos.y <= max.y
+            && pos.z >= min.z && pos.z <= max.z
+    }
+
+    pub fn clamp_feed_rate(&self, feed_rate: f64) -> f64 {
+        if let Some(max) = self.max_feed_rate {
+            feed_rate.min(max)
+        } else {
+            feed_rate
+        }
+    }
+
+    pub fn clamp_rpm(&self, rpm: f64) -> f64 {
+        let rpm = if let Some(max) = self.max_rpm {
+            rpm.min(max)
+        } else {
+            rpm
+        };
+        if let Some(min) = self.min_rpm {
+            rpm.max(min)
+        } else {
+            rpm
+        }
+    }
+
+    // Longest piece of an arc of radius that a line can stand in for while
+    // staying within arc_tolerance of it.
+    pub fn arc_step(&self, radius: f64) -> f64 {
+        if radius <= self.arc_tolerance {
+            return radius.max(0.0000001);
+        }
+        2.0 * radius * (1.0 - self.arc_tolerance / radius).acos()
+    }
+
+    // Limits for feeds_speeds. Anything the profile does not limit is left
+    // as the default's.
+    pub fn feed_limits(&self) -> feeds_speeds::FeedLimits {
+        let mut limits = feeds_speeds::FeedLimits::default();
+        if let Some(min) = self.min_rpm {
+            limits.min_rpm = min;
+        }
+        if let Some(max) = self.max_rpm {
+            limits.max_rpm = max;
+        }
+        if let Some(max) = self.max_feed_rate {
+            limits.max_feed_rate = max;
+            limits.max_plunge_rate = limits.max_plunge_rate.min(max);
+        }
+        limits
+    }
+}
+
+impl ZProbe {
+    pub fn from(
+        position: Option<(f64, f64)>,
+        plate_thickness: f64,
+        max_travel: f64,
+        feed_rate: f64,
+        retract: f64,
+    ) -> Self {
+        Self {
+            position: position,
+            plate_thickness: plate_thickness,
+            max_travel: max_travel,
+            feed_rate: feed_rate,
+            retract: retract,
+        }
+    }
+}
+
+impl EnvelopeViolations {
+    pub fn add(&mut self, pos: cnc_router::Coordinate) {
+        self.count += 1;
+        if self.first.is_none() {
+            self.first = Some(pos);
+        }
+    }
+
+    pub fn join(&mut self, other: &EnvelopeViolations) {
+        self.count += other.count;
+        if self.first.is_none() {
+            self.first = other.first;
+        }
+    }
+}
+
+impl std::fmt::Display for EnvelopeViolations {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} moves outside of the machine", self.count)?;
+        if let Some(first) = self.first {
+            write!(f, ", first to X{} Y{} Z{}", first.x, first.y, first.z)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_machine_profile() {
+        let mut profile = MachineProfile::default();
+        assert!(profile.contains(&cnc_router::Coordinate::from(1000.0, -5.0, 2.0)));
+        assert_eq!(profile.clamp_rpm(30000.0), 30000.0);
+
+        profile.envelope = Some((
+            cnc_router::Coordinate::from(0.0, 0.0, -1.0),
+            cnc_router::Coordinate::from(24.0, 12.0, 4.0),
+        ));
+        profile.min_rpm = Some(8000.0);
+        profile.max_rpm = Some(18000.0);
+        profile.max_feed_rate = Some(200.0);
+        assert!(profile.contains(&cnc_router::Coordinate::from(24.0, 0.0, 0.0)));
+        assert!(!profile.contains(&cnc_router::Coordinate::from(24.1, 0.0, 0.0)));
+        assert!(!profile.contains(&cnc_router::Coordinate::from(1.0, 1.0, -1.5)));
+        assert_eq!(profile.clamp_rpm(30000.0), 18000.0);
+        assert_eq!(profile.clamp_rpm(100.0), 8000.0);
+        assert_eq!(profile.clamp_feed_rate(250.0), 200.0);
+        assert_eq!(profile.feed_limits().max_feed_rate, 200.0);
+
+        // The middle of an arc_step long piece of the arc is arc_tolerance
+        // from the line across it.
+        let step = profile.arc_step(1.0);
+        assert!((1.0 - (step / 2.0).cos() - 0.001).abs() < 0.0000001);
+    }
+}
@@ -0,0 +1,189 @@
+use super::*;
+
+// Orders places the tool has to visit (shapes, holes, pockets) to cut down on
+// rapid moves between them. Starts with nearest neighbor then improves it
+// with 2-opt.
+
+// More points than this only get nearest neighbor since 2-opt is n^2 a pass.
+const MAX_POINTS_FOR_TWO_OPT: usize = 1000;
+const MAX_TWO_OPT_PASSES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RouteReport {
+    pub before: f64,
+    pub after: f64,
+}
+
+impl RouteReport {
+    pub fn add(&mut self, other: &RouteReport) {
+        self.before += other.before;
+        self.after += other.after;
+    }
+}
+
+impl std::fmt::Display for RouteReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Rapids: {:.3} -> {:.3}", self.before, self.after)
+    }
+}
+
+// Distance traveled going from start to every point in order.
+pub fn rapid_distance(
+    start: &lines_and_curves::Point,
+    points: &Vec<lines_and_curves::Point>,
+    order: &Vec<usize>,
+) -> f64 {
+    let mut distance = 0.0;
+    let mut current = *start;
+    for i in order {
+        distance += current.distance_to(&points[*i]);
+        current = points[*i];
+    }
+    distance
+}
+
+pub fn nearest_neighbor(
+    start: &lines_and_curves::Point,
+    points: &Vec<lines_and_curves::Point>,
+) -> Vec<usize> {
+    let mut order = Vec::with_capacity(points.len());
+    let mut used = vec![false; points.len()];
+    let mut current = *start;
+    for _ in 0..points.len() {
+        let mut best = None;
+        let mut best_distance = f64::INFINITY;
+        for i in 0..points.len() {
+            if used[i] {
+                continue;
+            }
+            let distance = current.distance_to(&points[i]);
+            if distance < best_distance {
+                best_distance = distance;
+                best = Some(i);
+            }
+        }
+        let Some(best) = best else {
+            break;
+        };
+        used[best] = true;
+        current = points[best];
+        order.push(best);
+    }
+    order
+}
+
+// Reverses parts of the route while that makes it shorter. The route is open
+// so the last point does not go back to start.
+pub fn two_opt(
+    start: &lines_and_curves::Point,
+    points: &Vec<lines_and_curves::Point>,
+    order: &mut Vec<usize>,
+) {
+    let n = order.len();
+    if n < 3 || n > MAX_POINTS_FOR_TWO_OPT {
+        return;
+    }
+    let point_at = |order: &Vec<usize>, i: usize| points[order[i]];
+
+    for _ in 0..MAX_TWO_OPT_PASSES {
+        let mut improved = false;
+        for i in 0..n - 1 {
+            let before_i = if i == 0 { *start } else { point_at(order, i - 1) };
+            for k in (i + 1)..n {
+                let removed = before_i.distance_to(&point_at(order, i))
+                    + if k + 1 < n {
+                        point_at(order, k).distance_to(&point_at(order, k + 1))
+                    } else {
+                        0.0
+                    };
+                let added = before_i.distance_to(&point_at(order, k))
+                    + if k + 1 < n {
+                        point_at(order, i).distance_to(&point_at(order, k + 1))
+                    } else {
+                        0.0
+                    };
+                if added + 0.0000001 < removed {
+                    order[i..=k].reverse();
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+// Best order to visit points starting at start along with how much shorter
+// the rapids are than going in the order given.
+pub fn order_points(
+    start: &lines_and_curves::Point,
+    points: &Vec<lines_and_curves::Point>,
+) -> (Vec<usize>, RouteReport) {
+    let original = (0..points.len()).collect::<Vec<usize>>();
+    let mut order = nearest_neighbor(start, points);
+    two_opt(start, points, &mut order);
+
+    let before = rapid_distance(start, points, &original);
+    let after = rapid_distance(start, points, &order);
+    if after > before {
+        return (original, RouteReport { before: before, after: before });
+    }
+    (order, RouteReport { before: before, after: after })
+}
+
+// Index of the point closest to position. Used to start a closed contour
+// where the tool already is.
+pub fn closest_point(
+    position: &lines_and_curves::Point,
+    points: &Vec<lines_and_curves::Point>,
+) -> Option<usize> {
+    let mut best = None;
+    let mut best_distance = f64::INFINITY;
+    for (i, point) in points.iter().enumerate() {
+        let distance = position.distance_to(point);
+        if distance < best_distance {
+            best_distance = distance;
+            best = Some(i);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_order_points() {
+        let points = vec![
+            lines_and_curves::Point::from(3.0, 0.0),
+            lines_and_curves::Point::from(1.0, 0.0),
+            lines_and_curves::Point::from(4.0, 0.0),
+            lines_and_curves::Point::from(2.0, 0.0),
+        ];
+        let start = lines_and_curves::Point::from(0.0, 0.0);
+        let (order, report) = order_points(&start, &points);
+        assert_eq!(order, vec![1, 3, 0, 2]);
+        assert_eq!(report.before, 3.0 + 2.0 + 3.0 + 2.0);
+        assert_eq!(report.after, 4.0);
+    }
+
+    #[test]
+    pub fn test_two_opt_removes_crossing() {
+        // Nearest neighbor goes right first then left then all the way right.
+        let points = vec![
+            lines_and_curves::Point::from(1.0, 0.0),
+            lines_and_curves::Point::from(-1.5, 0.0),
+            lines_and_curves::Point::from(-3.0, 0.0),
+            lines_and_curves::Point::from(4.0, 0.0),
+        ];
+        let start = lines_and_curves::Point::from(0.0, 0.0);
+        let mut order = nearest_neighbor(&start, &points);
+        assert_eq!(order, vec![0, 1, 2, 3]);
+        let greedy = rapid_distance(&start, &points, &order);
+        two_opt(&start, &points, &mut order);
+        assert!(rapid_distance(&start, &points, &order) < greedy);
+        assert_eq!(order, vec![2, 1, 0, 3]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Lets a UI follow along while GCodeCreator works and stop it part way.
+// The callback is called from the threads each tool is made on so it has to
+// be Send and Sync, send it down a channel to get it back on one thread.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub tool_index: usize,
+    pub tool_name: String,
+    pub fraction: f64, // how far across the bounding rect in x the tool is, 0 to 1
+    pub pockets_remaining: usize,
+    pub elapsed: Duration, // since the build started
+}
+
+// Cancels every copy of itself. Checked between pockets and tools so a
+// build stops soon after, returning error::Error::Cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Reporter {
+    callback: Option<ProgressCallback>,
+    cancellation_token: CancellationToken,
+    started: Instant,
+    tool_index: usize,
+    tool_name: String,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Self {
+            callback: None,
+            cancellation_token: CancellationToken::new(),
+            started: Instant::now(),
+            tool_index: 0,
+            tool_name: String::new(),
+        }
+    }
+}
+
+impl Reporter {
+    pub fn set_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.callback = callback;
+    }
+
+    pub fn set_cancellation_token(&mut self, cancellation_token: CancellationToken) {
+        self.cancellation_token = cancellation_token;
+    }
+
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation_token
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation_token.is_cancelled()
+    }
+
+    // Elapsed time is counted from here.
+    pub fn start(&mut self) {
+        self.started = Instant::now();
+    }
+
+    pub fn start_tool(&mut self, tool_index: usize, tool_name: &str) {
+        self.tool_index = tool_index;
+        self.tool_name = String::from(tool_name);
+        self.report(0.0, 0);
+    }
+
+    pub fn report(&self, fraction: f64, pockets_remaining: usize) {
+        if let Some(callback) = &self.callback {
+            callback(&Progress {
+                tool_index: self.tool_index,
+                tool_name: self.tool_name.clone(),
+                fraction: if fraction.is_finite() { fraction.clamp(0.0, 1.0) } else { 0.0 },
+                pockets_remaining: pockets_remaining,
+                elapsed: self.started.elapsed(),
+            });
+        }
+    }
+
+    // Reports how far x is across min_x to max_x.
+    pub fn report_x(&self, x: f64, min_x: f64, max_x: f64, pockets_remaining: usize) {
+        let width = max_x - min_x;
+        self.report(if width > 0.0 { (x - min_x) / width } else { 1.0 }, pockets_remaining);
+    }
+}
@@ -0,0 +1,597 @@
+use super::*;
+
+// Grayscale image where 1.0 is the top of the material and 0.0 is the
+// deepest part of the carving. Row 0 is the top of the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+// Roughs the relief one step_down at a time following the contour of what
+// can be cut at each level and stepping in from it till the level is clear.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Roughing {
+    pub tool_index: usize,
+    pub step_down: f64,
+    pub stock_to_leave: f64,
+}
+
+// Heightmap stretched over a rectangle of the sign.
+#[derive(Debug, Clone)]
+pub struct Relief {
+    heightmap: Heightmap,
+    rect: lines_and_curves::Rectangle,
+    max_depth: f64,
+}
+
+// Lowest the tip of a roughing tool can go, leaving the stock, on a grid over
+// the relief. Found once and used for the contours of every level.
+#[derive(Debug, Clone)]
+pub struct Waterline {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+    spacing: f64,
+    step_over: f64,
+    width: usize,
+    height: usize,
+    tip_z: Vec<f64>,
+}
+
+// A relief is carved on its own and not in a sign so problems with one are
+// given as sign 0.
+pub(crate) fn invalid(problem: validation::Problem) -> error::Error {
+    error::Error::Validation(validation::ValidationReport {
+        issues: vec![validation::Issue::from(problem, 0, None, None)],
+    })
+}
+
+// Input that is not a graymap or image. line is 0 for binary input.
+fn malformed(line: usize, message: &str) -> error::Error {
+    error::Error::Parse(gcode_parser::ParseError::from(line, String::from(message)))
+}
+
+// Line of the text that index is in, from 1.
+fn line_at(bytes: &[u8], index: usize) -> usize {
+    1 + bytes[..index.min(bytes.len())].iter().filter(|b| **b == b'\n').count()
+}
+
+impl Heightmap {
+    pub fn from(width: usize, height: usize, values: Vec<f32>) -> error::Result<Self> {
+        if width == 0 || height == 0 || values.len() != width * height {
+            return Err(invalid(validation::Problem::BadHeightmap));
+        }
+        Ok(Self {
+            width: width,
+            height: height,
+            values: values.iter().map(|v| v.clamp(0.0, 1.0)).collect(),
+        })
+    }
+
+    // Reads a P2 (ascii) or P5 (binary) portable graymap.
+    pub fn from_pgm(bytes: &[u8]) -> error::Result<Self> {
+        let mut index = 0;
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+                index += 1;
+            }
+            if index < bytes.len() && bytes[index] == b'#' {
+                while index < bytes.len() && bytes[index] != b'\n' {
+                    index += 1;
+                }
+                continue;
+            }
+            let start = index;
+            while index < bytes.len() && !bytes[index].is_ascii_whitespace() {
+                index += 1;
+            }
+            if start == index {
+                return Err(malformed(line_at(bytes, index), "PGM header is cut short."));
+            }
+            header.push((String::from_utf8_lossy(&bytes[start..index]).to_string(), start));
+        }
+
+        let parse = |(s, start): &(String, usize)| {
+            s.parse::<usize>()
+                .map_err(|_| malformed(line_at(bytes, *start), "PGM has a bad number."))
+        };
+        let width = parse(&header[1])?;
+        let height = parse(&header[2])?;
+        let max_value = parse(&header[3])?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(malformed(line_at(bytes, header[3].1), "PGM max value out of range."));
+        }
+
+        let mut values = Vec::with_capacity(width * height);
+        if header[0].0 == "P2" {
+            let text = String::from_utf8_lossy(&bytes[index..]);
+            let mut line = line_at(bytes, index);
+            for text_line in text.split('\n') {
+                for word in text_line.split_ascii_whitespace() {
+                    values.push(parse(&(String::from(word), 0)).map_err(|_| {
+                        malformed(line, "PGM has a bad number.")
+                    })? as f32 / max_value as f32);
+                }
+                line += 1;
+            }
+        } else if header[0].0 == "P5" {
+            // Exactly one whitespace byte after the max value.
+            let data = &bytes[(index + 1).min(bytes.len())..];
+            if max_value < 256 {
+                for b in data {
+                    values.push(*b as f32 / max_value as f32);
+                }
+            } else {
+                for b in data.chunks_exact(2) {
+                    values.push(((b[0] as usize) << 8 | b[1] as usize) as f32 / max_value as f32);
+                }
+            }
+        } else {
+            return Err(malformed(1, "Only P2 and P5 graymaps are supported."));
+        }
+        if values.len() < width * height {
+            return Err(malformed(0, "PGM has fewer values than its width times height."));
+        }
+        values.truncate(width * height);
+
+        Self::from(width, height, values)
+    }
+
+    #[cfg(feature = "png")]
+    pub fn from_png<R: std::io::Read>(reader: R) -> error::Result<Self> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| malformed(0, &e.to_string()))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|e| malformed(0, &e.to_string()))?;
+
+        let channels = info.color_type.samples();
+        let mut values = Vec::with_capacity(info.width as usize * info.height as usize);
+        for row in buffer.chunks(info.line_size).take(info.height as usize) {
+            for pixel in row.chunks(channels).take(info.width as usize) {
+                // Ignore alpha and average any color.
+                let colors = if channels >= 3 { 3 } else { 1 };
+                let total: u32 = pixel[..colors].iter().map(|c| *c as u32).sum();
+                values.push(total as f32 / (255.0 * colors as f32));
+            }
+        }
+
+        Self::from(info.width as usize, info.height as usize, values)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.values[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+
+    // Bilinear sample where u and v go from 0 to 1 across the image.
+    pub fn sample(&self, u: f64, v: f64) -> f64 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f64;
+        let y = v.clamp(0.0, 1.0) * (self.height - 1) as f64;
+        let x0 = x.floor() as usize;
+        let y0 = y.floor() as usize;
+        let fx = x - x0 as f64;
+        let fy = y - y0 as f64;
+
+        let top = self.get(x0, y0) as f64 * (1.0 - fx) + self.get(x0 + 1, y0) as f64 * fx;
+        let bottom = self.get(x0, y0 + 1) as f64 * (1.0 - fx) + self.get(x0 + 1, y0 + 1) as f64 * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+impl Relief {
+    pub fn from(
+        heightmap: Heightmap,
+        rect: lines_and_curves::Rectangle,
+        max_depth: f64,
+    ) -> error::Result<Self> {
+        // The raster steps by resolution so it can not be 0.
+        if !(rect.width() > 0.0 && rect.width().is_finite())
+            || !(rect.height() > 0.0 && rect.height().is_finite()) {
+            return Err(invalid(validation::Problem::EmptyRelief));
+        }
+        if !max_depth.is_finite() {
+            return Err(invalid(validation::Problem::NotFinite));
+        }
+        Ok(Self {
+            heightmap: heightmap,
+            rect: rect,
+            max_depth: max_depth.abs(),
+        })
+    }
+
+    pub fn rect(&self) -> &lines_and_curves::Rectangle {
+        &self.rect
+    }
+
+    pub fn max_depth(&self) -> f64 {
+        self.max_depth
+    }
+
+    // Distance between two pixels of the heightmap on the sign.
+    pub fn resolution(&self) -> f64 {
+        (self.rect.width() / (self.heightmap.width() - 1).max(1) as f64)
+            .min(self.rect.height() / (self.heightmap.height() - 1).max(1) as f64)
+    }
+
+    // Z of the carved surface with 0 being the top of the material.
+    pub fn surface_z(&self, x: f64, y: f64) -> f64 {
+        let u = (x - self.rect.min_x()) / self.rect.width();
+        let v = (self.rect.max_y() - y) / self.rect.height();
+        if u < 0.0 || u > 1.0 || v < 0.0 || v > 1.0 {
+            return 0.0;
+        }
+        -(1.0 - self.heightmap.sample(u, v)) * self.max_depth
+    }
+
+    // Lowest the tip of the tool can go at x, y without any part of the tool
+    // cutting below the surface around it.
+    pub fn tip_z(&self, tool: &cnc_router::Tool, x: f64, y: f64) -> f64 {
+        let step = self.resolution().max(tool.radius / 8.0);
+        let steps = (tool.radius / step).ceil() as i64;
+        let mut z = self.surface_z(x, y);
+        for i in -steps..=steps {
+            for j in -steps..=steps {
+                let dx = i as f64 * step;
+                let dy = j as f64 * step;
+                let r = (dx * dx + dy * dy).sqrt();
+                if r > tool.radius {
+                    continue;
+                }
+                let height = tool.geometry.profile_height(tool.radius, r);
+                z = z.max(self.surface_z(x + dx, y + dy) - height);
+            }
+        }
+        z.min(0.0)
+    }
+
+    // Zig zag over the rectangle going along x, stepping step_over in y.
+    // step_over has to be more than 0.
+    pub fn raster_path(&self, step_over: f64) -> bit_path::Path<f64> {
+        let step_along = self.resolution();
+        let width = (self.rect.width() / step_along).ceil() as usize + 1;
+        let height = (self.rect.height() / step_over).ceil() as usize + 1;
+        let min_x = self.rect.min_x();
+        let min_y = self.rect.min_y();
+        let max_x = self.rect.max_x();
+        let max_y = self.rect.max_y();
+        bit_path::Path::from(
+            width,
+            height,
+            Box::from(move |x: usize, y: usize| {
+                (
+                    (min_x + x as f64 * step_along).min(max_x),
+                    (min_y + y as f64 * step_over).min(max_y),
+                )
+            }),
+            bit_path::Path::path_x_then_y,
+        )
+    }
+
+    // Grid of where the tool can go for waterline roughing. The contours
+    // step in by no more than the radius of the tool so nothing is left in
+    // the middle of a level. step_over has to be more than 0.
+    pub fn waterline(
+        &self,
+        tool: &cnc_router::Tool,
+        stock_to_leave: f64,
+        step_over: f64,
+    ) -> Waterline {
+        let spacing = self.resolution().max(step_over / 4.0);
+        let width = (self.rect.width() / spacing).ceil() as usize + 1;
+        let height = (self.rect.height() / spacing).ceil() as usize + 1;
+        let mut tip_z = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                let x = (self.rect.min_x() + i as f64 * spacing).min(self.rect.max_x());
+                let y = (self.rect.min_y() + j as f64 * spacing).min(self.rect.max_y());
+                tip_z.push(self.tip_z(tool, x, y) + stock_to_leave);
+            }
+        }
+        Waterline {
+            min_x: self.rect.min_x(),
+            min_y: self.rect.min_y(),
+            max_x: self.rect.max_x(),
+            max_y: self.rect.max_y(),
+            spacing: spacing,
+            step_over: step_over.min(tool.radius),
+            width: width,
+            height: height,
+            tip_z: tip_z,
+        }
+    }
+}
+
+impl Waterline {
+    // The grid with a border of one point all the way around that the tool
+    // can never go to so every contour is closed.
+    fn padded_width(&self) -> usize {
+        self.width + 2
+    }
+
+    fn padded_point(&self, i: usize, j: usize) -> lines_and_curves::Point {
+        lines_and_curves::Point::from(
+            (self.min_x + (i as f64 - 1.0) * self.spacing).min(self.max_x),
+            (self.min_y + (j as f64 - 1.0) * self.spacing).min(self.max_y),
+        )
+    }
+
+    // How far each point of the padded grid is from where the tool can not
+    // go down to z, 0 where it can not.
+    fn distances(&self, z: f64) -> Vec<f64> {
+        let width = self.padded_width();
+        let height = self.height + 2;
+        let mut distances = vec![0.0; width * height];
+        for j in 0..self.height {
+            for i in 0..self.width {
+                if self.tip_z[j * self.width + i] <= z {
+                    distances[(j + 1) * width + i + 1] = f64::INFINITY;
+                }
+            }
+        }
+        // Two passes of a chamfer distance, first from the bottom left then
+        // back from the top right.
+        let straight = self.spacing;
+        let diagonal = self.spacing * std::f64::consts::SQRT_2;
+        for j in 1..height - 1 {
+            for i in 1..width - 1 {
+                let index = j * width + i;
+                let d = distances[index]
+                    .min(distances[index - 1] + straight)
+                    .min(distances[index - width] + straight)
+                    .min(distances[index - width - 1] + diagonal)
+                    .min(distances[index - width + 1] + diagonal);
+                distances[index] = d;
+            }
+        }
+        for j in (1..height - 1).rev() {
+            for i in (1..width - 1).rev() {
+                let index = j * width + i;
+                let d = distances[index]
+                    .min(distances[index + 1] + straight)
+                    .min(distances[index + width] + straight)
+                    .min(distances[index + width + 1] + diagonal)
+                    .min(distances[index + width - 1] + diagonal);
+                distances[index] = d;
+            }
+        }
+        distances
+    }
+
+    // Closed paths for the tip of the tool at z, the first following the edge
+    // of where it can go down to z and each next one step_over further in.
+    // The first point is not repeated at the end.
+    pub fn paths(&self, z: f64) -> Vec<Vec<lines_and_curves::Point>> {
+        let distances = self.distances(z);
+        let deepest = distances.iter().cloned().fold(0.0, f64::max);
+        let mut paths = Vec::new();
+        // The points next to where the tool can not go are a spacing away,
+        // so the first contour goes right through them.
+        let mut distance = self.spacing;
+        while distance <= deepest {
+            paths.append(&mut self.contours(&distances, distance));
+            distance += self.step_over;
+        }
+        paths
+    }
+
+    // Marching squares over the padded grid. Each edge of the grid that the
+    // contour crosses is in two squares so the segments join into loops.
+    fn contours(&self, distances: &Vec<f64>, distance: f64) -> Vec<Vec<lines_and_curves::Point>> {
+        let width = self.padded_width();
+        let height = self.height + 2;
+        let is_in = |i: usize, j: usize| distances[j * width + i] >= distance;
+        // Edge (i, j, 0) goes to the right of point (i, j) and (i, j, 1) up.
+        let edge = |i: usize, j: usize, up: usize| (j * width + i) * 2 + up;
+        let crossing = |i: usize, j: usize, up: usize| {
+            let (i2, j2) = if up == 0 { (i + 1, j) } else { (i, j + 1) };
+            let a = distances[j * width + i];
+            let b = distances[j2 * width + i2];
+            let t = ((distance - a) / (b - a)).clamp(0.0, 1.0);
+            let p1 = self.padded_point(i, j);
+            let p2 = self.padded_point(i2, j2);
+            lines_and_curves::Point::from(p1.x + (p2.x - p1.x) * t, p1.y + (p2.y - p1.y) * t)
+        };
+
+        let mut points = std::collections::HashMap::new();
+        let mut segments = Vec::new();
+        let mut edge_to_segments = std::collections::HashMap::<usize, Vec<usize>>::new();
+        for j in 0..height - 1 {
+            for i in 0..width - 1 {
+                // Corners going around the square and the edge after each.
+                let corners = [
+                    is_in(i, j),
+                    is_in(i + 1, j),
+                    is_in(i + 1, j + 1),
+                    is_in(i, j + 1),
+                ];
+                let edges = [(i, j, 0), (i + 1, j, 1), (i, j + 1, 0), (i, j, 1)];
+                let crossed = (0..4)
+                    .filter(|k| corners[*k] != corners[(k + 1) % 4])
+                    .collect::<Vec<usize>>();
+                let pairs = if crossed.len() == 2 {
+                    vec![(crossed[0], crossed[1])]
+                } else if crossed.len() == 4 {
+                    // Saddle, cut off the two corners not like the middle.
+                    let middle = (distances[j * width + i]
+                        + distances[j * width + i + 1]
+                        + distances[(j + 1) * width + i + 1]
+                        + distances[(j + 1) * width + i])
+                        / 4.0
+                        >= distance;
+                    if corners[1] != middle {
+                        vec![(0, 1), (2, 3)]
+                    } else {
+                        vec![(3, 0), (1, 2)]
+                    }
+                } else {
+                    vec![]
+                };
+                for (a, b) in pairs {
+                    let mut ends = [0; 2];
+                    for (end, k) in [a, b].into_iter().enumerate() {
+                        let (ei, ej, up) = edges[k];
+                        let key = edge(ei, ej, up);
+                        points.entry(key).or_insert_with(|| crossing(ei, ej, up));
+                        edge_to_segments.entry(key).or_default().push(segments.len());
+                        ends[end] = key;
+                    }
+                    segments.push(ends);
+                }
+            }
+        }
+
+        let mut used = vec![false; segments.len()];
+        let mut paths = Vec::new();
+        for first in 0..segments.len() {
+            if used[first] {
+                continue;
+            }
+            used[first] = true;
+            let mut keys = vec![segments[first][0]];
+            let mut key = segments[first][1];
+            while key != keys[0] {
+                keys.push(key);
+                let next = edge_to_segments[&key].iter().find(|s| !used[**s]);
+                let Some(&next) = next else {
+                    break;
+                };
+                used[next] = true;
+                key = if segments[next][0] == key { segments[next][1] } else { segments[next][0] };
+            }
+            let mut path: Vec<lines_and_curves::Point> = Vec::new();
+            for key in keys {
+                let point = points[&key];
+                if path.last().map_or(true, |last| last.distance_to(&point) > 0.0) {
+                    path.push(point);
+                }
+            }
+            if path.len() > 1 && path[0].distance_to(path.last().unwrap()) == 0.0 {
+                path.pop();
+            }
+            if path.len() > 1 {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_heightmap_from_pgm() {
+        let ascii = Heightmap::from_pgm(b"P2\n# comment\n2 2\n4\n0 4\n2 4\n").unwrap();
+        assert_eq!(ascii.width(), 2);
+        assert_eq!(ascii.height(), 2);
+        assert_eq!(ascii.get(0, 0), 0.0);
+        assert_eq!(ascii.get(0, 1), 0.5);
+
+        let binary = Heightmap::from_pgm(b"P5 2 2 255\n\x00\xff\x80\xff").unwrap();
+        assert_eq!(binary.get(1, 0), 1.0);
+        assert!((binary.sample(0.5, 0.5) - 0.625).abs() < 0.01);
+
+        assert!(matches!(
+            Heightmap::from_pgm(b"P2 2 2 4\n0 4 2"),
+            Err(error::Error::Parse(_))
+        ));
+        match Heightmap::from_pgm(b"P2\n2 2 4\n0 4\n2 x\n") {
+            Err(error::Error::Parse(e)) => assert_eq!(e.line, 4),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(Heightmap::from(2, 2, vec![0.0; 3]), Err(error::Error::Validation(_))));
+    }
+
+    #[test]
+    pub fn test_relief_tip_z_does_not_gouge() {
+        // A single raised pixel in the middle of a flat deep floor.
+        let mut values = vec![0.0; 17 * 17];
+        values[8 * 17 + 8] = 1.0;
+        let relief = Relief::from(
+            Heightmap::from(17, 17, values).unwrap(),
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(2.0, 2.0),
+            ),
+            1.0,
+        ).unwrap();
+        assert_eq!(relief.surface_z(0.0, 0.0), -1.0);
+        assert_eq!(relief.surface_z(1.0, 1.0), 0.0);
+
+        let mut tool = cnc_router::test_tool("Ball", 1, 0.25, cnc_router::ToolType::PartialCutBroad);
+        tool.smoothness = cnc_router::Smoothness::Finish;
+        tool.offset = 0.1;
+        assert_eq!(relief.tip_z(&tool, 1.25, 1.0), 0.0);
+        assert_eq!(relief.tip_z(&tool, 1.5, 1.0), -1.0);
+
+        tool.geometry = tool_geometry::ToolGeometry::BallEndMill;
+        let z = relief.tip_z(&tool, 1.25, 1.0);
+        assert!((z + 0.25).abs() < 0.000001);
+
+        assert!(matches!(Relief::from(
+            Heightmap::from(2, 2, vec![0.0; 4]).unwrap(),
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(1.0, 0.0),
+                lines_and_curves::Point::from(1.0, 2.0),
+            ),
+            1.0,
+        ), Err(error::Error::Validation(_))));
+    }
+
+    #[test]
+    pub fn test_waterline_follows_each_level() {
+        // Square pocket 1 deep in the middle of the top of the material.
+        let mut values = vec![1.0; 21 * 21];
+        for y in 5..16 {
+            for x in 5..16 {
+                values[y * 21 + x] = 0.0;
+            }
+        }
+        let relief = Relief::from(
+            Heightmap::from(21, 21, values).unwrap(),
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(2.0, 2.0),
+            ),
+            1.0,
+        ).unwrap();
+        let tool = cnc_router::test_tool("Rough", 1, 0.1, cnc_router::ToolType::PartialCutBroad);
+        let waterline = relief.waterline(&tool, 0.0, 0.1);
+        assert!(waterline.paths(0.0).len() > 0);
+
+        let paths = waterline.paths(-0.5);
+        // The tool fits from 0.6 to 1.4 and steps in 0.1 at a time.
+        assert!(paths.len() >= 3);
+        for path in paths.iter() {
+            assert!(path.len() >= 4);
+            for point in path.iter() {
+                assert!(relief.tip_z(&tool, point.x, point.y) <= -0.5 + 0.000001);
+            }
+        }
+        let edge = paths
+            .iter()
+            .flatten()
+            .map(|point| point.x)
+            .fold(f64::INFINITY, f64::min);
+        assert!((edge - 0.6).abs() < 0.05);
+
+        assert!(waterline.paths(-1.5).is_empty());
+    }
+}
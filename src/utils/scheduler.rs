@@ -0,0 +1,176 @@
+use super::*;
+
+// Orders the operations (one per entry in tools) so the same physical tool,
+// index_in_machine, is used back to back as much as possible without
+// breaking the order some operations need.
+
+// Returns true if tools[a] has to be cut before tools[b].
+pub fn must_come_before(tools: &Vec<cnc_router::Tool>, a: usize, b: usize) -> bool {
+    if a == b {
+        return false;
+    }
+    let first = tools[a].tool_type();
+    let second = tools[b].tool_type();
+
+    // Clearing is done biggest to smallest in the order given and everything
+    // else finishes what was cleared. Rest tools go from what the tools
+    // before them cut and space between tools from the thinnest one before
+    // them, otherwise tools the same size can go in any order so a tool
+    // given twice can be grouped.
+    if first.is_broad() && second.is_broad() {
+        let uses_tools_before = |tool_type: &cnc_router::ToolType| {
+            tool_type.is_rest()
+                || matches!(tool_type, cnc_router::ToolType::SpaceBetweenCutBroad(_, _, _))
+        };
+        return a < b
+            && (tools[a].radius != tools[b].radius
+                || uses_tools_before(&first)
+                || uses_tools_before(&second));
+    }
+    if first.is_broad() != second.is_broad() {
+        return first.is_broad();
+    }
+
+    // Chamfers break the edges left by everything else.
+    if first.is_chamfer() != second.is_chamfer() {
+        return second.is_chamfer();
+    }
+
+    // Braille goes on after the text around it is cut.
+    let first_braille_only = first.is_braille() && !first.is_text();
+    let second_braille_only = second.is_braille() && !second.is_text();
+    if first.is_text() && second_braille_only {
+        return true;
+    }
+    if second.is_text() && first_braille_only {
+        return false;
+    }
+
+    // Partial contours clean up after the contours before them on the same
+    // kind of shapes.
+    let overlaps = first.to_shape_type().overlaps(&second.to_shape_type());
+    if overlaps && a < b && is_partial_contour(&second) {
+        return true;
+    }
+    if overlaps && b < a && is_partial_contour(&first) {
+        return false;
+    }
+
+    false
+}
+
+fn is_partial_contour(tool_type: &cnc_router::ToolType) -> bool {
+    match tool_type {
+        cnc_router::ToolType::PartialContourAngle(_, _, _)
+        | cnc_router::ToolType::PartialContourRadius(_, _, _)
+        | cnc_router::ToolType::PartialContourRadiusOrAngle(_, _, _, _) => true,
+        _ => false,
+    }
+}
+
+// Indexes into tools in the order they should be cut.
+pub fn schedule(tools: &Vec<cnc_router::Tool>) -> Vec<usize> {
+    let mut order = Vec::with_capacity(tools.len());
+    let mut done = vec![false; tools.len()];
+    let mut current_tool: Option<usize> = None;
+
+    while order.len() < tools.len() {
+        let ready = (0..tools.len())
+            .filter(|b| {
+                !done[*b] && (0..tools.len()).all(|a| done[a] || !must_come_before(tools, a, *b))
+            })
+            .collect::<Vec<usize>>();
+
+        // Dependencies only loop if they are contradicting so fall back to
+        // the order given.
+        let next = if ready.is_empty() {
+            (0..tools.len()).find(|i| !done[*i]).unwrap()
+        } else if let Some(same_tool) = ready
+            .iter()
+            .find(|i| Some(tools[**i].index_in_machine) == current_tool)
+        {
+            *same_tool
+        } else {
+            ready[0]
+        };
+
+        done[next] = true;
+        current_tool = Some(tools[next].index_in_machine);
+        order.push(next);
+    }
+
+    order
+}
+
+// Number of times the machine has to swap tools cutting in order.
+pub fn tool_changes(tools: &Vec<cnc_router::Tool>, order: &Vec<usize>) -> usize {
+    let mut changes = 0;
+    let mut current_tool = None;
+    for i in order {
+        if current_tool != Some(tools[*i].index_in_machine) {
+            changes += 1;
+            current_tool = Some(tools[*i].index_in_machine);
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tool(index_in_machine: usize, tool_type: cnc_router::ToolType) -> cnc_router::Tool {
+        cnc_router::test_tool("", index_in_machine, 0.1, tool_type)
+    }
+
+    #[test]
+    pub fn test_schedule_groups_tools() {
+        let tools = vec![
+            tool(4, cnc_router::ToolType::FullCutBroad(100.0, false)),
+            tool(2, cnc_router::ToolType::full_text()),
+            tool(4, cnc_router::ToolType::full_text()),
+            tool(2, cnc_router::ToolType::full_braille()),
+        ];
+        let order = schedule(&tools);
+        assert_eq!(order, vec![0, 2, 1, 3]);
+        assert_eq!(tool_changes(&tools, &vec![0, 1, 2, 3]), 4);
+        assert_eq!(tool_changes(&tools, &order), 2);
+    }
+
+    #[test]
+    pub fn test_schedule_groups_broad_tools_the_same_size() {
+        let mut tools = vec![
+            tool(1, cnc_router::ToolType::FullCutBroad(100.0, false)),
+            tool(2, cnc_router::ToolType::FullCutBroad(100.0, false)),
+            tool(1, cnc_router::ToolType::DontAddCutBroad),
+        ];
+        assert_eq!(schedule(&tools), vec![0, 2, 1]);
+
+        // A smaller tool in between has to wait for the first and clear
+        // before the last.
+        tools[1].radius = 0.05;
+        assert!(must_come_before(&tools, 0, 1) && must_come_before(&tools, 1, 2));
+        assert_eq!(schedule(&tools), vec![0, 1, 2]);
+
+        tools[1].radius = 0.1;
+        tools[2].tool_type = cnc_router::ToolType::RestCutBroad(0.01);
+        assert_eq!(schedule(&tools), vec![0, 1, 2]);
+    }
+
+    #[test]
+    pub fn test_schedule_keeps_dependencies() {
+        let tools = vec![
+            tool(1, cnc_router::ToolType::full_braille()),
+            tool(2, cnc_router::ToolType::full_text()),
+            tool(1, cnc_router::ToolType::SpaceBetweenCutBroad(0.0, 0.0, 0.0)),
+            tool(2, cnc_router::ToolType::FullCutBroad(100.0, false)),
+        ];
+        let order = schedule(&tools);
+        assert_eq!(order, vec![2, 3, 1, 0]);
+        for i in 0..order.len() {
+            for j in (i + 1)..order.len() {
+                assert!(!must_come_before(&tools, order[j], order[i]));
+            }
+        }
+    }
+}
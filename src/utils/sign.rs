@@ -1,6 +1,5 @@
 #![allow(dead_code)]
 use super::*;
-use std::collections::HashMap;
 
 
 #[derive(Debug, Clone)]
@@ -85,6 +84,7 @@ impl<T: lines_and_curves::Intersection + Clone> Sign<T> {
         bit_radius: f64,
         do_cut_on_odd: bool,
         add_padding_to: &Vec<(cnc_router::ShapeType, f64)>,
+        logger: &dyn logging::Logger,
     ) -> Self {
         // TODO: Remove this eventually but fix the issue of braille sometimes
         // overlapping after growth sometimes leaving random circles where it should cut
@@ -92,11 +92,13 @@ impl<T: lines_and_curves::Intersection + Clone> Sign<T> {
             return self.expand_lines(
                 bit_radius - 0.08,
                 do_cut_on_odd,
-                add_padding_to
+                add_padding_to,
+                logger,
             ).expand_lines(
                 0.08,
                 do_cut_on_odd,
                 &Vec::new(),
+                logger,
             );
         }
         // if bit_radius > 0.01 {
@@ -106,10 +108,12 @@ impl<T: lines_and_curves::Intersection + Clone> Sign<T> {
 
         let mut sign_copy = self.clone();
 
-        let mut groups_of_intersections : HashMap<
+        // Kept in the order each shape type is first seen so the shapes come
+        // out in the same order every run.
+        let mut groups_of_intersections : Vec<(
             cnc_router::ShapeType,
             Vec<(Vec<T>, bool)>
-        > = HashMap::new();
+        )> = Vec::new();
 
         for shape in &self.shapes {
             let inner_point = lines_and_curves::Intersection::find_barely_inner_point(
@@ -135,17 +139,21 @@ impl<T: lines_and_curves::Intersection + Clone> Sign<T> {
                     &shape.lines(),
                     bit_radius + addition_radius,
                     can_cut_inside,
+                    logger,
                     // Box::from(|x, y| {
                     //     (sign_copy.y_values_before(x, y) % 2 == 1) == do_cut_on_odd
                     // })
                 );
 
-            if let Some(mut_v) = groups_of_intersections.get_mut(&shape.tool_type) {
+            if let Some((_, mut_v)) = groups_of_intersections
+                .iter_mut()
+                .find(|(tool_type, _)| *tool_type == shape.tool_type)
+            {
                 for lines in new_lines {
                     mut_v.push(lines);
                 }
             } else {
-                groups_of_intersections.insert(shape.tool_type, new_lines);
+                groups_of_intersections.push((shape.tool_type, new_lines));
             }
         }
 
@@ -156,6 +164,7 @@ impl<T: lines_and_curves::Intersection + Clone> Sign<T> {
                 lines_and_curves::Intersection::remove_touching_shapes(
                     &groups,
                     bit_radius >= 0.0,
+                    logger,
                 )
                 .iter()
                 .map(|shape : &(Vec<T>, bool)| {
@@ -308,7 +317,7 @@ impl<T: lines_and_curves::Intersection + Clone> Sign<T> {
 
         y_values.push(self.bounding_rect.min_y());
         y_values.push(self.bounding_rect.max_y());
-        y_values.sort_by(|l, r| l.partial_cmp(r).unwrap());
+        y_values.sort_by(|l, r| l.total_cmp(r));
         return y_values;
     }
 
@@ -320,7 +329,7 @@ impl<T: lines_and_curves::Intersection + Clone> Sign<T> {
             .flatten()
             .collect();
 
-        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        xs.sort_by(|a, b| a.total_cmp(b));
         return xs;
     }
 
@@ -375,6 +384,16 @@ impl<T: lines_and_curves::Intersection> Shape<T> {
         &self.lines
     }
 
+    // Same shape but the lines start at lines[start] so a closed contour can
+    // be entered anywhere.
+    pub fn rotated(&self, start: usize) -> Self where T: Clone {
+        let mut shape = self.clone();
+        if !shape.lines.is_empty() {
+            shape.lines.rotate_left(start % self.lines.len());
+        }
+        shape
+    }
+
     pub fn closest_line(&self, point: &lines_and_curves::Point)
         -> Option<(f64, &T)> {
         let mut closest : Option<(f64, &T)> = None;
@@ -427,7 +446,7 @@ impl<T: lines_and_curves::Intersection> Shape<T> {
 
             let mut y_values = Vec::new();
             y_values_and_is_inside[i].sort_by(|a, b| {
-                let result = a.0.partial_cmp(&b.0).unwrap();
+                let result = a.0.total_cmp(&b.0);
                 if result == std::cmp::Ordering::Equal {
                     if a.1 == b.1 {
                         std::cmp::Ordering::Equal
@@ -480,7 +499,7 @@ impl<T: lines_and_curves::Intersection> Shape<T> {
 
         let mut y_values = Vec::new();
         y_values_and_is_inside.sort_by(|a, b| {
-            let result = a.0.partial_cmp(&b.0).unwrap();
+            let result = a.0.total_cmp(&b.0);
             if result == std::cmp::Ordering::Equal {
                 if a.1 == b.1 {
                     std::cmp::Ordering::Equal
@@ -0,0 +1,319 @@
+use super::*;
+
+// Height field of the stock that a toolpath is played back on, then compared
+// to what the sign says should be left. Catches cuts into raised shapes
+// (gouges) and material that was meant to be cleared but was not (leftover).
+//
+// Z is 0 at the top of the stock and goes negative into it. Moves are in
+// machine Z so the tool's length is taken off to get where its tip is, the
+// same way GCodeCreator adds it.
+
+#[derive(Debug, Clone)]
+pub struct Stock {
+    rect: lines_and_curves::Rectangle,
+    thickness: f64,
+    cell_size: f64,
+    columns: usize,
+    rows: usize,
+    heights: Vec<f64>, // [row * columns + column]
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub rect: lines_and_curves::Rectangle,
+    pub cell_size: f64,
+    pub columns: usize,
+    pub rows: usize,
+    pub errors: Vec<f64>, // simulated height - wanted height, a heatmap
+    pub gouges: Vec<lines_and_curves::Rectangle>,
+    pub leftover: Vec<lines_and_curves::Rectangle>,
+    pub max_gouge: f64,
+    pub max_leftover: f64,
+    pub tolerance: f64,
+}
+
+impl Stock {
+    pub fn from(rect: lines_and_curves::Rectangle, thickness: f64, cell_size: f64) -> Self {
+        let columns = ((rect.width() / cell_size).ceil() as usize).max(1);
+        let rows = ((rect.height() / cell_size).ceil() as usize).max(1);
+        Self {
+            rect: rect,
+            thickness: thickness.abs(),
+            cell_size: cell_size,
+            columns: columns,
+            rows: rows,
+            heights: vec![0.0; columns * rows],
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn height(&self, column: usize, row: usize) -> f64 {
+        self.heights[row * self.columns + column]
+    }
+
+    pub fn cell_center(&self, column: usize, row: usize) -> lines_and_curves::Point {
+        lines_and_curves::Point::from(
+            self.rect.min_x() + (column as f64 + 0.5) * self.cell_size,
+            self.rect.min_y() + (row as f64 + 0.5) * self.cell_size,
+        )
+    }
+
+    // Height of the material under x, y.
+    pub fn height_at(&self, x: f64, y: f64) -> Option<f64> {
+        if !self.rect.contains_point(lines_and_curves::Point::from(x, y)) {
+            return None;
+        }
+        let column = (((x - self.rect.min_x()) / self.cell_size) as usize).min(self.columns - 1);
+        let row = (((y - self.rect.min_y()) / self.cell_size) as usize).min(self.rows - 1);
+        Some(self.height(column, row))
+    }
+
+    fn cell_range(&self, from: f64, to: f64, min: f64, count: usize) -> std::ops::Range<usize> {
+        let start = ((from - min) / self.cell_size).floor().max(0.0) as usize;
+        let end = (((to - min) / self.cell_size).ceil().max(0.0) as usize).min(count);
+        start.min(end)..end
+    }
+
+    // Removes everything the tool touches with its tip at x, y, tip_z.
+    pub fn cut_at(&mut self, x: f64, y: f64, tip_z: f64, tool: &cnc_router::Tool) {
+        if tip_z >= 0.0 {
+            return;
+        }
+        let r = tool.radius;
+        let columns = self.cell_range(x - r, x + r, self.rect.min_x(), self.columns);
+        let rows = self.cell_range(y - r, y + r, self.rect.min_y(), self.rows);
+        for row in rows {
+            for column in columns.clone() {
+                let center = self.cell_center(column, row);
+                let d = ((center.x - x).powi(2) + (center.y - y).powi(2)).sqrt();
+                if d > r {
+                    continue;
+                }
+                let z = (tip_z + tool.geometry.profile_height(r, d)).max(-self.thickness);
+                let height = &mut self.heights[row * self.columns + column];
+                if z < *height {
+                    *height = z;
+                }
+            }
+        }
+    }
+
+    pub fn cut_move(&mut self, m: &toolpath::Move, tool: &cnc_router::Tool) {
+        match m.kind {
+            toolpath::MoveKind::Dwell(_) | toolpath::MoveKind::ToolChange => return,
+            _ => {}
+        }
+        self.cut_at(m.start.x, m.start.y, m.start.z - tool.length, tool);
+        for p in m.points(self.cell_size / 2.0) {
+            self.cut_at(p.x, p.y, p.z - tool.length, tool);
+        }
+    }
+
+    // Plays back every move including rapids since a rapid through the stock
+    // cuts it too. tools is matched to moves by index_in_machine. A program
+    // read with gcode_parser can be played back the same way.
+    pub fn replay(&mut self, toolpath: &toolpath::Toolpath, tools: &Vec<cnc_router::Tool>) {
+        for m in toolpath.moves() {
+            let Some(tool) = tools.iter().find(|t| t.index_in_machine == m.tool) else {
+                continue;
+            };
+            self.cut_move(m, tool);
+        }
+    }
+
+    // Compares against the sign. Where do_cut_on_odd says to cut the stock
+    // should be cut_depth down, everywhere else it should be untouched.
+    pub fn compare<J: lines_and_curves::Intersection + Clone>(
+        &self,
+        sign: &sign::Sign<J>,
+        do_cut_on_odd: bool,
+        cut_depth: f64,
+        tolerance: f64,
+    ) -> SimulationReport {
+        let mut sign = sign.clone();
+        let mut errors = Vec::with_capacity(self.heights.len());
+        let mut gouges = Vec::new();
+        let mut leftover = Vec::new();
+        let mut max_gouge: f64 = 0.0;
+        let mut max_leftover: f64 = 0.0;
+        for row in 0..self.rows {
+            let mut gouge_start = None;
+            let mut leftover_start = None;
+            for column in 0..=self.columns {
+                let (is_gouge, is_leftover) = if column < self.columns {
+                    let center = self.cell_center(column, row);
+                    let wanted = if sign.sees_even_odd_lines_before(
+                        center.x,
+                        center.y,
+                        do_cut_on_odd,
+                        false,
+                    ) {
+                        -cut_depth.abs()
+                    } else {
+                        0.0
+                    };
+                    let error = self.height(column, row) - wanted;
+                    errors.push(error);
+                    max_gouge = max_gouge.max(-error);
+                    max_leftover = max_leftover.max(error);
+                    (-error > tolerance, error > tolerance)
+                } else {
+                    (false, false)
+                };
+
+                // Runs of bad cells along a row are joined into one rectangle.
+                for (is_bad, start, regions) in [
+                    (is_gouge, &mut gouge_start, &mut gouges),
+                    (is_leftover, &mut leftover_start, &mut leftover),
+                ] {
+                    if is_bad && start.is_none() {
+                        *start = Some(column);
+                    } else if !is_bad {
+                        if let Some(first) = start.take() {
+                            regions.push(self.cells_rect(first, column, row));
+                        }
+                    }
+                }
+            }
+        }
+
+        SimulationReport {
+            rect: self.rect.clone(),
+            cell_size: self.cell_size,
+            columns: self.columns,
+            rows: self.rows,
+            errors: errors,
+            gouges: gouges,
+            leftover: leftover,
+            max_gouge: max_gouge,
+            max_leftover: max_leftover,
+            tolerance: tolerance,
+        }
+    }
+
+    fn cells_rect(&self, first: usize, end: usize, row: usize) -> lines_and_curves::Rectangle {
+        lines_and_curves::Rectangle::from(
+            lines_and_curves::Point::from(
+                self.rect.min_x() + first as f64 * self.cell_size,
+                self.rect.min_y() + row as f64 * self.cell_size,
+            ),
+            lines_and_curves::Point::from(
+                (self.rect.min_x() + end as f64 * self.cell_size).min(self.rect.max_x()),
+                (self.rect.min_y() + (row + 1) as f64 * self.cell_size).min(self.rect.max_y()),
+            ),
+        )
+    }
+}
+
+impl SimulationReport {
+    pub fn passed(&self) -> bool {
+        self.max_gouge <= self.tolerance && self.max_leftover <= self.tolerance
+    }
+
+    pub fn is_leftover(&self, x: f64, y: f64) -> bool {
+        if !self.rect.contains_point(lines_and_curves::Point::from(x, y)) {
+            return false;
+        }
+        let column = (((x - self.rect.min_x()) / self.cell_size) as usize).min(self.columns - 1);
+        let row = (((y - self.rect.min_y()) / self.cell_size) as usize).min(self.rows - 1);
+        self.errors[row * self.columns + column] > self.tolerance
+    }
+
+    // True if a tool of radius centered at x, y would cut any leftover.
+    pub fn has_leftover_near(&self, x: f64, y: f64, radius: f64) -> bool {
+        let steps = (radius / self.cell_size).ceil() as i64;
+        for i in -steps..=steps {
+            for j in -steps..=steps {
+                let dx = i as f64 * self.cell_size;
+                let dy = j as f64 * self.cell_size;
+                if (dx * dx + dy * dy).sqrt() <= radius && self.is_leftover(x + dx, y + dy) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl std::fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: Gouges: {} (max {:.4}), Leftover: {} (max {:.4}), Tolerance: {}",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.gouges.len(),
+            self.max_gouge,
+            self.leftover.len(),
+            self.max_leftover,
+            self.tolerance,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_simulation_finds_gouge() {
+        let mut tool = cnc_router::test_tool("Bit", 1, 0.1, cnc_router::ToolType::full_text());
+        tool.length = 1.0;
+        let mut toolpath = toolpath::Toolpath::new();
+        for (start, end) in [((0.0, 0.1), (2.0, 0.1)), ((0.2, 0.5), (0.8, 0.5))] {
+            toolpath.push(toolpath::Move::from(
+                toolpath::MoveKind::Feed,
+                cnc_router::Coordinate::from(start.0, start.1, 0.9),
+                cnc_router::Coordinate::from(end.0, end.1, 0.9),
+                30.0,
+                1,
+            ));
+        }
+        let rect = lines_and_curves::Rectangle::from(
+            lines_and_curves::Point::from(0.0, 0.0),
+            lines_and_curves::Point::from(2.0, 1.0),
+        );
+        let mut stock = Stock::from(rect.clone(), 0.5, 0.05);
+        stock.replay(&toolpath, &vec![tool]);
+        assert!((stock.height_at(1.0, 0.1).unwrap() + 0.1).abs() < 0.000001);
+        assert_eq!(stock.height_at(1.0, 0.9), Some(0.0));
+
+        // Raised square that the second pass runs into.
+        let sign = sign::Sign::from(
+            rect,
+            vec![sign::Shape::from(
+                cnc_router::ShapeType::text(),
+                lines_and_curves::AllIntersections::from_line_segment(
+                    lines_and_curves::LineSegment::from_points(&vec![
+                        lines_and_curves::Point::from(0.5, 0.3),
+                        lines_and_curves::Point::from(1.5, 0.3),
+                        lines_and_curves::Point::from(1.5, 0.7),
+                        lines_and_curves::Point::from(0.5, 0.7),
+                    ]),
+                ),
+            )],
+        );
+        let report = stock.compare(&sign, false, 0.1, 0.01);
+        assert!(!report.passed());
+        assert!((report.max_gouge - 0.1).abs() < 0.000001);
+        assert!(!report.gouges.is_empty());
+        for gouge in &report.gouges {
+            assert!(gouge.min_x() >= 0.5 && gouge.max_x() <= 0.9);
+            assert!(gouge.min_y() >= 0.3 && gouge.max_y() <= 0.7);
+        }
+        assert!(!report.leftover.is_empty());
+        assert_eq!(report.errors.len(), stock.columns() * stock.rows());
+
+        // What a rest tool would look for.
+        assert!(!report.is_leftover(1.0, 0.1));
+        assert!(report.is_leftover(1.0, 0.9));
+        assert!(report.has_leftover_near(1.0, 0.1, 0.2));
+        assert!(!report.has_leftover_near(1.0, 0.5, 0.1));
+    }
+}
@@ -0,0 +1,206 @@
+use super::*;
+
+// Totals of a toolpath for quoting jobs. Time is estimated with every move
+// speeding up from a stop and slowing down to a stop at a fixed acceleration.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MachineSpeeds {
+    pub rapid_feed_rate: f64, // units per minute
+    pub acceleration: f64,    // units per second squared
+    pub tool_change_time: f64, // seconds
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ToolStats {
+    pub cutting_distance: f64,
+    pub rapid_distance: f64,
+    pub plunges: usize,
+    pub lifts: usize,
+    pub tool_changes: usize,
+    pub time: f64, // seconds
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stats {
+    pub per_tool: Vec<(usize, ToolStats)>, // index_in_machine, stats
+    pub total: ToolStats,
+}
+
+impl Default for MachineSpeeds {
+    fn default() -> Self {
+        Self {
+            rapid_feed_rate: 400.0,
+            acceleration: 20.0,
+            tool_change_time: 15.0,
+        }
+    }
+}
+
+impl MachineSpeeds {
+    pub fn from(rapid_feed_rate: f64, acceleration: f64, tool_change_time: f64) -> Self {
+        Self {
+            rapid_feed_rate: rapid_feed_rate,
+            acceleration: acceleration,
+            tool_change_time: tool_change_time,
+        }
+    }
+}
+
+impl ToolStats {
+    pub fn add(&mut self, other: &ToolStats) {
+        self.cutting_distance += other.cutting_distance;
+        self.rapid_distance += other.rapid_distance;
+        self.plunges += other.plunges;
+        self.lifts += other.lifts;
+        self.tool_changes += other.tool_changes;
+        self.time += other.time;
+    }
+}
+
+impl std::fmt::Display for ToolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Rounded before splitting so 59.6 seconds is 1:00 and not 0:60.
+        let seconds = self.time.max(0.0).round() as u64;
+        write!(
+            f,
+            "Cut: {:.3}, Rapid: {:.3}, Plunges: {}, Lifts: {}, Tool Changes: {}, Time: {}:{:02}",
+            self.cutting_distance,
+            self.rapid_distance,
+            self.plunges,
+            self.lifts,
+            self.tool_changes,
+            seconds / 60,
+            seconds % 60,
+        )
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (tool, stats) in &self.per_tool {
+            writeln!(f, "T{}: {}", tool, stats)?;
+        }
+        write!(f, "Total: {}", self.total)
+    }
+}
+
+// Seconds to go length starting and ending stopped with a top speed of
+// feed_rate units per minute.
+pub fn move_time(length: f64, feed_rate: f64, acceleration: f64) -> f64 {
+    if length <= 0.0 || feed_rate <= 0.0 {
+        return 0.0;
+    }
+    let speed = feed_rate / 60.0;
+    if acceleration <= 0.0 {
+        return length / speed;
+    }
+    // Distance used speeding up plus slowing down.
+    let ramp_distance = speed * speed / acceleration;
+    if length >= ramp_distance {
+        length / speed + speed / acceleration
+    } else {
+        2.0 * (length / acceleration).sqrt()
+    }
+}
+
+pub fn stats(toolpath: &toolpath::Toolpath, speeds: &MachineSpeeds) -> Stats {
+    let mut per_tool: Vec<(usize, ToolStats)> = Vec::new();
+    for m in toolpath.moves() {
+        let index = if let Some(index) = per_tool.iter().position(|(tool, _)| *tool == m.tool) {
+            index
+        } else {
+            per_tool.push((m.tool, ToolStats::default()));
+            per_tool.len() - 1
+        };
+        let tool_stats = &mut per_tool[index].1;
+
+        let length = m.length();
+        match m.kind {
+            toolpath::MoveKind::Rapid => {
+                tool_stats.rapid_distance += length;
+                tool_stats.time += move_time(length, speeds.rapid_feed_rate, speeds.acceleration);
+            }
+            toolpath::MoveKind::Feed | toolpath::MoveKind::Arc(_, _) => {
+                tool_stats.cutting_distance += length;
+                let feed_rate = if m.feed_rate > 0.0 {
+                    m.feed_rate.min(speeds.rapid_feed_rate)
+                } else {
+                    speeds.rapid_feed_rate
+                };
+                tool_stats.time += move_time(length, feed_rate, speeds.acceleration);
+            }
+            toolpath::MoveKind::Dwell(seconds) => {
+                tool_stats.time += seconds;
+            }
+            toolpath::MoveKind::ToolChange => {
+                tool_stats.tool_changes += 1;
+                tool_stats.time += speeds.tool_change_time;
+            }
+        }
+        if m.is_plunge() {
+            tool_stats.plunges += 1;
+        }
+        if m.is_lift() {
+            tool_stats.lifts += 1;
+        }
+    }
+
+    let mut total = ToolStats::default();
+    for (_, tool_stats) in &per_tool {
+        total.add(tool_stats);
+    }
+    Stats {
+        per_tool: per_tool,
+        total: total,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_move_time() {
+        // 60 units/min is 1 unit/s which takes 1 unit to get to and stop from.
+        assert_eq!(move_time(10.0, 60.0, 1.0), 11.0);
+        assert_eq!(move_time(0.25, 60.0, 1.0), 1.0);
+        assert_eq!(move_time(10.0, 60.0, 0.0), 10.0);
+    }
+
+    #[test]
+    pub fn test_stats() {
+        let mut router = cnc_router::CNCRouter::from(
+            Vec::new(),
+            false,
+            cnc_router::Coordinate::from(0.0, 0.0, 1.0),
+            Vec::new(),
+        );
+        router.record_toolpath(true);
+        router.move_to_coordinate_rapid(&cnc_router::Coordinate::from(3.0, 4.0, 1.0));
+        router.move_to_coordinate(&cnc_router::Coordinate::from(3.0, 4.0, 0.0), Some(60.0), false);
+        router.move_to_coordinate(&cnc_router::Coordinate::from(3.0, 6.0, 0.0), Some(60.0), false);
+        router.move_to_optional_coordinate(
+            &cnc_router::OptionalCoordinate::from_z(Some(1.0)),
+            Some(60.0),
+            false,
+        );
+        router.dewel(500);
+
+        let stats = stats(
+            router.get_toolpath().unwrap(),
+            &MachineSpeeds::from(600.0, 0.0, 10.0),
+        );
+        assert_eq!(stats.per_tool.len(), 1);
+        assert_eq!(stats.total.rapid_distance, 5.0);
+        assert_eq!(stats.total.cutting_distance, 4.0);
+        assert_eq!(stats.total.plunges, 1);
+        assert_eq!(stats.total.lifts, 1);
+        assert_eq!(stats.total.tool_changes, 0);
+        assert!((stats.total.time - (0.5 + 4.0 + 0.5)).abs() < 0.000001);
+        assert!(format!("{}", stats.total).ends_with("Time: 0:05"));
+
+        let mut total = stats.total;
+        total.time = 119.6;
+        assert!(format!("{}", total).ends_with("Time: 2:00"));
+    }
+}
@@ -0,0 +1,310 @@
+use super::*;
+
+// Draws a sign and the toolpath that cuts it as an SVG to check a job in a
+// browser before it goes to the machine.
+
+const TOOL_COLORS: &[&str] = &[
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SvgOptions {
+    pub pixels_per_unit: f64,
+    pub margin: f64,         // units around the drawing
+    pub arc_step: f64,       // units between points drawn along arcs
+    pub show_rapids: bool,
+    pub show_tool_width: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            pixels_per_unit: 100.0,
+            margin: 0.5,
+            arc_step: 0.01,
+            show_rapids: true,
+            show_tool_width: true,
+        }
+    }
+}
+
+pub fn tool_color(order: usize) -> &'static str {
+    TOOL_COLORS[order % TOOL_COLORS.len()]
+}
+
+struct Canvas {
+    min_x: f64,
+    max_y: f64,
+    scale: f64,
+}
+
+impl Canvas {
+    fn x(&self, x: f64) -> f64 {
+        (x - self.min_x) * self.scale
+    }
+
+    // SVG y goes down.
+    fn y(&self, y: f64) -> f64 {
+        (self.max_y - y) * self.scale
+    }
+
+    fn points(&self, points: &Vec<lines_and_curves::Point>) -> String {
+        points
+            .iter()
+            .map(|p| format!("{:.3},{:.3}", self.x(p.x), self.y(p.y)))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+fn bounds<J: lines_and_curves::Intersection + Clone>(
+    sign: Option<&sign::Sign<J>>,
+    toolpath: &toolpath::Toolpath,
+) -> lines_and_curves::Rectangle {
+    let mut rect: Option<lines_and_curves::Rectangle> = sign.map(|sign| sign.bounding_rect().clone());
+    for m in toolpath.moves() {
+        for c in [m.start, m.end] {
+            let p = lines_and_curves::Point::from(c.x, c.y);
+            let point_rect = lines_and_curves::Rectangle::from(p, p);
+            rect = Some(if let Some(rect) = rect {
+                rect.join(&point_rect)
+            } else {
+                point_rect
+            });
+        }
+    }
+    rect.unwrap_or(lines_and_curves::Rectangle::zero())
+}
+
+fn shape_svg<J: lines_and_curves::Intersection + cnc_router::CNCPath>(
+    canvas: &Canvas,
+    shape: &sign::Shape<J>,
+) -> Option<String> {
+    if shape.lines().len() == 1 {
+        if let Some(circle) = shape.lines()[0].as_circle() {
+            return Some(format!(
+                "<circle cx=\"{:.3}\" cy=\"{:.3}\" r=\"{:.3}\"/>",
+                canvas.x(circle.center.x),
+                canvas.y(circle.center.y),
+                circle.radius * canvas.scale,
+            ));
+        }
+    }
+    let points = cnc_router::CNCPath::to_path_vec(shape.lines())
+        .iter()
+        .filter_map(|p| Some(lines_and_curves::Point::from(p.x?, p.y?)))
+        .collect::<Vec<lines_and_curves::Point>>();
+    if points.len() < 2 {
+        return None;
+    }
+    Some(format!("<polygon points=\"{}\"/>", canvas.points(&points)))
+}
+
+// Writes the sign outlines in black then every tool's cuts in its own color.
+// tools gives the diameter drawn under each cut, matched by index_in_machine.
+pub fn write_svg<
+    W: std::io::Write,
+    J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath,
+>(
+    w: &mut W,
+    sign: Option<&sign::Sign<J>>,
+    toolpath: &toolpath::Toolpath,
+    tools: &Vec<cnc_router::Tool>,
+    options: &SvgOptions,
+) -> std::io::Result<()> {
+    let rect = bounds(sign, toolpath);
+    let canvas = Canvas {
+        min_x: rect.min_x() - options.margin,
+        max_y: rect.max_y() + options.margin,
+        scale: options.pixels_per_unit,
+    };
+    let width = (rect.width() + 2.0 * options.margin) * canvas.scale;
+    let height = (rect.height() + 2.0 * options.margin) * canvas.scale;
+    let line_width = 1.0;
+
+    writeln!(
+        w,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.3} {:.3}\">",
+        width, height, width, height,
+    )?;
+    writeln!(w, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>")?;
+
+    if let Some(sign) = sign {
+        writeln!(
+            w,
+            "<g id=\"sign\" fill=\"#eeeeee\" stroke=\"black\" stroke-width=\"{}\">",
+            line_width,
+        )?;
+        for shape in sign.shapes() {
+            if let Some(svg) = shape_svg(&canvas, shape) {
+                writeln!(w, "{}", svg)?;
+            }
+        }
+        writeln!(w, "</g>")?;
+    }
+
+    let tool_order = toolpath.tools();
+    for (order, tool) in tool_order.iter().enumerate() {
+        let color = tool_color(order);
+        let radius = tools
+            .iter()
+            .find(|t| t.index_in_machine == *tool)
+            .map(|t| t.radius)
+            .unwrap_or(0.0);
+
+        // Runs of cutting moves are drawn as one polyline each.
+        let mut cuts: Vec<Vec<lines_and_curves::Point>> = Vec::new();
+        let mut rapids = Vec::new();
+        let mut plunges = Vec::new();
+        let mut is_cutting = false;
+        for m in toolpath.moves().iter().filter(|m| m.tool == *tool) {
+            if m.is_plunge() {
+                plunges.push(lines_and_curves::Point::from(m.end.x, m.end.y));
+            }
+            if m.is_cutting() {
+                if !is_cutting {
+                    cuts.push(vec![lines_and_curves::Point::from(m.start.x, m.start.y)]);
+                }
+                let cut = cuts.last_mut().unwrap();
+                for p in m.points(options.arc_step) {
+                    cut.push(lines_and_curves::Point::from(p.x, p.y));
+                }
+                is_cutting = true;
+            } else {
+                if m.kind == toolpath::MoveKind::Rapid {
+                    rapids.push(m);
+                }
+                is_cutting = false;
+            }
+        }
+
+        writeln!(w, "<g id=\"tool-{}\">", tool)?;
+        writeln!(w, "<title>T{} D={}</title>", tool, radius * 2.0)?;
+        for cut in &cuts {
+            if options.show_tool_width && radius > 0.0 {
+                writeln!(
+                    w,
+                    "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-opacity=\"0.25\" stroke-width=\"{:.3}\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/>",
+                    canvas.points(cut),
+                    color,
+                    2.0 * radius * canvas.scale,
+                )?;
+            }
+            writeln!(
+                w,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                canvas.points(cut),
+                color,
+                line_width,
+            )?;
+        }
+        if options.show_rapids {
+            for m in rapids {
+                writeln!(
+                    w,
+                    "<line x1=\"{:.3}\" y1=\"{:.3}\" x2=\"{:.3}\" y2=\"{:.3}\" stroke=\"{}\" stroke-width=\"{}\" stroke-dasharray=\"4 4\"/>",
+                    canvas.x(m.start.x),
+                    canvas.y(m.start.y),
+                    canvas.x(m.end.x),
+                    canvas.y(m.end.y),
+                    color,
+                    line_width,
+                )?;
+            }
+        }
+        for p in plunges {
+            writeln!(
+                w,
+                "<circle cx=\"{:.3}\" cy=\"{:.3}\" r=\"3\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                canvas.x(p.x),
+                canvas.y(p.y),
+                color,
+                line_width,
+            )?;
+        }
+        writeln!(w, "</g>")?;
+    }
+
+    writeln!(w, "</svg>")
+}
+
+pub fn to_svg<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    sign: Option<&sign::Sign<J>>,
+    toolpath: &toolpath::Toolpath,
+    tools: &Vec<cnc_router::Tool>,
+    options: &SvgOptions,
+) -> String {
+    let mut svg = Vec::new();
+    write_svg(&mut svg, sign, toolpath, tools, options).expect("Writing to a Vec can not fail");
+    String::from_utf8(svg).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_to_svg() {
+        let mut toolpath = toolpath::Toolpath::new();
+        let points = [
+            (toolpath::MoveKind::Rapid, (0.0, 0.0, 1.0), (1.0, 1.0, 1.0)),
+            (toolpath::MoveKind::Feed, (1.0, 1.0, 1.0), (1.0, 1.0, 0.0)),
+            (toolpath::MoveKind::Feed, (1.0, 1.0, 0.0), (2.0, 1.0, 0.0)),
+            (toolpath::MoveKind::Feed, (2.0, 1.0, 0.0), (2.0, 1.0, 1.0)),
+        ];
+        for (kind, start, end) in points {
+            toolpath.push(toolpath::Move::from(
+                kind,
+                cnc_router::Coordinate::from(start.0, start.1, start.2),
+                cnc_router::Coordinate::from(end.0, end.1, end.2),
+                10.0,
+                3,
+            ));
+        }
+        let tools = vec![cnc_router::test_tool("Bit", 3, 0.125, cnc_router::ToolType::full_text())];
+        let sign = sign::Sign::from(
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(3.0, 2.0),
+            ),
+            vec![
+                sign::Shape::from(
+                    cnc_router::ShapeType::text(),
+                    lines_and_curves::AllIntersections::from_line_segment(
+                        lines_and_curves::LineSegment::from_points(&vec![
+                            lines_and_curves::Point::from(0.5, 0.5),
+                            lines_and_curves::Point::from(2.5, 0.5),
+                            lines_and_curves::Point::from(2.5, 1.5),
+                            lines_and_curves::Point::from(0.5, 1.5),
+                        ]),
+                    ),
+                ),
+                sign::Shape::from(
+                    cnc_router::ShapeType::braille(),
+                    lines_and_curves::AllIntersections::from_circle(vec![
+                        lines_and_curves::Circle {
+                            center: lines_and_curves::Point::from(1.5, 1.0),
+                            radius: 0.1,
+                        },
+                    ]),
+                ),
+            ],
+        );
+        let options = SvgOptions {
+            margin: 0.0,
+            ..SvgOptions::default()
+        };
+        let svg = to_svg(Some(&sign), &toolpath, &tools, &options);
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("width=\"300\" height=\"200\""));
+        assert_eq!(svg.matches("<polygon").count(), 1);
+        assert!(svg.contains("<circle cx=\"150.000\" cy=\"100.000\" r=\"10.000\"/>"));
+        assert_eq!(svg.matches("stroke-dasharray").count(), 1);
+        // The plunge at (1, 1) is 1 unit in from the top left.
+        assert!(svg.contains("<circle cx=\"100.000\" cy=\"100.000\" r=\"3\""));
+        // Width of the tool then the center line.
+        assert!(svg.contains("stroke-width=\"25.000\""));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+    }
+}
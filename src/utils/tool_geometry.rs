@@ -0,0 +1,209 @@
+use serde::{Serialize, Deserialize};
+
+// Shape of the cutting end of a tool. Every method takes the tool's radius
+// (half of the shank cutting diameter) since that lives on Tool.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ToolGeometry {
+    FlatEndMill,
+    BallEndMill,
+    BullNose(f64),         // corner radius
+    VBit(f64, f64),        // included angle in degrees, tip diameter
+    TaperedBall(f64, f64), // included taper angle in degrees, tip radius
+    Drill(f64),            // included point angle in degrees
+}
+
+impl Default for ToolGeometry {
+    fn default() -> Self {
+        Self::FlatEndMill
+    }
+}
+
+fn half_angle(included_angle: f64) -> f64 {
+    included_angle.to_radians() / 2.0
+}
+
+impl ToolGeometry {
+    pub fn description(&self) -> String {
+        match self {
+            ToolGeometry::FlatEndMill => String::from("flat end mill"),
+            ToolGeometry::BallEndMill => String::from("ball end mill"),
+            ToolGeometry::BullNose(_) => String::from("bull nose end mill"),
+            ToolGeometry::VBit(angle, _) => format!("{} deg v-bit", angle),
+            ToolGeometry::TaperedBall(angle, _) => format!("{} deg tapered ball end mill", angle),
+            ToolGeometry::Drill(angle) => format!("{} deg drill", angle),
+        }
+    }
+
+    pub fn corner_radius(&self, radius: f64) -> f64 {
+        match self {
+            ToolGeometry::FlatEndMill => 0.0,
+            ToolGeometry::BallEndMill => radius,
+            ToolGeometry::BullNose(corner_radius) => corner_radius.min(radius),
+            ToolGeometry::VBit(_, _) => 0.0,
+            ToolGeometry::TaperedBall(_, tip_radius) => tip_radius.min(radius),
+            ToolGeometry::Drill(_) => 0.0,
+        }
+    }
+
+    // Height above the tip of the cutting edge at distance r from the center
+    // of the tool. Past the radius of the tool nothing is cut.
+    pub fn profile_height(&self, radius: f64, r: f64) -> f64 {
+        let r = r.abs();
+        if r > radius {
+            return f64::INFINITY;
+        }
+        match self {
+            ToolGeometry::FlatEndMill => 0.0,
+            ToolGeometry::BallEndMill => radius - (radius * radius - r * r).sqrt(),
+            ToolGeometry::BullNose(corner_radius) => {
+                let corner_radius = corner_radius.min(radius);
+                let flat = radius - corner_radius;
+                if r <= flat {
+                    0.0
+                } else {
+                    let dr = r - flat;
+                    corner_radius - (corner_radius * corner_radius - dr * dr).sqrt()
+                }
+            }
+            ToolGeometry::VBit(angle, tip_diameter) => {
+                let tip_radius = tip_diameter / 2.0;
+                if r <= tip_radius {
+                    0.0
+                } else {
+                    (r - tip_radius) / half_angle(*angle).tan()
+                }
+            }
+            ToolGeometry::TaperedBall(angle, tip_radius) => {
+                let half = half_angle(*angle);
+                // Where the ball meets the cone.
+                let tangent_r = tip_radius * half.cos();
+                if r <= tangent_r {
+                    tip_radius - (tip_radius * tip_radius - r * r).sqrt()
+                } else {
+                    let tangent_h =
+                        tip_radius - (tip_radius * tip_radius - tangent_r * tangent_r).sqrt();
+                    tangent_h + (r - tangent_r) / half.tan()
+                }
+            }
+            ToolGeometry::Drill(angle) => r / half_angle(*angle).tan(),
+        }
+    }
+
+    // Radius the tool cuts at when its tip is depth below the surface.
+    pub fn effective_radius(&self, radius: f64, depth: f64) -> f64 {
+        let depth = depth.abs();
+        let r = match self {
+            ToolGeometry::FlatEndMill => radius,
+            ToolGeometry::BallEndMill => {
+                if depth >= radius {
+                    radius
+                } else {
+                    (depth * (2.0 * radius - depth)).sqrt()
+                }
+            }
+            ToolGeometry::BullNose(corner_radius) => {
+                let corner_radius = corner_radius.min(radius);
+                if depth >= corner_radius {
+                    radius
+                } else {
+                    radius - corner_radius + (depth * (2.0 * corner_radius - depth)).sqrt()
+                }
+            }
+            ToolGeometry::VBit(angle, tip_diameter) => {
+                tip_diameter / 2.0 + depth * half_angle(*angle).tan()
+            }
+            ToolGeometry::TaperedBall(angle, tip_radius) => {
+                let half = half_angle(*angle);
+                let tangent_r = tip_radius * half.cos();
+                let tangent_h =
+                    tip_radius - (tip_radius * tip_radius - tangent_r * tangent_r).sqrt();
+                if depth <= tangent_h {
+                    (depth * (2.0 * tip_radius - depth)).sqrt()
+                } else {
+                    tangent_r + (depth - tangent_h) * half.tan()
+                }
+            }
+            ToolGeometry::Drill(angle) => depth * half_angle(*angle).tan(),
+        };
+        r.min(radius)
+    }
+
+    // Height of the ridge left between two passes stepover apart.
+    pub fn scallop_height(&self, radius: f64, stepover: f64) -> f64 {
+        self.profile_height(radius, (stepover / 2.0).min(radius))
+    }
+
+    // Widest stepover, up to the diameter, that leaves ridges no higher than
+    // height. The ridge only gets higher as the passes get farther apart.
+    pub fn stepover_for_scallop(&self, radius: f64, height: f64) -> f64 {
+        let mut low = 0.0;
+        let mut high = 2.0 * radius;
+        if self.scallop_height(radius, high) <= height {
+            return high;
+        }
+        for _ in 0..50 {
+            let middle = (low + high) / 2.0;
+            if self.scallop_height(radius, middle) <= height {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_flat_end_mill() {
+        let geometry = ToolGeometry::FlatEndMill;
+        assert_eq!(geometry.effective_radius(0.125, 0.01), 0.125);
+        assert_eq!(geometry.scallop_height(0.125, 0.1), 0.0);
+        assert_eq!(geometry.stepover_for_scallop(0.125, 0.0), 0.25);
+        assert_eq!(geometry.corner_radius(0.125), 0.0);
+    }
+
+    #[test]
+    pub fn test_ball_end_mill() {
+        let geometry = ToolGeometry::BallEndMill;
+        assert_eq!(geometry.effective_radius(1.0, 2.0), 1.0);
+        assert!((geometry.effective_radius(1.0, 0.5) - 0.75_f64.sqrt()).abs() < 0.000001);
+        assert!((geometry.scallop_height(1.0, 1.0) - (1.0 - 0.75_f64.sqrt())).abs() < 0.000001);
+        assert!((geometry.stepover_for_scallop(1.0, 1.0 - 0.75_f64.sqrt()) - 1.0).abs() < 0.000001);
+        assert_eq!(geometry.stepover_for_scallop(1.0, 2.0), 2.0);
+        assert!((geometry.profile_height(1.0, 1.0) - 1.0).abs() < 0.000001);
+        assert_eq!(geometry.corner_radius(1.0), 1.0);
+    }
+
+    #[test]
+    pub fn test_bull_nose() {
+        let geometry = ToolGeometry::BullNose(0.25);
+        assert_eq!(geometry.profile_height(1.0, 0.5), 0.0);
+        assert!((geometry.profile_height(1.0, 1.0) - 0.25).abs() < 0.000001);
+        assert!((geometry.effective_radius(1.0, 0.25) - 1.0).abs() < 0.000001);
+        assert!((geometry.effective_radius(1.0, 0.0) - 0.75).abs() < 0.000001);
+    }
+
+    #[test]
+    pub fn test_v_bit() {
+        let geometry = ToolGeometry::VBit(90.0, 0.0);
+        assert!((geometry.effective_radius(1.0, 0.25) - 0.25).abs() < 0.000001);
+        assert!((geometry.profile_height(1.0, 0.25) - 0.25).abs() < 0.000001);
+        assert_eq!(geometry.effective_radius(1.0, 5.0), 1.0);
+
+        let geometry = ToolGeometry::VBit(90.0, 0.2);
+        assert!((geometry.effective_radius(1.0, 0.25) - 0.35).abs() < 0.000001);
+    }
+
+    #[test]
+    pub fn test_tapered_ball_matches_profile() {
+        let geometry = ToolGeometry::TaperedBall(20.0, 0.1);
+        for depth in [0.01, 0.05, 0.2, 0.5] {
+            let r = geometry.effective_radius(1.0, depth);
+            assert!((geometry.profile_height(1.0, r) - depth).abs() < 0.000001);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use super::*;
+
+// Picks the tools for a job out of a whole tool library using
+// feature_analysis. The biggest flat end mill roughs, smaller ones follow up
+// with SpaceBetweenCutBroad until everything is cleared, then the text and
+// braille get contoured by the biggest tool that fits them. Every tool picked
+// comes with why.
+
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub tool: cnc_router::Tool,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolSelection {
+    pub choices: Vec<Choice>,
+    pub analyses: Vec<feature_analysis::FeatureAnalysis>,
+    pub needed_radius: f64, // biggest radius that finishes every sign
+    pub finishes: bool,     // false if the library has nothing small enough
+    // Shapes no tool fits around and the radius they need.
+    pub unfit: Vec<(String, f64)>,
+}
+
+impl ToolSelection {
+    // In the order to give to CNCRouter::from.
+    pub fn tools(&self) -> Vec<cnc_router::Tool> {
+        self.choices.iter().map(|choice| choice.tool.clone()).collect()
+    }
+}
+
+impl std::fmt::Display for ToolSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for choice in &self.choices {
+            writeln!(
+                f,
+                "T{} {} D={}: {}: {}",
+                choice.tool.index_in_machine,
+                choice.tool.name,
+                choice.tool.radius * 2.0,
+                choice.tool.tool_type.description(),
+                choice.reason,
+            )?;
+        }
+        for (name, radius) in &self.unfit {
+            writeln!(
+                f,
+                "No tool fits around the {}, needs a radius of {:.4} or less",
+                name, radius,
+            )?;
+        }
+        if !self.finishes {
+            writeln!(
+                f,
+                "No tool is small enough to finish, needs a radius of {:.4} or less",
+                self.needed_radius,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn with_tool_type(tool: &cnc_router::Tool, tool_type: cnc_router::ToolType) -> cnc_router::Tool {
+    let mut tool = tool.clone();
+    tool.tool_type = tool_type;
+    tool
+}
+
+// Only the shapes of shape_type, or None if a sign has none of them.
+fn only_shapes<J: lines_and_curves::Intersection + Clone>(
+    signs: &Vec<sign::Sign<J>>,
+    shape_type: &cnc_router::ShapeType,
+) -> Option<Vec<sign::Sign<J>>> {
+    let signs: Vec<sign::Sign<J>> = signs
+        .iter()
+        .map(|sign| {
+            sign::Sign::from(
+                sign.bounding_rect().clone(),
+                sign.shapes()
+                    .iter()
+                    .filter(|shape| shape.tool_type().overlaps(shape_type))
+                    .cloned()
+                    .collect(),
+            )
+        })
+        .filter(|sign| !sign.shapes().is_empty())
+        .collect();
+    if signs.is_empty() {
+        None
+    } else {
+        Some(signs)
+    }
+}
+
+fn needed_radius<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    signs: &Vec<sign::Sign<J>>,
+    do_cut_on_odd: bool,
+    start_radius: f64,
+    cell_size: f64,
+    tolerance: f64,
+) -> f64 {
+    signs
+        .iter()
+        .map(|sign| {
+            feature_analysis::smallest_tool_radius(
+                sign,
+                do_cut_on_odd,
+                &feature_analysis::inside_corners(sign, do_cut_on_odd),
+                start_radius,
+                cell_size,
+                tolerance,
+            )
+        })
+        .fold(start_radius, f64::min)
+}
+
+// Biggest candidate no bigger than radius, None if none are.
+fn biggest_that_fits<'a>(
+    candidates: &Vec<&'a cnc_router::Tool>,
+    radius: f64,
+) -> Option<&'a cnc_router::Tool> {
+    candidates
+        .iter()
+        .find(|tool| tool.radius <= radius)
+        .map(|tool| *tool)
+}
+
+// Only flat end mills are picked since the rest do not leave a flat floor.
+pub fn select_tools<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    library: &Vec<cnc_router::Tool>,
+    signs: &Vec<sign::Sign<J>>,
+    do_cut_on_odd: bool,
+    cell_size: f64,
+    tolerance: f64,
+) -> ToolSelection {
+    let mut candidates: Vec<&cnc_router::Tool> = library
+        .iter()
+        .filter(|tool| {
+            tool.radius > 0.0 && tool.geometry == tool_geometry::ToolGeometry::FlatEndMill
+        })
+        .collect();
+    // Biggest first, the lower index breaks ties so the same library always
+    // picks the same tools.
+    candidates.sort_by(|a, b| {
+        b.radius
+            .total_cmp(&a.radius)
+            .then(a.index_in_machine.cmp(&b.index_in_machine))
+    });
+    candidates.dedup_by(|a, b| a.radius == b.radius);
+
+    let radii: Vec<f64> = candidates.iter().map(|tool| tool.radius).collect();
+    let analyses = feature_analysis::analyze(signs, do_cut_on_odd, &radii, cell_size, tolerance);
+    let needed = analyses
+        .iter()
+        .map(|analysis| analysis.smallest_tool_radius)
+        .fold(f64::INFINITY, f64::min);
+    let uncleared = |radius: f64| -> f64 {
+        analyses
+            .iter()
+            .flat_map(|analysis| analysis.fits.iter())
+            .filter(|fit| fit.tool_radius == radius)
+            .map(|fit| fit.uncleared_area)
+            .sum()
+    };
+
+    let mut choices = Vec::new();
+    let Some(roughing) = candidates.first() else {
+        return ToolSelection {
+            choices: choices,
+            analyses: analyses,
+            needed_radius: needed,
+            finishes: signs.is_empty(),
+            unfit: Vec::new(),
+        };
+    };
+
+    let mut last_uncleared = uncleared(roughing.radius);
+    let mut last_radius = roughing.radius;
+    choices.push(Choice {
+        tool: with_tool_type(roughing, cnc_router::ToolType::default()),
+        reason: if roughing.radius <= needed {
+            String::from("Biggest tool and it finishes the sign on its own")
+        } else {
+            format!("Biggest tool, leaves {:.4} square units for smaller tools", last_uncleared)
+        },
+    });
+
+    // Every smaller tool that reaches more than the one before it until one
+    // is small enough for the smallest feature.
+    for tool in candidates.iter().skip(1) {
+        if last_radius <= needed {
+            break;
+        }
+        let tool_uncleared = uncleared(tool.radius);
+        let finishes = tool.radius <= needed;
+        if !finishes && tool_uncleared >= last_uncleared - cell_size * cell_size {
+            continue;
+        }
+        choices.push(Choice {
+            tool: with_tool_type(tool, cnc_router::ToolType::SpaceBetweenCutBroad(0.0, 0.0, 0.0)),
+            reason: if finishes {
+                format!(
+                    "Small enough for the smallest feature, needs a radius of {:.4} or less",
+                    needed,
+                )
+            } else {
+                format!(
+                    "Reaches between shapes the last tool could not, leaves {:.4} square units",
+                    tool_uncleared,
+                )
+            },
+        });
+        last_uncleared = tool_uncleared;
+        last_radius = tool.radius;
+    }
+
+    let start_radius = candidates[0].radius;
+    let mut unfit = Vec::new();
+    for (shape_type, tool_type, name) in [
+        (cnc_router::ShapeType::text(), cnc_router::ToolType::full_text(), "text"),
+        (cnc_router::ShapeType::braille(), cnc_router::ToolType::full_braille(), "braille"),
+    ] {
+        let Some(shape_signs) = only_shapes(signs, &shape_type) else {
+            continue;
+        };
+        let radius = needed_radius(&shape_signs, do_cut_on_odd, start_radius, cell_size, tolerance);
+        let Some(tool) = biggest_that_fits(&candidates, radius) else {
+            unfit.push((String::from(name), radius));
+            continue;
+        };
+        choices.push(Choice {
+            tool: with_tool_type(tool, tool_type),
+            reason: format!(
+                "Biggest tool that fits around the {}, needs a radius of {:.4} or less",
+                name, radius,
+            ),
+        });
+    }
+
+    ToolSelection {
+        choices: choices,
+        analyses: analyses,
+        needed_radius: needed,
+        finishes: last_radius <= needed,
+        unfit: unfit,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_select_tools() {
+        let tool = |index: usize, radius: f64, geometry: tool_geometry::ToolGeometry| {
+            let mut tool = cnc_router::test_tool(
+                &format!("Bit {}", index), index, radius, cnc_router::ToolType::full_text(),
+            );
+            tool.geometry = geometry;
+            tool
+        };
+        let library = vec![
+            tool(1, 0.0625, tool_geometry::ToolGeometry::FlatEndMill),
+            tool(2, 0.25, tool_geometry::ToolGeometry::VBit(90.0, 0.0)),
+            tool(3, 0.125, tool_geometry::ToolGeometry::FlatEndMill),
+        ];
+
+        // One letter and one dot with lots of room around them.
+        let sign = sign::Sign::from(
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(4.0, 4.0),
+            ),
+            vec![
+                sign::Shape::from(
+                    cnc_router::ShapeType::text(),
+                    lines_and_curves::AllIntersections::from_line_segment(
+                        lines_and_curves::LineSegment::from_points(&vec![
+                            lines_and_curves::Point::from(1.0, 1.0),
+                            lines_and_curves::Point::from(2.0, 1.0),
+                            lines_and_curves::Point::from(2.0, 2.0),
+                            lines_and_curves::Point::from(1.0, 2.0),
+                        ]),
+                    ),
+                ),
+                sign::Shape::from(
+                    cnc_router::ShapeType::braille(),
+                    lines_and_curves::AllIntersections::from_circle(vec![
+                        lines_and_curves::Circle {
+                            center: lines_and_curves::Point::from(3.0, 3.0),
+                            radius: 0.1,
+                        },
+                    ]),
+                ),
+            ],
+        );
+
+        let selection = select_tools(&library, &vec![sign.clone()], false, 0.05, 0.01);
+        assert!(selection.finishes);
+        assert!(selection.unfit.is_empty());
+        let tools = selection.tools();
+        assert_eq!(tools.len(), 3);
+        assert_eq!(tools[0].index_in_machine, 3);
+        assert_eq!(tools[0].tool_type, cnc_router::ToolType::default());
+        assert!(tools[1].tool_type.is_text() && !tools[1].tool_type.is_braille());
+        assert!(tools[2].tool_type.is_braille() && !tools[2].tool_type.is_text());
+        assert!(tools.iter().all(|tool| tool.index_in_machine == 3));
+        assert!(selection.choices.iter().all(|choice| !choice.reason.is_empty()));
+        assert_eq!(format!("{}", selection).lines().count(), 3);
+
+        let candidates = vec![&library[2], &library[0]];
+        assert_eq!(biggest_that_fits(&candidates, 0.1).unwrap().index_in_machine, 1);
+        assert!(biggest_that_fits(&candidates, 0.05).is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use super::*;
+
+// What the machine does, recorded by CNCRouter as it writes gcode or read
+// back from gcode. Used for stats, previews and simulation.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveKind {
+    Rapid,
+    Feed,
+    Arc(bool, cnc_router::Coordinate), // is clockwise, center
+    Dwell(f64),                        // seconds
+    ToolChange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub kind: MoveKind,
+    pub start: cnc_router::Coordinate,
+    pub end: cnc_router::Coordinate,
+    pub feed_rate: f64,
+    pub tool: usize, // index_in_machine
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Toolpath {
+    moves: Vec<Move>,
+}
+
+const EPSILON: f64 = 0.0000001;
+
+impl Move {
+    pub fn from(
+        kind: MoveKind,
+        start: cnc_router::Coordinate,
+        end: cnc_router::Coordinate,
+        feed_rate: f64,
+        tool: usize,
+    ) -> Self {
+        Self {
+            kind: kind,
+            start: start,
+            end: end,
+            feed_rate: feed_rate,
+            tool: tool,
+        }
+    }
+
+    pub fn is_cutting(&self) -> bool {
+        match self.kind {
+            MoveKind::Feed | MoveKind::Arc(_, _) => true,
+            _ => false,
+        }
+    }
+
+    fn moves_only_z(&self) -> bool {
+        (self.end.x - self.start.x).abs() < EPSILON && (self.end.y - self.start.y).abs() < EPSILON
+    }
+
+    // Feed straight down into the material.
+    pub fn is_plunge(&self) -> bool {
+        MoveKind::Feed == self.kind && self.moves_only_z() && self.end.z < self.start.z - EPSILON
+    }
+
+    // Straight up out of the material.
+    pub fn is_lift(&self) -> bool {
+        (MoveKind::Feed == self.kind || MoveKind::Rapid == self.kind)
+            && self.moves_only_z()
+            && self.end.z > self.start.z + EPSILON
+    }
+
+    // Angle swept around the center going from start to end. Arcs that end
+    // where they start are full circles.
+    pub fn arc_angle(&self) -> f64 {
+        let MoveKind::Arc(is_clock_wise, center) = self.kind else {
+            return 0.0;
+        };
+        let start = (self.start.y - center.y).atan2(self.start.x - center.x);
+        let end = (self.end.y - center.y).atan2(self.end.x - center.x);
+        let mut angle = if is_clock_wise { start - end } else { end - start };
+        if angle <= EPSILON {
+            angle += 2.0 * std::f64::consts::PI;
+        }
+        angle
+    }
+
+    pub fn arc_radius(&self) -> f64 {
+        let MoveKind::Arc(_, center) = self.kind else {
+            return 0.0;
+        };
+        ((self.start.x - center.x).powi(2) + (self.start.y - center.y).powi(2)).sqrt()
+    }
+
+    pub fn length(&self) -> f64 {
+        match self.kind {
+            MoveKind::Rapid | MoveKind::Feed => {
+                let d = self.end - self.start;
+                (d.x * d.x + d.y * d.y + d.z * d.z).sqrt()
+            }
+            MoveKind::Arc(_, _) => {
+                let around = self.arc_angle() * self.arc_radius();
+                let dz = self.end.z - self.start.z;
+                (around * around + dz * dz).sqrt()
+            }
+            MoveKind::Dwell(_) | MoveKind::ToolChange => 0.0,
+        }
+    }
+
+    // Points along the move at most max_step apart, not including start.
+    pub fn points(&self, max_step: f64) -> Vec<cnc_router::Coordinate> {
+        let steps = ((self.length() / max_step.max(EPSILON)).ceil() as usize).max(1);
+        match self.kind {
+            MoveKind::Rapid | MoveKind::Feed => (1..=steps)
+                .map(|i| {
+                    let t = i as f64 / steps as f64;
+                    self.start + t * (self.end - self.start)
+                })
+                .collect(),
+            MoveKind::Arc(is_clock_wise, center) => {
+                let radius = self.arc_radius();
+                let start = (self.start.y - center.y).atan2(self.start.x - center.x);
+                let angle = if is_clock_wise {
+                    -self.arc_angle()
+                } else {
+                    self.arc_angle()
+                };
+                (1..=steps)
+                    .map(|i| {
+                        let t = i as f64 / steps as f64;
+                        let a = start + angle * t;
+                        cnc_router::Coordinate::from(
+                            center.x + radius * a.cos(),
+                            center.y + radius * a.sin(),
+                            self.start.z + (self.end.z - self.start.z) * t,
+                        )
+                    })
+                    .collect()
+            }
+            MoveKind::Dwell(_) | MoveKind::ToolChange => Vec::new(),
+        }
+    }
+}
+
+impl Toolpath {
+    pub fn new() -> Self {
+        Self { moves: Vec::new() }
+    }
+
+    pub fn push(&mut self, m: Move) {
+        self.moves.push(m);
+    }
+
+    pub fn append(&mut self, other: &Toolpath) {
+        self.moves.extend(other.moves.iter());
+    }
+
+    pub fn moves(&self) -> &Vec<Move> {
+        &self.moves
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    // index_in_machine of every tool in the order they are first used.
+    pub fn tools(&self) -> Vec<usize> {
+        let mut tools = Vec::new();
+        for m in &self.moves {
+            if !tools.contains(&m.tool) {
+                tools.push(m.tool);
+            }
+        }
+        tools
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_move_length() {
+        let line = Move::from(
+            MoveKind::Feed,
+            cnc_router::Coordinate::from(0.0, 0.0, 0.0),
+            cnc_router::Coordinate::from(3.0, 4.0, 0.0),
+            10.0,
+            1,
+        );
+        assert_eq!(line.length(), 5.0);
+
+        let half_circle = Move::from(
+            MoveKind::Arc(true, cnc_router::Coordinate::from(0.0, 0.0, 0.0)),
+            cnc_router::Coordinate::from(1.0, 0.0, 0.0),
+            cnc_router::Coordinate::from(-1.0, 0.0, 0.0),
+            10.0,
+            1,
+        );
+        assert!((half_circle.length() - std::f64::consts::PI).abs() < 0.000001);
+        let last = *half_circle.points(0.1).last().unwrap();
+        assert!((last.x + 1.0).abs() < 0.000001 && last.y.abs() < 0.000001);
+        // Going clockwise from the right the arc passes under the center.
+        assert!(half_circle.points(0.1)[10].y < 0.0);
+
+        let full_circle = Move::from(
+            MoveKind::Arc(false, cnc_router::Coordinate::from(0.0, 0.0, 0.0)),
+            cnc_router::Coordinate::from(1.0, 0.0, 0.0),
+            cnc_router::Coordinate::from(1.0, 0.0, 0.0),
+            10.0,
+            1,
+        );
+        assert!((full_circle.length() - 2.0 * std::f64::consts::PI).abs() < 0.000001);
+    }
+}
@@ -0,0 +1,400 @@
+use super::*;
+
+// Checks signs for geometry that makes odd toolpaths or panics before any
+// gcode is made. Every issue says which sign and shape it is in and where.
+
+const EPSILON: f64 = 0.000001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Problem {
+    NotFinite,        // NaN or infinite coordinate
+    OpenPath,         // a line does not start where the one before it ends
+    SelfIntersection, // two lines of the same shape cross
+    ZeroArea,
+    DuplicatePoint,   // a line with no length
+    OutsideSign,      // not inside the sign's bounding rect
+    OutsideMachine,   // the sign and the tools around it do not fit in the machine
+    BadHeightmap,     // a relief heightmap without as many values as pixels
+    EmptyRelief,      // a relief over a rect with no width or height
+    BadStep,          // a step down or step over that is not more than 0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub problem: Problem,
+    pub sign_index: usize,
+    pub shape_index: Option<usize>, // None if it is the sign itself
+    pub location: Option<lines_and_curves::Point>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl Problem {
+    pub fn description(&self) -> String {
+        match self {
+            Problem::NotFinite => String::from("NaN or infinite coordinate"),
+            Problem::OpenPath => String::from("Open path"),
+            Problem::SelfIntersection => String::from("Self intersection"),
+            Problem::ZeroArea => String::from("Zero area"),
+            Problem::DuplicatePoint => String::from("Duplicate point"),
+            Problem::OutsideSign => String::from("Outside of the sign"),
+            Problem::OutsideMachine => String::from("Outside of the machine"),
+            Problem::BadHeightmap => String::from("Heightmap size does not match its values"),
+            Problem::EmptyRelief => String::from("Relief with no width or height"),
+            Problem::BadStep => String::from("Step that is not more than 0"),
+        }
+    }
+
+    // Errors stop generation, the rest only make worse toolpaths.
+    pub fn is_error(&self) -> bool {
+        match self {
+            Problem::ZeroArea | Problem::DuplicatePoint => false,
+            _ => true,
+        }
+    }
+}
+
+impl Issue {
+    pub fn from(
+        problem: Problem,
+        sign_index: usize,
+        shape_index: Option<usize>,
+        location: Option<lines_and_curves::Point>,
+    ) -> Self {
+        Self {
+            problem: problem,
+            sign_index: sign_index,
+            shape_index: shape_index,
+            location: location,
+        }
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} in sign {}",
+            if self.problem.is_error() { "Error" } else { "Warning" },
+            self.problem.description(),
+            self.sign_index,
+        )?;
+        if let Some(shape_index) = self.shape_index {
+            write!(f, " shape {}", shape_index)?;
+        }
+        if let Some(location) = self.location {
+            write!(f, " at ({}, {})", location.x, location.y)?;
+        }
+        Ok(())
+    }
+}
+
+impl ValidationReport {
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|issue| issue.problem.is_error())
+    }
+
+    pub fn errors(&self) -> Vec<&Issue> {
+        self.issues.iter().filter(|issue| issue.problem.is_error()).collect()
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+fn is_finite(point: &lines_and_curves::Point) -> bool {
+    point.x.is_finite() && point.y.is_finite()
+}
+
+fn same_point(a: &lines_and_curves::Point, b: &lines_and_curves::Point) -> bool {
+    (a.x - b.x).abs() < EPSILON && (a.y - b.y).abs() < EPSILON
+}
+
+// Where each line starts and the points it goes through, all in x, y.
+fn line_points<J: cnc_router::CNCPath>(line: &J) -> Vec<lines_and_curves::Point> {
+    let mut points = Vec::new();
+    if let Some(start) = line.start_path() {
+        points.push(lines_and_curves::Point::from(start.x, start.y));
+    }
+    for p in line.to_path() {
+        points.push(lines_and_curves::Point::from(
+            p.x.unwrap_or(f64::NAN),
+            p.y.unwrap_or(f64::NAN),
+        ));
+    }
+    points
+}
+
+// Shape::from closes line segments and drops repeated points so lines can be
+// checked before making a shape out of them to catch those too.
+pub fn validate_lines<J: lines_and_curves::Intersection + cnc_router::CNCPath>(
+    sign_index: usize,
+    shape_index: usize,
+    shape_lines: &Vec<J>,
+    bounding_rect: &lines_and_curves::Rectangle,
+) -> Vec<Issue> {
+    let issue = |problem, location| Issue::from(problem, sign_index, Some(shape_index), location);
+    let mut issues = Vec::new();
+
+    let lines = shape_lines.iter().map(line_points).collect::<Vec<_>>();
+    let circles = shape_lines
+        .iter()
+        .filter_map(|line| line.as_circle())
+        .collect::<Vec<lines_and_curves::Circle>>();
+
+    // Nothing else can be trusted with NaN around.
+    for point in lines.iter().flatten() {
+        if !is_finite(point) {
+            issues.push(issue(Problem::NotFinite, None));
+            return issues;
+        }
+    }
+    for circle in &circles {
+        if !is_finite(&circle.center) || !circle.radius.is_finite() {
+            issues.push(issue(Problem::NotFinite, None));
+            return issues;
+        }
+    }
+
+    if let Some(shape_rect) = lines_and_curves::bounding_box(shape_lines) {
+        if !bounding_rect.contains_rect(&shape_rect) {
+            issues.push(issue(
+                Problem::OutsideSign,
+                Some(lines_and_curves::Point::from(shape_rect.mid_x(), shape_rect.mid_y())),
+            ));
+        }
+    }
+
+    // Lines that are whole shapes on their own, like circles and rectangles,
+    // do not connect to the next line.
+    let connected = shape_lines
+        .iter()
+        .map(|line| line.is_connected())
+        .collect::<Vec<bool>>();
+    let mut segments = Vec::new();
+    let mut outline = Vec::new();
+    for i in 0..lines.len() {
+        if !connected[i] {
+            continue;
+        }
+        let next = (i + 1) % lines.len();
+        if let (Some(end), Some(start)) = (lines[i].last(), lines[next].first()) {
+            if connected[next] && !same_point(end, start) {
+                issues.push(issue(Problem::OpenPath, Some(*end)));
+            }
+        }
+        for pair in lines[i].windows(2) {
+            if same_point(&pair[0], &pair[1]) {
+                issues.push(issue(Problem::DuplicatePoint, Some(pair[0])));
+            } else {
+                segments.push(lines_and_curves::LineSegment::from(pair[0], pair[1]));
+            }
+        }
+        if let Some(end) = lines[i].last() {
+            outline.push(*end);
+        }
+    }
+
+    // Touching the line before or after at the shared point is how a path
+    // connects so only other touches count.
+    let n = segments.len();
+    for (point, i, j) in lines_and_curves::LineSegment::all_intersections(&segments) {
+        let is_neighbor = j == i + 1 || (i == 0 && j + 1 == n) || (j == 0 && i + 1 == n);
+        let is_shared_end = is_neighbor
+            && (same_point(&point, &segments[i].point1()) || same_point(&point, &segments[i].point2()));
+        if !is_shared_end {
+            issues.push(issue(Problem::SelfIntersection, Some(point)));
+        }
+    }
+
+    let area = lines_and_curves::Point::area(&outline).abs()
+        + circles.iter().map(|c| c.radius * c.radius).sum::<f64>()
+        + shape_lines
+            .iter()
+            .zip(connected.iter())
+            .filter(|(line, is_connected)| !**is_connected && line.as_circle().is_none())
+            .map(|(line, _)| {
+                let rect = line.bounding_box();
+                rect.width() * rect.height()
+            })
+            .sum::<f64>();
+    if area < EPSILON {
+        issues.push(issue(Problem::ZeroArea, outline.first().copied()));
+    }
+
+    issues
+}
+
+pub fn validate_shape<J: lines_and_curves::Intersection + cnc_router::CNCPath>(
+    sign_index: usize,
+    shape_index: usize,
+    shape: &sign::Shape<J>,
+    bounding_rect: &lines_and_curves::Rectangle,
+) -> Vec<Issue> {
+    validate_lines(sign_index, shape_index, shape.lines(), bounding_rect)
+}
+
+pub fn validate_sign<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    sign_index: usize,
+    sign: &sign::Sign<J>,
+) -> Vec<Issue> {
+    let rect = sign.bounding_rect();
+    if !is_finite(&rect.p1()) || !is_finite(&rect.p2()) {
+        return vec![Issue::from(Problem::NotFinite, sign_index, None, None)];
+    }
+    let mut issues = Vec::new();
+    for (shape_index, shape) in sign.shapes().iter().enumerate() {
+        issues.extend(validate_shape(sign_index, shape_index, shape, rect));
+    }
+    issues
+}
+
+// Corners of each sign, grown by the biggest tool radius, that are outside of
+// the machine profile's envelope in x, y.
+pub fn validate_machine<J: lines_and_curves::Intersection + Clone>(
+    signs: &Vec<sign::Sign<J>>,
+    profile: &machine_profile::MachineProfile,
+    tool_radius: f64,
+) -> Vec<Issue> {
+    let Some((min, max)) = &profile.envelope else {
+        return Vec::new();
+    };
+    let mut issues = Vec::new();
+    for (sign_index, sign) in signs.iter().enumerate() {
+        let rect = sign.bounding_rect();
+        for (x, y) in [
+            (rect.min_x() - tool_radius, rect.min_y() - tool_radius),
+            (rect.max_x() + tool_radius, rect.min_y() - tool_radius),
+            (rect.max_x() + tool_radius, rect.max_y() + tool_radius),
+            (rect.min_x() - tool_radius, rect.max_y() + tool_radius),
+        ] {
+            if x < min.x || x > max.x || y < min.y || y > max.y {
+                issues.push(Issue::from(
+                    Problem::OutsideMachine,
+                    sign_index,
+                    None,
+                    Some(lines_and_curves::Point::from(x, y)),
+                ));
+            }
+        }
+    }
+    issues
+}
+
+pub fn validate<J: lines_and_curves::Intersection + Clone + cnc_router::CNCPath>(
+    signs: &Vec<sign::Sign<J>>,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    for (sign_index, sign) in signs.iter().enumerate() {
+        report.issues.extend(validate_sign(sign_index, sign));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn shape(points: Vec<(f64, f64)>) -> sign::Shape<lines_and_curves::LineSegment> {
+        sign::Shape::from(
+            cnc_router::ShapeType::text(),
+            lines_and_curves::LineSegment::from_points(
+                &points
+                    .iter()
+                    .map(|(x, y)| lines_and_curves::Point::from(*x, *y))
+                    .collect(),
+            ),
+        )
+    }
+
+    fn problems(shape: sign::Shape<lines_and_curves::LineSegment>) -> Vec<Problem> {
+        let sign = sign::Sign::from(
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(10.0, 10.0),
+            ),
+            vec![shape],
+        );
+        validate(&vec![sign]).issues.iter().map(|issue| issue.problem).collect()
+    }
+
+    #[test]
+    pub fn test_validate() {
+        assert_eq!(problems(shape(vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0)])), vec![]);
+
+        // Bow tie.
+        let bow_tie = problems(shape(vec![(1.0, 1.0), (2.0, 2.0), (2.0, 1.0), (1.0, 2.0)]));
+        assert!(bow_tie.contains(&Problem::SelfIntersection));
+
+        assert_eq!(
+            problems(shape(vec![(1.0, 1.0), (2.0, 1.0), (3.0, 1.0)])),
+            vec![Problem::ZeroArea],
+        );
+        let line = |x1, y1, x2, y2| {
+            lines_and_curves::LineSegment::from(
+                lines_and_curves::Point::from(x1, y1),
+                lines_and_curves::Point::from(x2, y2),
+            )
+        };
+        let rect = lines_and_curves::Rectangle::from(
+            lines_and_curves::Point::from(0.0, 0.0),
+            lines_and_curves::Point::from(10.0, 10.0),
+        );
+        let problems_in = |lines: Vec<lines_and_curves::LineSegment>| {
+            validate_lines(0, 0, &lines, &rect)
+                .iter()
+                .map(|issue| issue.problem)
+                .collect::<Vec<Problem>>()
+        };
+        let duplicate = vec![
+            line(1.0, 1.0, 2.0, 1.0),
+            line(2.0, 1.0, 2.0, 1.0),
+            line(2.0, 1.0, 2.0, 2.0),
+            line(2.0, 2.0, 1.0, 1.0),
+        ];
+        assert_eq!(problems_in(duplicate), vec![Problem::DuplicatePoint]);
+        assert_eq!(
+            problems(shape(vec![(9.0, 9.0), (11.0, 9.0), (11.0, 11.0)])),
+            vec![Problem::OutsideSign],
+        );
+
+        let open = vec![line(1.0, 1.0, 2.0, 1.0), line(2.0, 1.0, 2.0, 2.0), line(2.0, 2.5, 1.0, 1.0)];
+        let issues = validate_lines(0, 3, &open, &rect);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].problem, Problem::OpenPath);
+        assert_eq!(issues[0].shape_index, Some(3));
+        assert_eq!(issues[0].location, Some(lines_and_curves::Point::from(2.0, 2.0)));
+    }
+
+    #[test]
+    pub fn test_validate_not_finite() {
+        let report = validate(&vec![sign::Sign::from(
+            lines_and_curves::Rectangle::from(
+                lines_and_curves::Point::from(0.0, 0.0),
+                lines_and_curves::Point::from(10.0, 10.0),
+            ),
+            vec![
+                shape(vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0)]),
+                shape(vec![(1.0, 1.0), (f64::NAN, 1.0), (2.0, 2.0)]),
+            ],
+        )]);
+        assert!(report.has_errors());
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].shape_index, Some(1));
+        assert_eq!(report.to_string(), "Error: NaN or infinite coordinate in sign 0 shape 1");
+    }
+}